@@ -0,0 +1,112 @@
+#!/bin/sh
+
+# measures the latency of page loads for an account with a style applied, with and without
+# the account style cache. requires curl, and a build of flock (preferably a release one)
+#
+# usage: contrib/bench/style-lookup.sh [path to flock] [number of requests] [port]
+
+set -e
+
+flock="${1:-target/release/flock}"
+requests="${2:-2000}"
+port="${3:-18080}"
+
+if [ ! -x "$flock" ]; then
+	echo "unable to find flock at $flock. build it or pass its path as the first argument" >&2
+	exit 1
+fi
+
+directory="`mktemp -d`"
+server=""
+
+cleanup() {
+	if [ ! -z "$server" ]; then
+		kill "$server" 2> /dev/null || true
+	fi
+	rm -rf "$directory"
+}
+trap cleanup EXIT INT TERM
+
+export FLOCK__SQLITE__PATH="$directory/flock.db"
+export FLOCK__HTTP__ADDRESS="127.0.0.1:$port"
+export FLOCK__GENERAL__LOG_FILTER=warn
+
+url="http://127.0.0.1:$port"
+
+start() {
+	"$flock" serve > "$directory/server.log" 2>&1 &
+	server=$!
+
+	# wait for the server to start accepting connections
+	for _ in `seq 50`; do
+		if curl -s -o /dev/null "$url/login"; then
+			return
+		fi
+		sleep 0.1
+	done
+
+	echo "the server didn't start, see its log below:" >&2
+	cat "$directory/server.log" >&2
+	exit 1
+}
+
+stop() {
+	kill "$server"
+	wait "$server" 2> /dev/null || true
+	server=""
+}
+
+# time n requests over a single connection, printing their mean and percentiles in
+# milliseconds
+measure() {
+	n="$1"
+	set --
+	for _ in `seq "$n"`; do
+		set -- "$@" -o /dev/null "$url/post"
+	done
+
+	curl -s -b "flock.id=$account_id" -w "%{time_total}\n" "$@" \
+		| sort -n \
+		| awk '
+			{ times[NR] = $1 * 1000; sum += $1 * 1000 }
+			END {
+				printf "mean %.3fms, p50 %.3fms, p90 %.3fms, p99 %.3fms\n",
+					sum / NR, times[int(NR * 0.5)], times[int(NR * 0.9)], times[int(NR * 0.99)]
+			}
+		'
+}
+
+"$flock" migrate > /dev/null
+
+start
+
+account_id="`curl -s -o /dev/null -D - -d "tags=benchmark" "$url/signup" \
+	| sed -n 's/^[Ss]et-[Cc]ookie: flock\.id=\([^;]*\);.*/\1/p'`"
+
+printf 'body { color: black; }' > "$directory/style.css"
+curl -s -o /dev/null -b "flock.id=$account_id" \
+	-F "stylesheet=@$directory/style.css;type=text/css" "$url/post-style"
+
+style_id="`curl -s -b "flock.id=$account_id" "$url/styles?sort=new" \
+	| sed -n 's/.*class="style-id id">\([^<]*\)<.*/\1/p' | head -n 1`"
+
+curl -s -o /dev/null -b "flock.id=$account_id" -X POST "$url/styles/$style_id/use"
+
+# warm up the connection pool and the caches
+measure 100 > /dev/null
+
+echo "with the account style cache ($requests requests):"
+measure "$requests"
+
+stop
+
+export FLOCK__ROUTES__ACCOUNT_STYLE_CACHE_CAPACITY=0
+
+start
+
+measure 100 > /dev/null
+
+echo "without the account style cache ($requests requests):"
+measure "$requests"
+
+stop
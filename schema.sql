@@ -1,9 +1,110 @@
 CREATE TABLE IF NOT EXISTS accounts (
     account_id TEXT NOT NULL PRIMARY KEY,
-    feed BLOB NOT NULL,
     style_id TEXT
 );
 
+CREATE INDEX IF NOT EXISTS accounts_style_id ON accounts (style_id);
+
+-- the number of feed refreshes each link has been delivered in for an account
+CREATE TABLE IF NOT EXISTS deliveries (
+    account_id TEXT NOT NULL,
+    link_id TEXT NOT NULL,
+    refreshes INTEGER NOT NULL,
+    PRIMARY KEY (account_id, link_id)
+);
+
+CREATE TABLE IF NOT EXISTS feed_tokens (
+    token TEXT NOT NULL PRIMARY KEY,
+    account_id TEXT NOT NULL,
+    -- seconds since the unix epoch
+    created INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS feed_tokens_account_id ON feed_tokens (account_id);
+
+CREATE TABLE IF NOT EXISTS feed_refreshes (
+    account_id TEXT NOT NULL,
+    -- seconds since the unix epoch
+    refreshed INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS feed_refreshes_account_id ON feed_refreshes (account_id, refreshed);
+
+-- every feed generated for an account, the latest of which is its current feed. feeds were
+-- stored as messagepack in `accounts` before version 7, and are converted by `flock migrate`.
+-- older feeds are kept for `algorithm.feed_retention`
+CREATE TABLE IF NOT EXISTS feeds (
+    account_id TEXT NOT NULL,
+    -- seconds since the unix epoch
+    generated_at INTEGER NOT NULL,
+    PRIMARY KEY (account_id, generated_at)
+);
+
+-- the links picked for each feed
+CREATE TABLE IF NOT EXISTS feed_items (
+    account_id TEXT NOT NULL,
+    generated_at INTEGER NOT NULL,
+    -- the order the links appear in within the feed
+    position INTEGER NOT NULL,
+    link_id TEXT NOT NULL,
+    -- the link's overall score at the time it was picked
+    rating REAL NOT NULL,
+    deviation REAL NOT NULL,
+    volatility REAL NOT NULL,
+    -- the segment of the sorted candidates the link was picked from, where 0 is the top. null
+    -- for links picked before explanations were recorded
+    segment INTEGER,
+    PRIMARY KEY (account_id, generated_at, position)
+);
+
+CREATE INDEX IF NOT EXISTS feed_items_link_id ON feed_items (link_id);
+
+-- the tags shared by the account and each link in its feeds, explaining why they were picked
+CREATE TABLE IF NOT EXISTS feed_item_tags (
+    account_id TEXT NOT NULL,
+    generated_at INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    tag_id TEXT NOT NULL,
+    -- the tag's share of the sum of the account's tag scores
+    importance_rating REAL NOT NULL,
+    importance_deviation REAL NOT NULL,
+    importance_volatility REAL NOT NULL,
+    -- the link's score for the tag
+    score_rating REAL NOT NULL,
+    score_deviation REAL NOT NULL,
+    score_volatility REAL NOT NULL,
+    PRIMARY KEY (account_id, generated_at, position, tag_id)
+);
+
+CREATE INDEX IF NOT EXISTS feed_item_tags_tag_id ON feed_item_tags (tag_id);
+
+CREATE TRIGGER IF NOT EXISTS feeds_delete_items AFTER DELETE ON feeds
+BEGIN
+    DELETE FROM feed_items
+     WHERE account_id = OLD.account_id AND generated_at = OLD.generated_at;
+END;
+
+CREATE TRIGGER IF NOT EXISTS feed_items_delete_tags AFTER DELETE ON feed_items
+BEGIN
+    DELETE FROM feed_item_tags
+     WHERE account_id = OLD.account_id
+       AND generated_at = OLD.generated_at
+       AND position = OLD.position;
+END;
+
+-- invite codes, which are needed to sign up while signups are invite-only
+CREATE TABLE IF NOT EXISTS invites (
+    code TEXT NOT NULL PRIMARY KEY,
+    -- empty for invites created from the command line
+    inviter TEXT NOT NULL,
+    -- seconds since the unix epoch
+    created INTEGER NOT NULL,
+    -- the account which signed up with the invite, once it has been used
+    invitee TEXT
+);
+
+CREATE INDEX IF NOT EXISTS invites_inviter ON invites (inviter);
+
 CREATE TABLE IF NOT EXISTS links (
     --TODO: allow for links to be purely textual
     link_id TEXT NOT NULL PRIMARY KEY,
@@ -11,13 +112,96 @@ CREATE TABLE IF NOT EXISTS links (
     description TEXT NOT NULL
 );
 
+CREATE INDEX IF NOT EXISTS links_link ON links (link);
+
+-- links hidden by a moderator, which are left out of feeds, tag pages, search and the front
+-- page
+CREATE TABLE IF NOT EXISTS hidden_links (
+    link_id TEXT NOT NULL PRIMARY KEY
+);
+
+-- the actions taken by moderators, kept even if the moderator's account is deleted
+CREATE TABLE IF NOT EXISTS moderation_log (
+    entry_id TEXT NOT NULL PRIMARY KEY,
+    moderator TEXT NOT NULL,
+    action TEXT NOT NULL,
+    target_id TEXT NOT NULL,
+    details TEXT NOT NULL
+);
+
+-- the accounts which posted each link, for links posted by a logged-in account
+CREATE TABLE IF NOT EXISTS posts (
+    link_id TEXT NOT NULL PRIMARY KEY,
+    account_id TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS posts_account_id ON posts (account_id);
+
+CREATE TABLE IF NOT EXISTS reports (
+    report_id TEXT NOT NULL PRIMARY KEY,
+    -- either 'link' or 'style'
+    kind TEXT NOT NULL,
+    target_id TEXT NOT NULL,
+    reporter TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    resolved BOOLEAN NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS reports_target ON reports (kind, target_id, resolved);
+
+-- the accounts with elevated privileges. an account without a row here has none
+CREATE TABLE IF NOT EXISTS roles (
+    account_id TEXT NOT NULL PRIMARY KEY,
+    -- either 'admin' or 'moderator'
+    role TEXT NOT NULL
+);
+
+-- the glicko-2 score of an account or link for each of its tags. scores were stored as
+-- messagepack before version 6, and are converted by `flock migrate`
 CREATE TABLE IF NOT EXISTS scores (
     id TEXT NOT NULL,
     tag_id TEXT NOT NULL,
-    score BLOB NOT NULL,
+    -- on the glicko-2 scale, rather than the one shown on the site
+    rating REAL NOT NULL,
+    deviation REAL NOT NULL,
+    volatility REAL NOT NULL,
+    -- seconds since the unix epoch
+    last_period INTEGER NOT NULL,
     PRIMARY KEY (id, tag_id)
 );
 
+CREATE INDEX IF NOT EXISTS scores_tag_id_rating ON scores (tag_id, rating);
+
+-- the results of ratings which haven't been incorporated into a score yet
+CREATE TABLE IF NOT EXISTS score_results (
+    id TEXT NOT NULL,
+    tag_id TEXT NOT NULL,
+    -- the order the results were queued in
+    position INTEGER NOT NULL,
+    opponent_rating REAL NOT NULL,
+    opponent_deviation REAL NOT NULL,
+    opponent_volatility REAL NOT NULL,
+    -- 1 for a win, 0.5 for a draw and 0 for a loss
+    score REAL NOT NULL,
+    PRIMARY KEY (id, tag_id, position)
+);
+
+CREATE TRIGGER IF NOT EXISTS scores_delete_results AFTER DELETE ON scores
+BEGIN
+    DELETE FROM score_results WHERE id = OLD.id AND tag_id = OLD.tag_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS scores_move_results AFTER UPDATE OF id, tag_id ON scores
+BEGIN
+    UPDATE score_results SET id = NEW.id, tag_id = NEW.tag_id
+     WHERE id = OLD.id AND tag_id = OLD.tag_id;
+END;
+
+CREATE TABLE IF NOT EXISTS secrets (
+    name TEXT NOT NULL PRIMARY KEY,
+    secret BLOB NOT NULL
+);
+
 CREATE TABLE IF NOT EXISTS seen (
     account_id TEXT NOT NULL,
     link_id TEXT NOT NULL,
@@ -25,6 +209,13 @@ CREATE TABLE IF NOT EXISTS seen (
     PRIMARY KEY (account_id, link_id)
 );
 
+-- proof-of-work challenges which have already been used to sign up, kept until they expire
+CREATE TABLE IF NOT EXISTS spent_challenges (
+    challenge TEXT NOT NULL PRIMARY KEY,
+    -- seconds since the unix epoch
+    expires INTEGER NOT NULL
+);
+
 CREATE TABLE IF NOT EXISTS styles (
     style_id TEXT NOT NULL PRIMARY KEY,
     name TEXT NOT NULL,
@@ -32,7 +223,55 @@ CREATE TABLE IF NOT EXISTS styles (
     style TEXT NOT NULL
 );
 
+-- the current version of each style. styles are served at a url containing their version so
+-- that they can be cached indefinitely, with every change to a stylesheet creating a new one
+CREATE TABLE IF NOT EXISTS style_versions (
+    style_id TEXT NOT NULL PRIMARY KEY,
+    version TEXT NOT NULL
+);
+
+-- styles uploaded before they were versioned start at a version matching their id
+INSERT INTO style_versions (style_id, version)
+     SELECT style_id, style_id
+       FROM styles
+      WHERE style_id NOT IN (SELECT style_id FROM style_versions);
+
+CREATE TABLE IF NOT EXISTS suspensions (
+    account_id TEXT NOT NULL PRIMARY KEY,
+    moderator TEXT NOT NULL,
+    -- seconds since the unix epoch
+    suspended INTEGER NOT NULL
+);
+
 CREATE TABLE IF NOT EXISTS tags (
     tag_id TEXT NOT NULL PRIMARY KEY,
     name TEXT NOT NULL UNIQUE
 );
+
+CREATE VIRTUAL TABLE IF NOT EXISTS links_search USING fts5 (
+    link_id UNINDEXED,
+    link,
+    description,
+    tags
+);
+
+-- index any links that were posted before the search index existed
+INSERT INTO links_search (link_id, link, description, tags)
+     SELECT links.link_id,
+            links.link,
+            links.description,
+            COALESCE(
+                (
+                    SELECT group_concat(tags.name, ' ')
+                      FROM scores
+                INNER JOIN tags ON scores.tag_id = tags.tag_id
+                     WHERE scores.id = links.link_id
+                ),
+                ''
+            )
+       FROM links
+      WHERE links.link_id NOT IN (SELECT link_id FROM links_search)
+        AND links.link_id NOT IN (SELECT link_id FROM hidden_links);
+
+-- bump this (and `schema::SCHEMA_VERSION`) whenever the schema changes
+PRAGMA user_version = 7;
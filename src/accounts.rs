@@ -0,0 +1,479 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use sqlx::{pool::PoolConnection, Connection, Sqlite};
+use std::time::SystemTime;
+use tracing::debug;
+
+use crate::{
+    moderation, scores,
+    util::{ScaledRatingData, ScaledRatingWrapper},
+};
+
+/// Gather everything stored about an account into a json document
+///
+/// Tag scores are included both in their raw Glicko-2 form (including any results that haven't
+/// been incorporated yet) and on the scale shown elsewhere on the site
+pub async fn export(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+) -> Result<Value, (StatusCode, &'static str)> {
+    let style_id = sqlx::query_scalar!(
+        r#"SELECT style_id as "style_id?" FROM accounts WHERE account_id = ?"#,
+        account_id
+    )
+    .fetch_optional(&mut **connection)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to query the db"))?
+    .ok_or((
+        StatusCode::BAD_REQUEST,
+        "the requested account does not exist",
+    ))?;
+
+    let tags = scores::load(connection, account_id)
+        .await?
+        .into_iter()
+        .map(|tag| {
+            json!({
+                "name": tag.name,
+                "rating": <ScaledRatingWrapper as Into<ScaledRatingData>>::into(ScaledRatingWrapper(tag.score.score)),
+                "glicko2": tag.score,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let seen = sqlx::query!(
+        r#"SELECT seen.link_id as "link_id!", links.link as "link?", seen.rated as "rated!: bool" FROM seen LEFT JOIN links ON seen.link_id = links.link_id WHERE seen.account_id = ? ORDER BY seen.link_id"#,
+        account_id
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's seen links from the db",
+        )
+    })?
+    .into_iter()
+    .map(|seen| {
+        json!({
+            "link_id": seen.link_id,
+            "link": seen.link,
+            "rated": seen.rated,
+        })
+    })
+    .collect::<Vec<_>>();
+
+    let feed_items = sqlx::query!(
+        r#"SELECT feed_items.generated_at as "generated_at!: i64", feed_items.position as "position!: i64", feed_items.link_id as "link_id!", links.link as "link?" FROM feed_items LEFT JOIN links ON feed_items.link_id = links.link_id WHERE feed_items.account_id = ? ORDER BY feed_items.generated_at, feed_items.position"#,
+        account_id
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's feeds from the db",
+        )
+    })?
+    .into_iter()
+    .map(|feed_item| {
+        json!({
+            "generated_at": feed_item.generated_at,
+            "position": feed_item.position,
+            "link_id": feed_item.link_id,
+            "link": feed_item.link,
+        })
+    })
+    .collect::<Vec<_>>();
+
+    let posts = sqlx::query!(
+        r#"SELECT links.link_id as "link_id!", links.link as "link!", links.description as "description!" FROM posts INNER JOIN links ON posts.link_id = links.link_id WHERE posts.account_id = ? ORDER BY links.link_id"#,
+        account_id
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's posted links from the db",
+        )
+    })?
+    .into_iter()
+    .map(|post| {
+        json!({
+            "link_id": post.link_id,
+            "link": post.link,
+            "description": post.description,
+        })
+    })
+    .collect::<Vec<_>>();
+
+    let styles = sqlx::query!(
+        r#"SELECT style_id as "style_id!", name as "name!", style as "style!" FROM styles WHERE creator = ? ORDER BY style_id"#,
+        account_id
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's styles from the db",
+        )
+    })?
+    .into_iter()
+    .map(|style| {
+        json!({
+            "style_id": style.style_id,
+            "name": style.name,
+            "style": style.style,
+        })
+    })
+    .collect::<Vec<_>>();
+
+    let feed_tokens = sqlx::query!(
+        r#"SELECT token as "token!", created as "created!: i64" FROM feed_tokens WHERE account_id = ? ORDER BY created"#,
+        account_id
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's feed tokens",
+        )
+    })?
+    .into_iter()
+    .map(|feed_token| {
+        json!({
+            "token": feed_token.token,
+            "created": feed_token.created,
+        })
+    })
+    .collect::<Vec<_>>();
+
+    let invites = sqlx::query!(
+        r#"SELECT code as "code!", created as "created!: i64", invitee FROM invites WHERE inviter = ? ORDER BY created"#,
+        account_id
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's invites",
+        )
+    })?
+    .into_iter()
+    .map(|invite| {
+        json!({
+            "code": invite.code,
+            "created": invite.created,
+            "invitee": invite.invitee,
+        })
+    })
+    .collect::<Vec<_>>();
+
+    let invited_by = sqlx::query_scalar!(
+        r#"SELECT inviter as "inviter!" FROM invites WHERE invitee = ?"#,
+        account_id
+    )
+    .fetch_optional(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's invite",
+        )
+    })?;
+
+    let reports = sqlx::query!(
+        r#"SELECT kind as "kind!", target_id as "target_id!", reason as "reason!", resolved as "resolved!: bool" FROM reports WHERE reporter = ? ORDER BY report_id"#,
+        account_id
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's reports",
+        )
+    })?
+    .into_iter()
+    .map(|report| {
+        json!({
+            "kind": report.kind,
+            "target_id": report.target_id,
+            "reason": report.reason,
+            "resolved": report.resolved,
+        })
+    })
+    .collect::<Vec<_>>();
+
+    Ok(json!({
+        "account_id": account_id,
+        "exported": SystemTime::UNIX_EPOCH
+            .elapsed()
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to calculate the amount of time that has passed since the unix epoch",
+                )
+            })?
+            .as_secs(),
+        "style_id": style_id,
+        "tags": tags,
+        "seen": seen,
+        "feed_items": feed_items,
+        "posted_links": posts,
+        "styles": styles,
+        "feed_tokens": feed_tokens,
+        "role": moderation::role(connection, account_id)
+            .await?
+            .map(|role| role.as_str()),
+        "suspended": moderation::is_suspended(connection, account_id).await?,
+        "reports": reports,
+        "invites": invites,
+        "invited_by": invited_by,
+    }))
+}
+
+/// Remove every row keyed by an account's id
+///
+/// Links and styles the account created are shared with everyone else, so rather than being
+/// removed they are disassociated from the account, as are its reports and the invites it
+/// used or handed out. Unused invites are deleted. The moderation log is an audit trail, so
+/// it is left untouched
+pub async fn delete(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+) -> Result<(), (StatusCode, &'static str)> {
+    let mut transaction = connection.begin().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to begin a transaction",
+        )
+    })?;
+
+    for (query, error) in [
+        (
+            sqlx::query!("DELETE FROM scores WHERE id = ?", account_id),
+            "unable to delete the account's tag scores",
+        ),
+        (
+            sqlx::query!("DELETE FROM seen WHERE account_id = ?", account_id),
+            "unable to delete the account's seen links",
+        ),
+        (
+            sqlx::query!("DELETE FROM deliveries WHERE account_id = ?", account_id),
+            "unable to delete the account's feed deliveries",
+        ),
+        (
+            sqlx::query!(
+                "DELETE FROM feed_refreshes WHERE account_id = ?",
+                account_id
+            ),
+            "unable to delete the account's feed refreshes",
+        ),
+        (
+            sqlx::query!("DELETE FROM feeds WHERE account_id = ?", account_id),
+            "unable to delete the account's feeds",
+        ),
+        (
+            sqlx::query!("DELETE FROM feed_tokens WHERE account_id = ?", account_id),
+            "unable to delete the account's feed tokens",
+        ),
+        (
+            sqlx::query!("DELETE FROM roles WHERE account_id = ?", account_id),
+            "unable to delete the account's role",
+        ),
+        (
+            sqlx::query!("DELETE FROM suspensions WHERE account_id = ?", account_id),
+            "unable to delete the account's suspension",
+        ),
+        (
+            sqlx::query!(
+                "UPDATE reports SET reporter = '' WHERE reporter = ?",
+                account_id
+            ),
+            "unable to disassociate the account from its reports",
+        ),
+        (
+            sqlx::query!(
+                "DELETE FROM invites WHERE inviter = ? AND invitee IS NULL",
+                account_id
+            ),
+            "unable to delete the account's unused invites",
+        ),
+        (
+            sqlx::query!(
+                "UPDATE invites SET inviter = '' WHERE inviter = ?",
+                account_id
+            ),
+            "unable to disassociate the account from its used invites",
+        ),
+        (
+            sqlx::query!(
+                "UPDATE invites SET invitee = '' WHERE invitee = ?",
+                account_id
+            ),
+            "unable to disassociate the account from its invite",
+        ),
+        (
+            sqlx::query!("DELETE FROM posts WHERE account_id = ?", account_id),
+            "unable to disassociate the account from its posted links",
+        ),
+        (
+            sqlx::query!(
+                "UPDATE styles SET creator = '' WHERE creator = ?",
+                account_id
+            ),
+            "unable to disassociate the account from its styles",
+        ),
+        (
+            sqlx::query!("DELETE FROM accounts WHERE account_id = ?", account_id),
+            "unable to delete the account",
+        ),
+    ] {
+        query
+            .execute(&mut *transaction)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, error))?;
+    }
+
+    transaction.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to commit the transaction",
+        )
+    })?;
+
+    debug!("deleted account {}", account_id);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::SqliteConnection;
+
+    use super::{delete, export};
+    use crate::schema;
+
+    /// Store a bit of everything an account can have, alongside another account's data
+    async fn populate(connection: &mut SqliteConnection) {
+        sqlx::query(
+            "INSERT INTO accounts VALUES ('account', 'style'), ('other', NULL);
+             INSERT INTO tags VALUES ('cats', 'cats');
+             INSERT INTO scores VALUES ('account', 'cats', 1, 2, 0.06, 0),
+                                       ('other', 'cats', 1, 2, 0.06, 0);
+             INSERT INTO score_results VALUES ('account', 'cats', 0, 0, 2, 0.06, 1);
+             INSERT INTO links VALUES ('link', 'https://example.com', 'a link');
+             INSERT INTO posts VALUES ('link', 'account');
+             INSERT INTO styles VALUES ('style', 'a style', 'account', 'a {}');
+             INSERT INTO seen VALUES ('account', 'link', true);
+             INSERT INTO deliveries VALUES ('account', 'link', 1);
+             INSERT INTO feed_refreshes VALUES ('account', 1);
+             INSERT INTO feeds VALUES ('account', 1);
+             INSERT INTO feed_items VALUES ('account', 1, 0, 'link', 0, 0, 0, 0);
+             INSERT INTO feed_item_tags VALUES ('account', 1, 0, 'cats', 1, 1, 1, 1, 2, 0.06);
+             INSERT INTO feed_tokens VALUES ('token', 'account', 1);
+             INSERT INTO roles VALUES ('account', 'moderator');
+             INSERT INTO suspensions VALUES ('account', 'other', 1);
+             INSERT INTO reports VALUES ('report', 'link', 'link', 'account', 'spam', false);
+             INSERT INTO invites VALUES ('unused', 'account', 1, NULL),
+                                        ('used', 'account', 1, 'other'),
+                                        ('own', 'other', 1, 'account');",
+        )
+        .execute(&mut *connection)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn exports_include_everything_about_the_account() {
+        let mut connection = schema::memory().await.acquire().await.unwrap();
+        populate(&mut connection).await;
+
+        let exported = export(&mut connection, "account").await.unwrap();
+
+        assert_eq!(exported["account_id"], "account");
+        assert_eq!(exported["style_id"], "style");
+        assert_eq!(exported["tags"][0]["name"], "cats");
+        assert_eq!(exported["seen"][0]["link"], "https://example.com");
+        assert_eq!(exported["feed_items"][0]["link_id"], "link");
+        assert_eq!(exported["posted_links"][0]["link_id"], "link");
+        assert_eq!(exported["styles"][0]["style_id"], "style");
+        assert_eq!(exported["feed_tokens"][0]["token"], "token");
+        assert_eq!(exported["role"], "moderator");
+        assert_eq!(exported["suspended"], true);
+        assert_eq!(exported["reports"][0]["reason"], "spam");
+        assert_eq!(exported["invites"].as_array().unwrap().len(), 2);
+        assert_eq!(exported["invited_by"], "other");
+
+        assert!(export(&mut connection, "nobody").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn deletion_leaves_nothing_keyed_by_the_account() {
+        let mut connection = schema::memory().await.acquire().await.unwrap();
+        populate(&mut connection).await;
+
+        delete(&mut connection, "account").await.unwrap();
+
+        // every column which could hold an account id, in every table
+        let columns = sqlx::query_as::<_, (String, String)>(
+            "SELECT tables.name, columns.name
+               FROM sqlite_schema AS tables, pragma_table_info(tables.name) AS columns
+              WHERE tables.type = 'table'
+                AND tables.name NOT LIKE 'links_search%'
+                AND tables.name != 'moderation_log'",
+        )
+        .fetch_all(&mut *connection)
+        .await
+        .unwrap();
+        assert!(!columns.is_empty());
+
+        for (table, column) in columns {
+            let rows = sqlx::query_scalar::<_, i64>(&format!(
+                "SELECT COUNT(1) FROM \"{}\" WHERE \"{}\" = 'account'",
+                table, column
+            ))
+            .fetch_one(&mut *connection)
+            .await
+            .unwrap();
+
+            assert_eq!(rows, 0, "{}.{} still refers to the account", table, column);
+        }
+
+        // shared content stays, as does everything belonging to other accounts
+        let remaining = sqlx::query_scalar::<_, i64>(
+            "SELECT (SELECT COUNT(1) FROM links)
+                  + (SELECT COUNT(1) FROM styles)
+                  + (SELECT COUNT(1) FROM reports)
+                  + (SELECT COUNT(1) FROM invites)
+                  + (SELECT COUNT(1) FROM scores WHERE id = 'other')",
+        )
+        .fetch_one(&mut *connection)
+        .await
+        .unwrap();
+        assert_eq!(remaining, 6);
+    }
+}
@@ -0,0 +1,588 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use anyhow::{anyhow, bail, Context};
+use axum::http::StatusCode;
+use sqlx::SqlitePool;
+use tracing::{debug, info};
+
+use crate::{
+    accounts,
+    configuration::Algorithm as AlgorithmConfiguration,
+    moderation::{self, Role},
+    scores, search, signup,
+    util::{self, ScaledRatingData, ScaledRatingWrapper},
+};
+
+/// Convert an error meant for a route into one suitable for the command line
+fn route_error((_, message): (StatusCode, &'static str)) -> anyhow::Error {
+    anyhow!(message)
+}
+
+/// Print a summary of everything stored about an account
+pub async fn show_account(sqlite: &SqlitePool, account_id: &str) -> anyhow::Result<()> {
+    let mut connection = sqlite
+        .acquire()
+        .await
+        .context("unable to acquire a db connection")?;
+
+    let style_id = sqlx::query_scalar!(
+        r#"SELECT style_id as "style_id?" FROM accounts WHERE account_id = ?"#,
+        account_id
+    )
+    .fetch_optional(&mut *connection)
+    .await
+    .context("unable to query the db for the account")?
+    .context("the requested account does not exist")?;
+
+    let counts = sqlx::query!(
+        r#"SELECT
+               (SELECT COUNT(1) FROM seen WHERE account_id = ?1) as "seen!: i64",
+               (SELECT COUNT(1) FROM seen WHERE account_id = ?1 AND rated) as "rated!: i64",
+               (SELECT COUNT(1) FROM posts WHERE account_id = ?1) as "posts!: i64",
+               (SELECT COUNT(1) FROM styles WHERE creator = ?1) as "styles!: i64",
+               (SELECT COUNT(1) FROM feed_tokens WHERE account_id = ?1) as "feed_tokens!: i64""#,
+        account_id
+    )
+    .fetch_one(&mut *connection)
+    .await
+    .context("unable to count the account's rows")?;
+
+    let tags = scores::load(&mut connection, account_id)
+        .await
+        .map_err(route_error)?;
+
+    println!("account {}", account_id);
+    println!("  style: {}", style_id.as_deref().unwrap_or("none"));
+    println!("  seen links: {} ({} rated)", counts.seen, counts.rated);
+    println!("  posted links: {}", counts.posts);
+    println!("  styles: {}", counts.styles);
+    println!("  feed tokens: {}", counts.feed_tokens);
+    println!(
+        "  role: {}",
+        moderation::role(&mut connection, account_id)
+            .await
+            .map_err(route_error)?
+            .map_or("none", |role| role.as_str())
+    );
+    println!(
+        "  invited by: {}",
+        sqlx::query_scalar!(
+            r#"SELECT inviter as "inviter!" FROM invites WHERE invitee = ?"#,
+            account_id
+        )
+        .fetch_optional(&mut *connection)
+        .await
+        .context("unable to query the account's invite")?
+        .map_or_else(
+            || "nobody".to_string(),
+            |inviter| {
+                if inviter.is_empty() {
+                    "the command line or a deleted account".to_string()
+                } else {
+                    inviter
+                }
+            }
+        )
+    );
+    println!(
+        "  suspended: {}",
+        moderation::is_suspended(&mut connection, account_id)
+            .await
+            .map_err(route_error)?
+    );
+    println!("  tags:");
+
+    for tag in tags {
+        println!(
+            "    {}: {} ({} pending results)",
+            tag.name,
+            <ScaledRatingWrapper as Into<ScaledRatingData>>::into(ScaledRatingWrapper(
+                tag.score.score
+            ))
+            .to_string(),
+            tag.score.result_queue.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Delete an account and everything keyed by its id
+pub async fn delete_account(sqlite: &SqlitePool, account_id: &str) -> anyhow::Result<()> {
+    let mut connection = sqlite
+        .acquire()
+        .await
+        .context("unable to acquire a db connection")?;
+
+    if sqlx::query_scalar!("SELECT 1 FROM accounts WHERE account_id = ?", account_id)
+        .fetch_optional(&mut *connection)
+        .await
+        .context("unable to check if the account exists")?
+        .is_none()
+    {
+        bail!("the requested account does not exist");
+    }
+
+    accounts::delete(&mut connection, account_id)
+        .await
+        .map_err(route_error)?;
+
+    info!("deleted account {}", account_id);
+
+    Ok(())
+}
+
+/// Grant an account a role, or take its role away if the role is `none`
+pub async fn set_role(sqlite: &SqlitePool, account_id: &str, role: &str) -> anyhow::Result<()> {
+    let role = match role {
+        "none" => None,
+        role => Some(
+            Role::from_str(role)
+                .context("the role must be one of `admin`, `moderator`, or `none`")?,
+        ),
+    };
+
+    let mut connection = sqlite
+        .acquire()
+        .await
+        .context("unable to acquire a db connection")?;
+
+    if sqlx::query_scalar!("SELECT 1 FROM accounts WHERE account_id = ?", account_id)
+        .fetch_optional(&mut *connection)
+        .await
+        .context("unable to check if the account exists")?
+        .is_none()
+    {
+        bail!("the requested account does not exist");
+    }
+
+    moderation::set_role(&mut connection, account_id, role)
+        .await
+        .map_err(route_error)?;
+
+    info!(
+        "set the role of account {} to {}",
+        account_id,
+        role.map_or("none", |role| role.as_str())
+    );
+
+    Ok(())
+}
+
+/// Create an invite which isn't counted against any account's quota
+pub async fn create_invite(sqlite: &SqlitePool) -> anyhow::Result<()> {
+    let mut connection = sqlite
+        .acquire()
+        .await
+        .context("unable to acquire a db connection")?;
+
+    let code = signup::create_invite(&mut connection, None, 0)
+        .await
+        .map_err(route_error)?;
+
+    println!("{}", code);
+
+    Ok(())
+}
+
+/// Delete a link, removing it from any feeds it appears in
+pub async fn delete_link(sqlite: &SqlitePool, link_id: &str) -> anyhow::Result<()> {
+    let mut transaction = sqlite
+        .begin()
+        .await
+        .context("unable to begin a transaction")?;
+
+    if sqlx::query_scalar!("SELECT 1 FROM links WHERE link_id = ?", link_id)
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("unable to check if the link exists")?
+        .is_none()
+    {
+        bail!("the requested link does not exist");
+    }
+
+    for query in [
+        sqlx::query!("DELETE FROM scores WHERE id = ?", link_id),
+        sqlx::query!("DELETE FROM seen WHERE link_id = ?", link_id),
+        sqlx::query!("DELETE FROM deliveries WHERE link_id = ?", link_id),
+        sqlx::query!("DELETE FROM posts WHERE link_id = ?", link_id),
+        sqlx::query!("DELETE FROM hidden_links WHERE link_id = ?", link_id),
+        sqlx::query!(
+            "DELETE FROM reports WHERE kind = 'link' AND target_id = ?",
+            link_id
+        ),
+        sqlx::query!("DELETE FROM links_search WHERE link_id = ?", link_id),
+        sqlx::query!("DELETE FROM links WHERE link_id = ?", link_id),
+    ] {
+        query
+            .execute(&mut *transaction)
+            .await
+            .context("unable to delete the link")?;
+    }
+
+    let removed = sqlx::query!("DELETE FROM feed_items WHERE link_id = ?", link_id)
+        .execute(&mut *transaction)
+        .await
+        .context("unable to remove the link from feeds")?
+        .rows_affected();
+
+    transaction
+        .commit()
+        .await
+        .context("unable to commit the link's deletion")?;
+
+    info!(
+        "deleted link {}, removing it from {} feeds",
+        link_id, removed
+    );
+
+    Ok(())
+}
+
+/// Merge one tag into another, moving every score for the former onto the latter
+///
+/// Where something has a score for both tags, the score for the tag being merged into is kept
+pub async fn merge_tags(sqlite: &SqlitePool, from: &str, into: &str) -> anyhow::Result<()> {
+    let mut transaction = sqlite
+        .begin()
+        .await
+        .context("unable to begin a transaction")?;
+
+    let from_tag_id = sqlx::query_scalar!(
+        r#"SELECT tag_id as "tag_id!" FROM tags WHERE name = ?"#,
+        from
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("unable to query the db for the tag")?
+    .with_context(|| format!("the tag {} does not exist", from))?;
+
+    let into_tag_id = sqlx::query_scalar!(
+        r#"SELECT tag_id as "tag_id!" FROM tags WHERE name = ?"#,
+        into
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("unable to query the db for the tag")?
+    .with_context(|| format!("the tag {} does not exist", into))?;
+
+    if from_tag_id == into_tag_id {
+        bail!("a tag can't be merged into itself");
+    }
+
+    let link_ids = sqlx::query_scalar!(
+        r#"SELECT id as "id!" FROM scores WHERE tag_id = ? AND id IN (SELECT link_id FROM links)"#,
+        from_tag_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("unable to query the links carrying the tag")?;
+
+    // things with scores for both tags keep their score for the tag being merged into
+    for query in [
+        sqlx::query!(
+            "UPDATE OR IGNORE scores SET tag_id = ? WHERE tag_id = ?",
+            into_tag_id,
+            from_tag_id
+        ),
+        sqlx::query!("DELETE FROM scores WHERE tag_id = ?", from_tag_id),
+        sqlx::query!(
+            "UPDATE OR IGNORE feed_item_tags SET tag_id = ? WHERE tag_id = ?",
+            into_tag_id,
+            from_tag_id
+        ),
+        sqlx::query!("DELETE FROM feed_item_tags WHERE tag_id = ?", from_tag_id),
+        sqlx::query!("DELETE FROM tags WHERE tag_id = ?", from_tag_id),
+    ] {
+        query
+            .execute(&mut *transaction)
+            .await
+            .context("unable to merge the tags")?;
+    }
+
+    for link_id in &link_ids {
+        search::index_link(&mut transaction, link_id)
+            .await
+            .map_err(route_error)?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("unable to commit the merge")?;
+
+    info!(
+        "merged tag {} into {}, updating {} links",
+        from,
+        into,
+        link_ids.len()
+    );
+
+    Ok(())
+}
+
+/// Apply any pending decay and rating periods to every score
+///
+/// Scores are otherwise only brought up to date lazily, when they're next used
+pub async fn recompute_scores(
+    sqlite: &SqlitePool,
+    algorithm_configuration: &AlgorithmConfiguration,
+) -> anyhow::Result<()> {
+    let mut connection = sqlite
+        .acquire()
+        .await
+        .context("unable to acquire a db connection")?;
+
+    let rows = sqlx::query!(
+        r#"SELECT scores.id as "id!", scores.tag_id as "tag_id!", accounts.account_id IS NOT NULL as "is_account!: bool" FROM scores LEFT JOIN accounts ON scores.id = accounts.account_id"#
+    )
+    .fetch_all(&mut *connection)
+    .await
+    .context("unable to query the scores")?;
+
+    let total = rows.len();
+    let mut updated = 0;
+
+    for row in rows {
+        let Some(mut score) = scores::load_one(&mut connection, &row.id, &row.tag_id)
+            .await
+            .map_err(route_error)?
+        else {
+            continue;
+        };
+
+        // the same periods used when rating links
+        let period = if row.is_account { 1 } else { 12 };
+
+        if !util::decay_score(algorithm_configuration, &mut score, period).map_err(route_error)? {
+            continue;
+        }
+
+        debug!("recomputed the score for ({}, {})", row.id, row.tag_id);
+
+        scores::store(&mut connection, &row.id, &row.tag_id, &score)
+            .await
+            .map_err(route_error)?;
+
+        updated += 1;
+    }
+
+    info!("recomputed {} of {} scores", updated, total);
+
+    Ok(())
+}
+
+/// Print counts of the rows in each table, alongside the most used tags
+pub async fn stats(sqlite: &SqlitePool) -> anyhow::Result<()> {
+    let mut connection = sqlite
+        .acquire()
+        .await
+        .context("unable to acquire a db connection")?;
+
+    let counts = sqlx::query!(
+        r#"SELECT
+               (SELECT COUNT(1) FROM accounts) as "accounts!: i64",
+               (SELECT COUNT(1) FROM links) as "links!: i64",
+               (SELECT COUNT(1) FROM tags) as "tags!: i64",
+               (SELECT COUNT(1) FROM scores) as "scores!: i64",
+               (SELECT COUNT(1) FROM seen) as "seen!: i64",
+               (SELECT COUNT(1) FROM seen WHERE rated) as "rated!: i64",
+               (SELECT COUNT(1) FROM styles) as "styles!: i64",
+               (SELECT COUNT(1) FROM feed_tokens) as "feed_tokens!: i64""#
+    )
+    .fetch_one(&mut *connection)
+    .await
+    .context("unable to count the db's rows")?;
+
+    let top_tags = sqlx::query!(
+        r#"SELECT tags.name as "name!", COUNT(1) as "count!: i64" FROM scores INNER JOIN tags ON scores.tag_id = tags.tag_id GROUP BY tags.tag_id ORDER BY COUNT(1) DESC, tags.name LIMIT 10"#
+    )
+    .fetch_all(&mut *connection)
+    .await
+    .context("unable to query the most used tags")?
+    .into_iter()
+    .map(|tag| (tag.name, tag.count))
+    .collect::<Vec<_>>();
+
+    println!("accounts: {}", counts.accounts);
+    println!("links: {}", counts.links);
+    println!("tags: {}", counts.tags);
+    println!("scores: {}", counts.scores);
+    println!("seen links: {} ({} rated)", counts.seen, counts.rated);
+    println!("styles: {}", counts.styles);
+    println!("feed tokens: {}", counts.feed_tokens);
+    println!("most used tags:");
+
+    for (name, count) in top_tags {
+        println!("  {}: {}", name, count);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::SqlitePool;
+
+    use super::{delete_account, delete_link, merge_tags, set_role};
+    use crate::schema;
+
+    /// Run a query returning a single count
+    async fn count(sqlite: &SqlitePool, query: &str) -> i64 {
+        sqlx::query_scalar(query).fetch_one(sqlite).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn merged_tags_keep_the_score_of_the_tag_merged_into() {
+        let sqlite = schema::memory().await;
+        sqlx::query(
+            "INSERT INTO tags VALUES ('cats', 'cats'), ('kittens', 'kittens');
+             INSERT INTO links VALUES ('both', 'https://example.com/both', 'both tags'),
+                                      ('one', 'https://example.com/one', 'one tag');
+             INSERT INTO scores VALUES ('both', 'cats', 2, 2, 0.06, 0),
+                                       ('both', 'kittens', 1, 2, 0.06, 0),
+                                       ('one', 'kittens', 3, 2, 0.06, 0);
+             INSERT INTO feed_item_tags VALUES ('account', 1, 0, 'kittens', 1, 1, 1, 3, 2, 0.06);",
+        )
+        .execute(&sqlite)
+        .await
+        .unwrap();
+
+        assert!(merge_tags(&sqlite, "cats", "cats").await.is_err());
+        assert!(merge_tags(&sqlite, "dogs", "cats").await.is_err());
+
+        merge_tags(&sqlite, "kittens", "cats").await.unwrap();
+
+        let scores = sqlx::query_as::<_, (String, String, f64)>(
+            "SELECT id, tag_id, rating FROM scores ORDER BY id",
+        )
+        .fetch_all(&sqlite)
+        .await
+        .unwrap();
+        assert_eq!(
+            scores,
+            [
+                ("both".to_string(), "cats".to_string(), 2.0),
+                ("one".to_string(), "cats".to_string(), 3.0)
+            ]
+        );
+
+        assert_eq!(
+            count(
+                &sqlite,
+                "SELECT COUNT(1) FROM tags WHERE tag_id = 'kittens'"
+            )
+            .await,
+            0
+        );
+        assert_eq!(
+            count(
+                &sqlite,
+                "SELECT COUNT(1) FROM feed_item_tags WHERE tag_id = 'cats'"
+            )
+            .await,
+            1
+        );
+        assert_eq!(
+            count(
+                &sqlite,
+                "SELECT COUNT(1) FROM links_search WHERE links_search MATCH 'tags:cats'"
+            )
+            .await,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn deleted_links_are_removed_from_feeds() {
+        let sqlite = schema::memory().await;
+        sqlx::query(
+            "INSERT INTO tags VALUES ('cats', 'cats');
+             INSERT INTO links VALUES ('deleted', 'https://example.com/deleted', 'deleted'),
+                                      ('kept', 'https://example.com/kept', 'kept');
+             INSERT INTO scores VALUES ('deleted', 'cats', 1, 2, 0.06, 0),
+                                       ('kept', 'cats', 1, 2, 0.06, 0);
+             INSERT INTO seen VALUES ('account', 'deleted', true);
+             INSERT INTO deliveries VALUES ('account', 'deleted', 1);
+             INSERT INTO feeds VALUES ('account', 1);
+             INSERT INTO feed_items VALUES ('account', 1, 0, 'deleted', 0, 2, 0.06, 0),
+                                           ('account', 1, 1, 'kept', 0, 2, 0.06, 0);
+             INSERT INTO feed_item_tags VALUES ('account', 1, 0, 'cats', 1, 1, 1, 1, 2, 0.06);
+             INSERT INTO reports VALUES ('report', 'link', 'deleted', 'account', 'spam', false);",
+        )
+        .execute(&sqlite)
+        .await
+        .unwrap();
+
+        assert!(delete_link(&sqlite, "missing").await.is_err());
+
+        delete_link(&sqlite, "deleted").await.unwrap();
+
+        assert_eq!(
+            count(
+                &sqlite,
+                "SELECT (SELECT COUNT(1) FROM links WHERE link_id = 'deleted')
+                      + (SELECT COUNT(1) FROM scores WHERE id = 'deleted')
+                      + (SELECT COUNT(1) FROM seen)
+                      + (SELECT COUNT(1) FROM deliveries)
+                      + (SELECT COUNT(1) FROM feed_items WHERE link_id = 'deleted')
+                      + (SELECT COUNT(1) FROM feed_item_tags)
+                      + (SELECT COUNT(1) FROM reports)"
+            )
+            .await,
+            0
+        );
+        assert_eq!(
+            count(
+                &sqlite,
+                "SELECT COUNT(1) FROM feed_items WHERE link_id = 'kept'"
+            )
+            .await,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn account_commands_require_the_account_to_exist() {
+        let sqlite = schema::memory().await;
+        sqlx::query("INSERT INTO accounts VALUES ('account', NULL)")
+            .execute(&sqlite)
+            .await
+            .unwrap();
+
+        assert!(set_role(&sqlite, "missing", "admin").await.is_err());
+        assert!(set_role(&sqlite, "account", "owner").await.is_err());
+        assert!(delete_account(&sqlite, "missing").await.is_err());
+
+        set_role(&sqlite, "account", "moderator").await.unwrap();
+        assert_eq!(
+            sqlx::query_scalar::<_, String>("SELECT role FROM roles")
+                .fetch_all(&sqlite)
+                .await
+                .unwrap(),
+            ["moderator"]
+        );
+
+        set_role(&sqlite, "account", "none").await.unwrap();
+        assert_eq!(count(&sqlite, "SELECT COUNT(1) FROM roles").await, 0);
+
+        delete_account(&sqlite, "account").await.unwrap();
+        assert_eq!(count(&sqlite, "SELECT COUNT(1) FROM accounts").await, 0);
+    }
+}
@@ -0,0 +1,370 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use anyhow::{bail, Context};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePool},
+    ConnectOptions, Connection,
+};
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{debug, info, trace, warn};
+
+use crate::{
+    configuration::Sqlite as SqliteConfiguration,
+    schema::{self, SCHEMA_VERSION},
+};
+
+/// The prefix given to the names of periodic backups
+const BACKUP_PREFIX: &str = "flock-";
+
+/// The extension given to the names of periodic backups
+const BACKUP_EXTENSION: &str = ".db";
+
+/// Write a consistent copy of the database to the provided path, which must not already exist
+///
+/// This is safe to do while the server is running, as `VACUUM INTO` reads from a single
+/// transaction
+pub async fn backup(sqlite: &SqlitePool, path: &Path) -> anyhow::Result<()> {
+    if path.exists() {
+        bail!("{} already exists", path.display());
+    }
+
+    let path = path
+        .to_str()
+        .context("the backup path must be valid unicode")?;
+
+    debug!("backing up the db to {}", path);
+
+    sqlx::query("VACUUM INTO ?")
+        .bind(path)
+        .execute(sqlite)
+        .await
+        .context("unable to back up the db")?;
+
+    Ok(())
+}
+
+/// Periodically back up the database into the configured directory, removing the oldest
+/// backups beyond the configured retention
+pub async fn backup_periodically(
+    sqlite: SqlitePool,
+    directory: PathBuf,
+    sqlite_configuration: SqliteConfiguration,
+) {
+    let mut interval = time::interval(sqlite_configuration.backup_period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        trace!("performing a periodic backup");
+
+        if let Err(error) = periodic_backup(&sqlite, &directory, &sqlite_configuration).await {
+            warn!("unable to perform a periodic backup: {:?}", error);
+        }
+    }
+}
+
+async fn periodic_backup(
+    sqlite: &SqlitePool,
+    directory: &Path,
+    sqlite_configuration: &SqliteConfiguration,
+) -> anyhow::Result<()> {
+    fs::create_dir_all(directory).context("unable to create the backup directory")?;
+
+    // the timestamp is zero-padded so that sorting by name sorts by age
+    let path = directory.join(format!(
+        "{}{:020}{}",
+        BACKUP_PREFIX,
+        SystemTime::UNIX_EPOCH.elapsed()?.as_secs(),
+        BACKUP_EXTENSION
+    ));
+
+    backup(sqlite, &path).await?;
+
+    info!("backed up the db to {}", path.display());
+
+    let mut backups = fs::read_dir(directory)
+        .context("unable to read the backup directory")?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_EXTENSION)
+                })
+        })
+        .collect::<Vec<_>>();
+
+    backups.sort_unstable();
+
+    let excess = backups
+        .len()
+        .saturating_sub(sqlite_configuration.backup_retention);
+
+    for path in &backups[..excess] {
+        debug!("removing old backup {}", path.display());
+
+        fs::remove_file(path)
+            .with_context(|| format!("unable to remove the old backup {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Replace the database with a backup, after checking that the backup is intact and was made
+/// with the schema this build expects
+///
+/// The server must not be running while this happens
+pub async fn restore(
+    sqlite_configuration: &SqliteConfiguration,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let database = &sqlite_configuration.path;
+    let mut staging = database.clone().into_os_string();
+    staging.push(".restore");
+    let staging = PathBuf::from(staging);
+
+    // copying next to the database first means that the swap itself is a single rename, so an
+    // interrupted restore never leaves a partially written database behind. it also lets the
+    // copy be validated without touching the backup, as checking fts5 tables requires write
+    // access
+    fs::copy(path, &staging)
+        .with_context(|| format!("unable to copy the backup to {}", staging.display()))?;
+
+    if let Err(error) = validate(&staging).await {
+        fs::remove_file(&staging)
+            .with_context(|| format!("unable to remove {}", staging.display()))?;
+
+        return Err(error);
+    }
+
+    fs::File::open(&staging)
+        .and_then(|file| file.sync_all())
+        .context("unable to flush the copied backup to disk")?;
+
+    // the write-ahead log belongs to the old database and must not be replayed onto the new one
+    for suffix in ["-wal", "-shm"] {
+        let mut sidecar = database.clone().into_os_string();
+        sidecar.push(suffix);
+        let sidecar = PathBuf::from(sidecar);
+
+        match fs::remove_file(&sidecar) {
+            Err(error) if error.kind() != ErrorKind::NotFound => {
+                return Err(error)
+                    .with_context(|| format!("unable to remove {}", sidecar.display()))
+            }
+            _ => {}
+        }
+    }
+
+    fs::rename(&staging, database)
+        .with_context(|| format!("unable to replace {}", database.display()))?;
+
+    info!("restored {} from {}", database.display(), path.display());
+
+    Ok(())
+}
+
+/// Check that a database is intact and was made with the schema this build expects
+async fn validate(path: &Path) -> anyhow::Result<()> {
+    trace!("validating the backup at {}", path.display());
+
+    let mut connection = SqliteConnectOptions::new()
+        .filename(path)
+        .connect()
+        .await
+        .with_context(|| format!("unable to open the backup at {}", path.display()))?;
+
+    let version = schema::version(&mut connection)
+        .await
+        .context("unable to read the backup's schema version")?;
+
+    if version != SCHEMA_VERSION {
+        bail!(
+            "the backup has schema version {}, but this build of flock expects schema version {}",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+
+    let integrity = sqlx::query_scalar::<_, String>("PRAGMA integrity_check")
+        .fetch_all(&mut connection)
+        .await
+        .context("unable to check the backup's integrity")?;
+
+    if integrity != ["ok"] {
+        bail!("the backup is corrupt: {}", integrity.join(", "));
+    }
+
+    connection
+        .close()
+        .await
+        .context("unable to close the backup")
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{
+        sqlite::{SqliteConnectOptions, SqlitePool},
+        ConnectOptions, Connection,
+    };
+    use std::{env, fs, path::PathBuf};
+    use ulid::Ulid;
+
+    use super::{backup, periodic_backup, restore};
+    use crate::{configuration::Sqlite as SqliteConfiguration, schema};
+
+    /// Create an empty directory for a test to write databases into, along with a migrated
+    /// database within it to back up
+    ///
+    /// The database is stored on disk, as `VACUUM INTO` writes in-memory databases' backups
+    /// into memory as well
+    async fn setup() -> (PathBuf, SqlitePool) {
+        let directory = env::temp_dir().join(format!("flock-backup-{}", Ulid::new()));
+        fs::create_dir(&directory).unwrap();
+
+        let sqlite = SqlitePool::connect_with(
+            SqliteConnectOptions::new()
+                .filename(directory.join("live.db"))
+                .create_if_missing(true),
+        )
+        .await
+        .unwrap();
+        schema::migrate(&mut sqlite.acquire().await.unwrap())
+            .await
+            .unwrap();
+
+        (directory, sqlite)
+    }
+
+    #[tokio::test]
+    async fn restored_backups_replace_the_database() {
+        let (directory, sqlite) = setup().await;
+        sqlx::query("INSERT INTO tags VALUES ('cats', 'cats')")
+            .execute(&sqlite)
+            .await
+            .unwrap();
+
+        let path = directory.join("backup.db");
+        backup(&sqlite, &path).await.unwrap();
+        assert!(backup(&sqlite, &path).await.is_err());
+
+        let sqlite_configuration = SqliteConfiguration {
+            path: directory.join("flock.db"),
+            ..Default::default()
+        };
+        fs::write(&sqlite_configuration.path, "not a database").unwrap();
+        fs::write(directory.join("flock.db-wal"), "not a write-ahead log").unwrap();
+
+        restore(&sqlite_configuration, &path).await.unwrap();
+
+        assert!(!directory.join("flock.db-wal").exists());
+        assert!(!directory.join("flock.db.restore").exists());
+
+        let mut connection = SqliteConnectOptions::new()
+            .filename(&sqlite_configuration.path)
+            .connect()
+            .await
+            .unwrap();
+        let tags = sqlx::query_scalar::<_, String>("SELECT name FROM tags")
+            .fetch_all(&mut connection)
+            .await
+            .unwrap();
+        assert_eq!(tags, ["cats"]);
+        connection.close().await.unwrap();
+
+        sqlite.close().await;
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn backups_with_another_schema_version_are_rejected() {
+        let (directory, sqlite) = setup().await;
+        sqlx::query(&format!(
+            "PRAGMA user_version = {}",
+            schema::SCHEMA_VERSION + 1
+        ))
+        .execute(&sqlite)
+        .await
+        .unwrap();
+
+        let path = directory.join("backup.db");
+        backup(&sqlite, &path).await.unwrap();
+
+        let sqlite_configuration = SqliteConfiguration {
+            path: directory.join("flock.db"),
+            ..Default::default()
+        };
+        fs::write(&sqlite_configuration.path, "the current database").unwrap();
+
+        assert!(restore(&sqlite_configuration, &path).await.is_err());
+
+        assert_eq!(
+            fs::read_to_string(&sqlite_configuration.path).unwrap(),
+            "the current database"
+        );
+        assert!(!directory.join("flock.db.restore").exists());
+
+        sqlite.close().await;
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn periodic_backups_remove_the_oldest_beyond_the_retention() {
+        let (directory, sqlite) = setup().await;
+        let backups = directory.join("backups");
+        fs::create_dir(&backups).unwrap();
+
+        for (name, contents) in [
+            ("flock-00000000000000000001.db", "oldest"),
+            ("flock-00000000000000000002.db", "older"),
+            ("unrelated.db", "unrelated"),
+        ] {
+            fs::write(backups.join(name), contents).unwrap();
+        }
+
+        let sqlite_configuration = SqliteConfiguration {
+            backup_retention: 2,
+            ..Default::default()
+        };
+        periodic_backup(&sqlite, &backups, &sqlite_configuration)
+            .await
+            .unwrap();
+
+        let mut remaining = fs::read_dir(&backups)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        remaining.sort_unstable();
+
+        assert_eq!(remaining.len(), 3);
+        assert_eq!(remaining[0], "flock-00000000000000000002.db");
+        assert!(remaining[1].starts_with("flock-") && remaining[1] != remaining[0]);
+        assert_eq!(remaining[2], "unrelated.db");
+
+        sqlite.close().await;
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
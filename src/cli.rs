@@ -0,0 +1,124 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+/// baa (with twenty instances of the letter "a")
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Start the server (the default when no command is given)
+    Serve,
+
+    /// Write a consistent copy of the database to a new file. This is safe to do while the
+    /// server is running
+    Backup {
+        /// The path to write the backup to, which must not already exist
+        path: PathBuf,
+    },
+
+    /// Replace the database with a backup. The server must be stopped first
+    Restore {
+        /// The path of the backup to restore
+        path: PathBuf,
+    },
+
+    /// Create the database, or bring an existing one up to date with this build's schema
+    Migrate,
+
+    /// Inspect or remove accounts
+    Account {
+        #[command(subcommand)]
+        command: AccountCommand,
+    },
+
+    /// Remove links
+    Link {
+        #[command(subcommand)]
+        command: LinkCommand,
+    },
+
+    /// Maintain tags
+    Tag {
+        #[command(subcommand)]
+        command: TagCommand,
+    },
+
+    /// Create an invite which isn't counted against any account's quota, for signing up while
+    /// signups are invite-only
+    Invite,
+
+    /// Apply any pending decay and rating periods to every score. The server should be
+    /// stopped first
+    RecomputeScores,
+
+    /// Print counts of what's stored in the database
+    Stats,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AccountCommand {
+    /// Print a summary of everything stored about an account
+    Show {
+        /// The id of the account
+        account_id: String,
+    },
+
+    /// Delete an account and everything keyed by its id
+    Delete {
+        /// The id of the account
+        account_id: String,
+    },
+
+    /// Grant an account a role, allowing it to use the moderation queue
+    Role {
+        /// The id of the account
+        account_id: String,
+
+        /// The role to grant: `admin`, `moderator`, or `none` to take its role away
+        role: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LinkCommand {
+    /// Delete a link, removing it from any feeds it appears in
+    Delete {
+        /// The id of the link
+        link_id: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TagCommand {
+    /// Merge one tag into another. The server should be stopped first
+    Merge {
+        /// The name of the tag to merge, which is removed afterwards
+        from: String,
+
+        /// The name of the tag to merge into
+        into: String,
+    },
+}
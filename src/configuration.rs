@@ -46,6 +46,10 @@ pub struct Configuration {
     /// The `algorithm` section of the configuration
     #[serde(default)]
     pub algorithm: Algorithm,
+
+    /// The `rate_limits` section of the configuration
+    #[serde(default)]
+    pub rate_limits: RateLimits,
 }
 
 impl Configuration {
@@ -148,6 +152,18 @@ pub struct Sqlite {
 
     /// The maximum number of connections to have open to the database
     pub max_connections: Option<u32>,
+
+    /// The directory periodic backups of the database are written to. Unset to disable
+    /// periodic backups
+    pub backup_directory: Option<PathBuf>,
+
+    /// The amount of time between periodic backups
+    #[serde(default = "default_backup_period", with = "humantime_serde")]
+    pub backup_period: Duration,
+
+    /// The number of periodic backups to keep before the oldest are removed
+    #[serde(default = "default_backup_retention")]
+    pub backup_retention: usize,
 }
 
 impl Default for Sqlite {
@@ -157,6 +173,9 @@ impl Default for Sqlite {
             create_if_missing: default_create_if_missing(),
             min_connections: None,
             max_connections: None,
+            backup_directory: None,
+            backup_period: default_backup_period(),
+            backup_retention: default_backup_retention(),
         }
     }
 }
@@ -176,18 +195,70 @@ fn default_create_if_missing() -> bool {
     true
 }
 
+/// The default value for the `backup_period` field in the [`Sqlite`] configuration section
+#[inline(always)]
+fn default_backup_period() -> Duration {
+    // 24 hours
+    Duration::from_secs(60 * 60 * 24)
+}
+
+/// The default value for the `backup_retention` field in the [`Sqlite`] configuration section
+#[inline(always)]
+fn default_backup_retention() -> usize {
+    7
+}
+
 /// Configuration pertaining specifically to how routes are responded to
 #[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct Routes {
     /// Whether or not to enforce that cookies be set only to secure origins
     #[serde(default = "default_secure_cookies")]
     pub secure_cookies: bool,
+
+    /// Who may sign up
+    #[serde(default = "default_signup_mode")]
+    pub signup_mode: SignupMode,
+
+    /// The number of invites each account may create while signups are invite-only
+    #[serde(default = "default_invite_quota")]
+    pub invite_quota: usize,
+
+    /// The number of leading zero bits the hash of a proof-of-work stamp must have while
+    /// signups require proof of work. Each additional bit doubles the expected work
+    #[serde(default = "default_proof_of_work_difficulty")]
+    pub proof_of_work_difficulty: u32,
+
+    /// The maximum size of an uploaded style, in bytes
+    #[serde(default = "default_max_style_size")]
+    pub max_style_size: usize,
+
+    /// The number of stylesheets kept in memory, to avoid querying the db for them on every
+    /// page load
+    #[serde(default = "default_style_cache_capacity")]
+    pub style_cache_capacity: usize,
+
+    /// The number of accounts whose choice of style is kept in memory, to avoid querying the
+    /// db for it on every page load
+    #[serde(default = "default_account_style_cache_capacity")]
+    pub account_style_cache_capacity: usize,
+
+    /// How long a request waits for a lock on an account's or link's information (held while
+    /// rating, changing tags or regenerating a feed) before giving up
+    #[serde(default = "default_lock_timeout", with = "humantime_serde")]
+    pub lock_timeout: Duration,
 }
 
 impl Default for Routes {
     fn default() -> Self {
         Self {
             secure_cookies: default_secure_cookies(),
+            signup_mode: default_signup_mode(),
+            invite_quota: default_invite_quota(),
+            proof_of_work_difficulty: default_proof_of_work_difficulty(),
+            max_style_size: default_max_style_size(),
+            style_cache_capacity: default_style_cache_capacity(),
+            account_style_cache_capacity: default_account_style_cache_capacity(),
+            lock_timeout: default_lock_timeout(),
         }
     }
 }
@@ -198,6 +269,69 @@ fn default_secure_cookies() -> bool {
     false
 }
 
+/// The default value for the `signup_mode` field in the [`Routes`] configuration section
+#[inline(always)]
+fn default_signup_mode() -> SignupMode {
+    SignupMode::Open
+}
+
+/// The default value for the `invite_quota` field in the [`Routes`] configuration section
+#[inline(always)]
+fn default_invite_quota() -> usize {
+    5
+}
+
+/// The default value for the `proof_of_work_difficulty` field in the [`Routes`]
+/// configuration section
+#[inline(always)]
+fn default_proof_of_work_difficulty() -> u32 {
+    // roughly a million hashes, which takes a second or so
+    20
+}
+
+/// The default value for the `max_style_size` field in the [`Routes`] configuration section
+#[inline(always)]
+fn default_max_style_size() -> usize {
+    64 * 1024
+}
+
+/// The default value for the `style_cache_capacity` field in the [`Routes`] configuration
+/// section
+#[inline(always)]
+fn default_style_cache_capacity() -> usize {
+    256
+}
+
+/// The default value for the `account_style_cache_capacity` field in the [`Routes`]
+/// configuration section
+#[inline(always)]
+fn default_account_style_cache_capacity() -> usize {
+    10_000
+}
+
+/// The default value for the `lock_timeout` field in the [`Routes`] configuration section
+#[inline(always)]
+fn default_lock_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// The ways in which signups can be restricted
+#[derive(Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignupMode {
+    /// Anyone may sign up
+    Open,
+
+    /// Signing up requires an invite created by an existing account
+    Invite,
+
+    /// Nobody may sign up
+    Closed,
+
+    /// Signing up requires a hashcash stamp for a challenge issued by the signup page
+    ProofOfWork,
+}
+
 /// Configuration pertaining to the algorithm
 #[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct Algorithm {
@@ -205,16 +339,50 @@ pub struct Algorithm {
     #[serde(default = "default_feed_refresh_period", with = "humantime_serde")]
     pub feed_refresh_period: Duration,
 
+    /// How long an account's past feeds are kept for, after which they're removed the next time
+    /// its feed is refreshed
+    #[serde(default = "default_feed_retention", with = "humantime_serde")]
+    pub feed_retention: Duration,
+
     /// The rating period, in terms of number of ratings made
     #[serde(default = "default_rating_period")]
     pub rating_period: usize,
+
+    /// The refresh period for the front page shown to logged-out visitors
+    #[serde(
+        default = "default_front_page_refresh_period",
+        with = "humantime_serde"
+    )]
+    pub front_page_refresh_period: Duration,
+
+    /// The number of links in each list on the front page
+    #[serde(default = "default_front_page_length")]
+    pub front_page_length: usize,
+
+    /// The minimum amount of time between manual refreshes of an account's feed
+    #[serde(default = "default_manual_refresh_interval", with = "humantime_serde")]
+    pub manual_refresh_interval: Duration,
+
+    /// The number of manual refreshes an account may make in a day
+    #[serde(default = "default_manual_refresh_quota")]
+    pub manual_refresh_quota: usize,
+
+    /// The number of feed refreshes a link may be delivered in before it is treated as seen,
+    /// even if it was never visited. Unset to only treat visited links as seen
+    pub delivered_as_seen_after: Option<usize>,
 }
 
 impl Default for Algorithm {
     fn default() -> Self {
         Self {
             feed_refresh_period: default_feed_refresh_period(),
+            feed_retention: default_feed_retention(),
             rating_period: default_rating_period(),
+            front_page_refresh_period: default_front_page_refresh_period(),
+            front_page_length: default_front_page_length(),
+            manual_refresh_interval: default_manual_refresh_interval(),
+            manual_refresh_quota: default_manual_refresh_quota(),
+            delivered_as_seen_after: None,
         }
     }
 }
@@ -226,9 +394,211 @@ fn default_feed_refresh_period() -> Duration {
     Duration::from_secs(60 * 60 * 24)
 }
 
+/// The default value for the `feed_retention` field in the [`Algorithm`] configuration section
+#[inline(always)]
+fn default_feed_retention() -> Duration {
+    // 30 days
+    Duration::from_secs(60 * 60 * 24 * 30)
+}
+
 /// The default value for the `rating_period` field in the [`Algorithm`] configuration section
 #[inline(always)]
 fn default_rating_period() -> usize {
     // 5 links
     5
 }
+
+/// The default value for the `front_page_refresh_period` field in the [`Algorithm`]
+/// configuration section
+#[inline(always)]
+fn default_front_page_refresh_period() -> Duration {
+    // 15 minutes
+    Duration::from_secs(60 * 15)
+}
+
+/// The default value for the `front_page_length` field in the [`Algorithm`] configuration
+/// section
+#[inline(always)]
+fn default_front_page_length() -> usize {
+    30
+}
+
+/// The default value for the `manual_refresh_interval` field in the [`Algorithm`]
+/// configuration section
+#[inline(always)]
+fn default_manual_refresh_interval() -> Duration {
+    // 10 minutes
+    Duration::from_secs(60 * 10)
+}
+
+/// The default value for the `manual_refresh_quota` field in the [`Algorithm`] configuration
+/// section
+#[inline(always)]
+fn default_manual_refresh_quota() -> usize {
+    5
+}
+
+/// Configuration pertaining to the rate limits applied to routes which create things
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct RateLimits {
+    /// Whether or not to take the client's address from the `X-Forwarded-For` header. Only
+    /// enable this if flock is behind a reverse proxy which sets it
+    #[serde(default = "default_trust_forwarded_for")]
+    pub trust_forwarded_for: bool,
+
+    /// The maximum number of clients tracked by each rate limit, beyond which the least
+    /// recently seen are forgotten
+    #[serde(default = "default_max_tracked_clients")]
+    pub max_tracked_clients: usize,
+
+    /// The rate limits applied to signing up
+    #[serde(default = "default_signup_rate_limit")]
+    pub signup: RateLimit,
+
+    /// The rate limits applied to posting links
+    #[serde(default = "default_post_rate_limit")]
+    pub post: RateLimit,
+
+    /// The rate limits applied to importing links, each import carrying up to a thousand
+    #[serde(default = "default_import_rate_limit")]
+    pub import: RateLimit,
+
+    /// The rate limits applied to uploading styles
+    #[serde(default = "default_post_style_rate_limit")]
+    pub post_style: RateLimit,
+
+    /// The rate limits applied to rating links
+    #[serde(default = "default_rating_rate_limit")]
+    pub rating: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            trust_forwarded_for: default_trust_forwarded_for(),
+            max_tracked_clients: default_max_tracked_clients(),
+            signup: default_signup_rate_limit(),
+            post: default_post_rate_limit(),
+            import: default_import_rate_limit(),
+            post_style: default_post_style_rate_limit(),
+            rating: default_rating_rate_limit(),
+        }
+    }
+}
+
+/// The default value for the `trust_forwarded_for` field in the [`RateLimits`] configuration
+/// section
+#[inline(always)]
+fn default_trust_forwarded_for() -> bool {
+    false
+}
+
+/// The default value for the `max_tracked_clients` field in the [`RateLimits`] configuration
+/// section
+#[inline(always)]
+fn default_max_tracked_clients() -> usize {
+    100_000
+}
+
+/// The default value for the `signup` field in the [`RateLimits`] configuration section
+#[inline(always)]
+fn default_signup_rate_limit() -> RateLimit {
+    RateLimit {
+        // 5 accounts, then one every 10 minutes
+        per_ip: Some(Bucket {
+            burst: 5,
+            period: Duration::from_secs(60 * 10),
+        }),
+        per_account: None,
+    }
+}
+
+/// The default value for the `post` field in the [`RateLimits`] configuration section
+#[inline(always)]
+fn default_post_rate_limit() -> RateLimit {
+    RateLimit {
+        // 20 links, then one every minute
+        per_ip: Some(Bucket {
+            burst: 20,
+            period: Duration::from_secs(60),
+        }),
+        // 10 links, then one every 2 minutes
+        per_account: Some(Bucket {
+            burst: 10,
+            period: Duration::from_secs(60 * 2),
+        }),
+    }
+}
+
+/// The default value for the `import` field in the [`RateLimits`] configuration section
+#[inline(always)]
+fn default_import_rate_limit() -> RateLimit {
+    RateLimit {
+        // 3 imports, then one every hour
+        per_ip: Some(Bucket {
+            burst: 3,
+            period: Duration::from_secs(60 * 60),
+        }),
+        // 2 imports, then one every 12 hours
+        per_account: Some(Bucket {
+            burst: 2,
+            period: Duration::from_secs(60 * 60 * 12),
+        }),
+    }
+}
+
+/// The default value for the `post_style` field in the [`RateLimits`] configuration section
+#[inline(always)]
+fn default_post_style_rate_limit() -> RateLimit {
+    RateLimit {
+        // 10 styles, then one every 10 minutes
+        per_ip: Some(Bucket {
+            burst: 10,
+            period: Duration::from_secs(60 * 10),
+        }),
+        // 5 styles, then one every 30 minutes
+        per_account: Some(Bucket {
+            burst: 5,
+            period: Duration::from_secs(60 * 30),
+        }),
+    }
+}
+
+/// The default value for the `rating` field in the [`RateLimits`] configuration section
+#[inline(always)]
+fn default_rating_rate_limit() -> RateLimit {
+    RateLimit {
+        // 120 ratings, then one every 2 seconds
+        per_ip: Some(Bucket {
+            burst: 120,
+            period: Duration::from_secs(2),
+        }),
+        // 60 ratings, then one every 5 seconds
+        per_account: Some(Bucket {
+            burst: 60,
+            period: Duration::from_secs(5),
+        }),
+    }
+}
+
+/// The structure representing a single class of routes in the `rate_limits` section of the
+/// configuration
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct RateLimit {
+    /// The token bucket each client address is given. Unset to not limit by address
+    pub per_ip: Option<Bucket>,
+
+    /// The token bucket each account is given. Unset to not limit by account
+    pub per_account: Option<Bucket>,
+}
+
+/// A token bucket, which allows a burst of requests before limiting clients to a steady rate
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Bucket {
+    /// The number of requests that can be made at once
+    pub burst: u32,
+
+    /// The amount of time it takes for one more request to become available
+    #[serde(with = "humantime_serde")]
+    pub period: Duration,
+}
@@ -0,0 +1,344 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use cssparser::{ParseError, Parser, ParserInput, Token};
+
+/// How deeply blocks and functions may be nested before the statement containing them is
+/// removed, which keeps pathological stylesheets from exhausting the stack
+const MAX_NESTING_DEPTH: usize = 32;
+
+/// The reason part of a stylesheet was removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Problem {
+    Import,
+    ExternalUrl,
+    ParseError,
+    TooDeep,
+}
+
+impl Problem {
+    fn describe(&self) -> &'static str {
+        match self {
+            Self::Import => "@import rules aren't allowed",
+            Self::ExternalUrl => "urls must refer to this instance",
+            Self::ParseError => "it couldn't be parsed",
+            Self::TooDeep => "it is nested too deeply",
+        }
+    }
+}
+
+/// A stylesheet with everything that could load resources from elsewhere removed
+#[derive(Debug)]
+pub struct Sanitized {
+    pub stylesheet: String,
+
+    /// A description of each part of the stylesheet that was removed, and why
+    pub removals: Vec<String>,
+}
+
+/// Sanitize a stylesheet, removing `@import` rules, rules and declarations referring to urls
+/// outside of this instance, and anything which couldn't be parsed
+///
+/// Everything else is kept exactly as it was written
+pub fn sanitize(stylesheet: &str) -> Sanitized {
+    let mut input = ParserInput::new(stylesheet);
+    let mut parser = Parser::new(&mut input);
+    let mut sanitized = Sanitized {
+        stylesheet: String::with_capacity(stylesheet.len()),
+        removals: Vec::new(),
+    };
+
+    sanitize_block(&mut parser, &mut sanitized, 0);
+
+    sanitized
+}
+
+/// Sanitize a list of statements (rules at the top level, or declarations and nested rules
+/// within a block), appending what remains to the output
+fn sanitize_block(parser: &mut Parser<'_, '_>, sanitized: &mut Sanitized, depth: usize) {
+    loop {
+        let start = parser.position();
+        let location = parser.current_source_location();
+
+        let mut token = match parser.next_including_whitespace_and_comments() {
+            Ok(Token::WhiteSpace(_) | Token::Comment(_)) => {
+                sanitized.stylesheet.push_str(parser.slice_from(start));
+                continue;
+            }
+            Ok(token) => token.clone(),
+            Err(_) => return,
+        };
+
+        let mut problem = None;
+
+        if let Token::AtKeyword(name) = &token
+            && name.eq_ignore_ascii_case("import")
+        {
+            problem = Some(Problem::Import);
+        }
+
+        // statements end with a semicolon, a block or the end of the input
+        let has_block = loop {
+            match token {
+                Token::Semicolon => break false,
+                Token::CurlyBracketBlock => break true,
+                token => check_token(parser, &token, &mut problem, false, depth),
+            }
+
+            token = match parser.next_including_whitespace_and_comments() {
+                Ok(token) => token.clone(),
+                Err(_) => break false,
+            };
+        };
+
+        if has_block && depth >= MAX_NESTING_DEPTH {
+            problem.get_or_insert(Problem::TooDeep);
+        }
+
+        if let Some(problem) = problem {
+            // the block, if any, is skipped by the next call to the parser
+            sanitized.removals.push(format!(
+                "line {}, column {}: removed because {}",
+                location.line + 1,
+                location.column,
+                problem.describe()
+            ));
+        } else if has_block {
+            sanitized.stylesheet.push_str(parser.slice_from(start));
+
+            let _ = parser.parse_nested_block(|parser| {
+                sanitize_block(parser, sanitized, depth + 1);
+
+                Ok::<_, ParseError<'_, ()>>(())
+            });
+
+            sanitized.stylesheet.push('}');
+        } else {
+            sanitized.stylesheet.push_str(parser.slice_from(start));
+        }
+    }
+}
+
+/// Check a token within a statement for anything that would cause the statement to be
+/// removed, descending into functions and blocks
+///
+/// `url_strings` is set within functions whose string arguments are urls, such as `url()` and
+/// `image-set()`
+fn check_token(
+    parser: &mut Parser<'_, '_>,
+    token: &Token<'_>,
+    problem: &mut Option<Problem>,
+    url_strings: bool,
+    depth: usize,
+) {
+    let nested_url_strings = match token {
+        Token::UnquotedUrl(url) => {
+            if is_external(url) {
+                problem.get_or_insert(Problem::ExternalUrl);
+            }
+
+            return;
+        }
+        Token::QuotedString(url) => {
+            if url_strings && is_external(url) {
+                problem.get_or_insert(Problem::ExternalUrl);
+            }
+
+            return;
+        }
+        Token::BadUrl(_)
+        | Token::BadString(_)
+        | Token::CloseParenthesis
+        | Token::CloseSquareBracket
+        | Token::CloseCurlyBracket => {
+            problem.get_or_insert(Problem::ParseError);
+
+            return;
+        }
+        Token::Function(name) => ["url", "src", "image-set", "-webkit-image-set"]
+            .iter()
+            .any(|function| name.eq_ignore_ascii_case(function)),
+        Token::ParenthesisBlock | Token::SquareBracketBlock | Token::CurlyBracketBlock => false,
+        _ => return,
+    };
+
+    if depth >= MAX_NESTING_DEPTH {
+        problem.get_or_insert(Problem::TooDeep);
+
+        return;
+    }
+
+    let _ = parser.parse_nested_block(|parser| {
+        while let Ok(token) = parser.next_including_whitespace_and_comments() {
+            let token = token.clone();
+
+            check_token(parser, &token, problem, nested_url_strings, depth + 1);
+        }
+
+        Ok::<_, ParseError<'_, ()>>(())
+    });
+}
+
+/// Determine whether a url refers to something outside of this instance
+///
+/// Only relative urls and `data:` urls (which embed what they refer to) are allowed, so
+/// anything else with a scheme is considered external
+fn is_external(url: &str) -> bool {
+    // browsers ignore tabs and newlines anywhere in a url, and leading spaces and control
+    // characters
+    let url = url
+        .chars()
+        .filter(|character| !matches!(character, '\t' | '\n' | '\r'))
+        .collect::<String>();
+    let url = url.trim_start_matches(|character: char| character <= ' ');
+
+    // protocol-relative urls, where backslashes are treated like slashes
+    let mut characters = url.chars();
+    if matches!(
+        (characters.next(), characters.next()),
+        (Some('/' | '\\'), Some('/' | '\\'))
+    ) {
+        return true;
+    }
+
+    url.split_once(':').is_some_and(|(scheme, _)| {
+        !scheme.is_empty()
+            && scheme
+                .chars()
+                .all(|character| character.is_ascii_alphanumeric() || "+-.".contains(character))
+            && !scheme.eq_ignore_ascii_case("data")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_external, sanitize, MAX_NESTING_DEPTH};
+
+    #[test]
+    fn urls_with_schemes_are_external() {
+        for url in [
+            "https://example.com/a.png",
+            "HTTP://example.com",
+            "//example.com/a.png",
+            "\\\\example.com",
+            "/\\example.com",
+            " \t//example.com",
+            "java\nscript:alert(1)",
+            "ftp:a",
+        ] {
+            assert!(is_external(url), "{:?}", url);
+        }
+    }
+
+    #[test]
+    fn relative_and_data_urls_are_local() {
+        for url in [
+            "/styles/a.png",
+            "a.png",
+            "../a.png",
+            "#fragment",
+            "?a=b:c",
+            "",
+            "data:image/png;base64,AAAA",
+            "DATA:text/plain,hi",
+        ] {
+            assert!(!is_external(url), "{:?}", url);
+        }
+    }
+
+    #[test]
+    fn safe_stylesheets_are_unchanged() {
+        let stylesheet = "body { color: red; }\n/* c */ a:hover{background:url(/x.png)}\n\
+                          a::after { content: \"https://example.com\" }";
+        let sanitized = sanitize(stylesheet);
+
+        assert_eq!(sanitized.stylesheet, stylesheet);
+        assert!(sanitized.removals.is_empty());
+    }
+
+    #[test]
+    fn imports_are_removed() {
+        let sanitized = sanitize("@import url(\"https://example.com/a.css\");\nbody{color:red}");
+        assert_eq!(sanitized.stylesheet, "\nbody{color:red}");
+        assert_eq!(
+            sanitized.removals,
+            ["line 1, column 1: removed because @import rules aren't allowed"]
+        );
+
+        assert_eq!(sanitize("@IMPORT 'a.css'; a{}").stylesheet, " a{}");
+    }
+
+    #[test]
+    fn external_urls_are_removed() {
+        for (stylesheet, expected) in [
+            (
+                "a { color: red; background: url(https://example.com/a.png); margin: 0 }",
+                "a { color: red;  margin: 0 }",
+            ),
+            (
+                "a { background: url(\"//example.com/a\"); content: \"https://example.com\" }",
+                "a {  content: \"https://example.com\" }",
+            ),
+            (
+                "a { background-image: image-set(\"https://example.com/a.png\" 1x); }",
+                "a {  }",
+            ),
+            (
+                "@font-face { font-family: x; src: url(https://example.com/f.woff) }\np{}",
+                "@font-face { font-family: x; }\np{}",
+            ),
+        ] {
+            let sanitized = sanitize(stylesheet);
+
+            assert_eq!(sanitized.stylesheet, expected);
+            assert_eq!(sanitized.removals.len(), 1);
+            assert!(sanitized.removals[0].ends_with("urls must refer to this instance"));
+        }
+    }
+
+    #[test]
+    fn unparseable_statements_are_removed() {
+        let sanitized = sanitize("a { color: red; ) } b { color: blue }");
+
+        assert_eq!(sanitized.stylesheet, "a { color: red; } b { color: blue }");
+        assert_eq!(
+            sanitized.removals,
+            ["line 1, column 17: removed because it couldn't be parsed"]
+        );
+    }
+
+    #[test]
+    fn deep_nesting_is_removed() {
+        let depth = MAX_NESTING_DEPTH + 8;
+        let blocks = "@media screen {".repeat(depth) + &"}".repeat(depth);
+        let sanitized = sanitize(&blocks);
+
+        assert_eq!(
+            sanitized.stylesheet,
+            "@media screen {".repeat(MAX_NESTING_DEPTH) + &"}".repeat(MAX_NESTING_DEPTH)
+        );
+        assert!(sanitized.removals[0].ends_with("it is nested too deeply"));
+
+        let functions = format!(
+            "a {{ b: calc({}{}) }}",
+            "(".repeat(depth),
+            ")".repeat(depth)
+        );
+        assert_eq!(sanitize(&functions).stylesheet, "a { }");
+    }
+}
@@ -18,32 +18,54 @@
 
 use axum::http::StatusCode;
 use rand::seq::SliceRandom;
-use sqlx::{pool::PoolConnection, Sqlite};
-use std::collections::{HashMap, HashSet};
+use sqlx::{pool::PoolConnection, Connection, Sqlite, SqliteConnection, SqlitePool};
+use std::{
+    collections::{HashMap, HashSet},
+    iter,
+    time::{Duration, SystemTime},
+};
 use tracing::{debug, trace};
 
 use crate::{
     configuration::Algorithm as AlgorithmConfiguration,
+    locks::{LockMap, LockMapGuard},
     model,
     rand::pcg_thread_rng,
+    scores,
     util::{self, ScaledRatingData, ScaledRatingWrapper},
 };
 
-//TODO(superwhiskers): this is all pretty suboptimal. pass over it and optimize it
-pub async fn generate_feed<'a>(
-    algorithm_configuration: &'a AlgorithmConfiguration,
-    mut connection: PoolConnection<Sqlite>,
-    account_id: &'a str,
-) -> Result<Vec<(String, ScaledRatingData)>, (StatusCode, &'static str)> {
-    trace!("generating feed for account {}", account_id);
+/// The number of links displayed on a single page of an account's feed archive
+pub const ARCHIVE_PAGE_LENGTH: usize = 25;
+
+/// A link which was in one of an account's past feeds
+#[derive(Debug)]
+pub struct ArchivedLink {
+    pub id: String,
+    pub description: String,
 
+    /// The time the last feed the link was in was generated, expressed in seconds since unix
+    /// epoch
+    pub delivered: u64,
+
+    /// Whether the account has visited the link (or had it treated as seen)
+    pub visited: bool,
+    pub rated: bool,
+}
+
+/// Find the links which could be picked for an account's feed, which are those sharing a tag
+/// with the account that it hasn't seen and which aren't hidden
+pub async fn candidates(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+) -> Result<HashSet<String>, (StatusCode, &'static str)> {
     let mut candidates: HashSet<String> = HashSet::new();
 
     for tag in sqlx::query_scalar!(
         r#"SELECT tag_id as "tag_id!" FROM scores WHERE id = ?"#,
         account_id
     )
-    .fetch_all(&mut *connection)
+    .fetch_all(&mut **connection)
     .await
     .map_err(|_| {
         (
@@ -66,11 +88,12 @@ pub async fn generate_feed<'a>(
                               FROM scores
                              WHERE scores.tag_id = ?
                                AND scores.id = links.link_id
-                          )"#,
+                          )
+               AND links.link_id NOT IN (SELECT link_id FROM hidden_links)"#,
                 account_id,
                 tag
             )
-            .fetch_all(&mut *connection)
+            .fetch_all(&mut **connection)
             .await
             .map_err(|_| {
                 (
@@ -81,180 +104,1234 @@ pub async fn generate_feed<'a>(
         );
     }
 
+    Ok(candidates)
+}
+
+/// Lock an account and each of the links which could be picked for its feed, as generating
+/// a feed decays (and writes back) their scores
+///
+/// The locks are held until the returned guards are dropped, and the candidates returned
+/// are to be passed to [`generate_feed`]
+pub async fn lock_feed(
+    lock_map: &'static LockMap,
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+) -> Result<(Vec<LockMapGuard>, HashSet<String>), (StatusCode, &'static str)> {
+    let candidates = candidates(connection, account_id).await?;
+
+    trace!(
+        "locking the tags of account {} and {} candidate links",
+        account_id,
+        candidates.len()
+    );
+
+    let guards = lock_map
+        .lock_many(iter::once(account_id).chain(candidates.iter().map(String::as_str)))
+        .await
+        .ok_or((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "timed out waiting for a lock on the tag information of your account or the links in its feed. try again in a few seconds",
+        ))?;
+
+    Ok((guards, candidates))
+}
+
+/// Generate a feed for the provided account from the candidates locked by [`lock_feed`],
+/// never picking any of the excluded links
+pub async fn generate_feed<'a>(
+    algorithm_configuration: &'a AlgorithmConfiguration,
+    mut connection: PoolConnection<Sqlite>,
+    account_id: &'a str,
+    candidates: &'a HashSet<String>,
+    excluded_links: &'a HashSet<String>,
+) -> Result<Vec<(String, ScaledRatingData, model::FeedExplanation)>, (StatusCode, &'static str)> {
+    trace!("generating feed for account {}", account_id);
+
+    let candidates = candidates
+        .iter()
+        .filter(|candidate| !excluded_links.contains(*candidate))
+        .collect::<Vec<_>>();
+
     if candidates.is_empty() {
         return Ok(Vec::new());
     }
 
-    //TODO(superwhiskers): it would likely be more beneficial memory-wise to not use
-    //                     fetch_all and instead use fetch, if possible
-    //TODO(superwhiskers): consider parallelizing this
-    let mut tags = sqlx::query!(
-        r#"SELECT tag_id as "tag_id!", score as "score!" FROM scores WHERE id = ?"#,
-        account_id
+    let mut tags = scores::load(&mut connection, account_id).await?;
+
+    let mut tag_sum = ScaledRatingData {
+        rating: 0.0,
+        deviation: 0.0,
+        volatility: 0.0,
+    };
+
+    for tag in &mut tags {
+        if util::decay_score(algorithm_configuration, &mut tag.score, 1)? {
+            scores::store(&mut connection, account_id, &tag.tag_id, &tag.score).await?;
+        }
+
+        tag_sum += ScaledRatingWrapper(tag.score.score).abs();
+    }
+
+    let mut tag_importance = HashMap::with_capacity(tags.len());
+    for tag in tags {
+        let importance = (ScaledRatingWrapper(tag.score.score).abs() / tag_sum).prune_nan();
+        debug!(
+            "inserting tag importance for {} with score {:?}: {:?}",
+            tag.tag_id, tag.score, importance
+        );
+        tag_importance.insert(tag.tag_id, importance);
+    }
+
+    let candidates = serde_json::to_string(&candidates).map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to convert the candidate links to json",
+        )
+    })?;
+
+    // only the scores which have outlived a rating period or have enough results queued for
+    // them change when decayed, so only those are brought up to date
+    let decay_cutoff = SystemTime::UNIX_EPOCH
+        .elapsed()
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to calculate the amount of time that has passed since the unix epoch",
+            )
+        })?
+        .as_secs()
+        .saturating_sub(util::period_length(12)) as i64;
+    let rating_period = algorithm_configuration.rating_period as i64;
+
+    for stale in sqlx::query!(
+        r#"SELECT scores.id as "id!", scores.tag_id as "tag_id!"
+             FROM scores
+            WHERE scores.id IN (SELECT value FROM json_each(?1))
+              AND scores.tag_id IN (SELECT tag_id FROM scores WHERE id = ?2)
+              AND (
+                       scores.last_period <= ?3
+                    OR (
+                           SELECT COUNT(1)
+                             FROM score_results
+                            WHERE score_results.id = scores.id
+                              AND score_results.tag_id = scores.tag_id
+                       ) >= ?4
+                  )"#,
+        candidates,
+        account_id,
+        decay_cutoff,
+        rating_period
     )
     .fetch_all(&mut *connection)
     .await
     .map_err(|_| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
-            "unable to query the account's tags from the db",
+            "unable to query the candidate links' scores from the db",
+        )
+    })? {
+        if let Some(mut score) = scores::load_one(&mut connection, &stale.id, &stale.tag_id).await?
+            && util::decay_score(algorithm_configuration, &mut score, 12)?
+        {
+            scores::store(&mut connection, &stale.id, &stale.tag_id, &score).await?;
+        }
+    }
+
+    // each candidate's overall score is the average of its scores for the tags it shares with
+    // the account, weighted by how important those tags are to the account. candidates are
+    // ordered from worst to best, following the ordering of `ScaledRatingData`
+    let mut candidate_scores = sqlx::query!(
+        r#"WITH account_scores AS (
+                    SELECT tag_id, rating, deviation, volatility
+                      FROM scores
+                     WHERE id = ?1
+                ),
+                totals AS (
+                    SELECT SUM(ABS(rating)) AS rating,
+                           SUM(ABS(deviation)) AS deviation,
+                           SUM(ABS(volatility)) AS volatility
+                      FROM account_scores
+                ),
+                importance AS (
+                    SELECT account_scores.tag_id,
+                           COALESCE(ABS(account_scores.rating) / NULLIF(totals.rating, 0), 0) AS rating,
+                           COALESCE(ABS(account_scores.deviation) / NULLIF(totals.deviation, 0), 0) AS deviation,
+                           COALESCE(ABS(account_scores.volatility) / NULLIF(totals.volatility, 0), 0) AS volatility
+                      FROM account_scores, totals
+                )
+           SELECT scores.id as "link_id!",
+                  AVG(importance.rating * scores.rating) as "rating!: f64",
+                  AVG(importance.deviation * scores.deviation) as "deviation!: f64",
+                  AVG(importance.volatility * scores.volatility) as "volatility!: f64"
+             FROM scores
+       INNER JOIN importance ON importance.tag_id = scores.tag_id
+            WHERE scores.id IN (SELECT value FROM json_each(?2))
+         GROUP BY scores.id
+         ORDER BY 2, 3 DESC, 4 DESC"#,
+        account_id,
+        candidates
+    )
+    .fetch_all(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to rank the candidate links",
         )
     })?
     .into_iter()
-    .map(|tag| {
-        rmp_serde::from_slice(&tag.score)
-            .map(|score| (tag.tag_id, score))
-            .map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to deserialize the score data for a tag",
-                )
-            })
+    .map(|candidate| {
+        (
+            candidate.link_id,
+            ScaledRatingData {
+                rating: candidate.rating,
+                deviation: candidate.deviation,
+                volatility: candidate.volatility,
+            },
+        )
     })
-    .collect::<Result<Vec<(String, model::Score)>, _>>()?;
+    .collect::<Vec<_>>();
 
-    let mut tag_sum = ScaledRatingData {
-        rating: 0.0,
-        deviation: 0.0,
-        volatility: 0.0,
+    if candidate_scores.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // the rng can't be held across an await, so links are picked (and shuffled) before their
+    // explanations are put together
+    let picked = {
+        let mut rng = pcg_thread_rng();
+        let mut picked = Vec::with_capacity(10);
+        let segment_length = candidate_scores.len().div_ceil(4);
+        for (i, segment) in candidate_scores.rchunks_mut(segment_length).enumerate() {
+            picked.extend(
+                segment
+                    .choose_multiple(&mut rng, 4 - i)
+                    .map(|(id, overall_score)| (id.to_string(), *overall_score, i)),
+            );
+        }
+
+        picked.shuffle(&mut rng);
+
+        picked
     };
 
-    for (ref tag, ref mut score) in &mut tags {
-        if util::decay_score(algorithm_configuration, score, 1)? {
-            let score = rmp_serde::to_vec(&*score).map_err(|_| {
+    let mut feed = Vec::with_capacity(picked.len());
+    for (id, overall_score, segment) in picked {
+        let explanation = scores::load(&mut connection, &id)
+            .await?
+            .into_iter()
+            .filter_map(|tag| {
+                tag_importance
+                    .get(&tag.tag_id)
+                    .map(|importance| model::TagExplanation {
+                        tag_id: tag.tag_id,
+                        importance: *importance,
+                        score: ScaledRatingWrapper(tag.score.score).into(),
+                    })
+            })
+            .collect();
+
+        feed.push((
+            id,
+            overall_score,
+            model::FeedExplanation {
+                segment,
+                tags: explanation,
+            },
+        ));
+    }
+
+    Ok(feed)
+}
+
+/// Load an account's feed, regenerating it first if it has outlived the refresh period
+///
+/// If the account doesn't exist, `None` is returned
+pub async fn load_feed(
+    algorithm_configuration: &AlgorithmConfiguration,
+    sqlite: &SqlitePool,
+    lock_map: &'static LockMap,
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+) -> Result<Option<model::Feed>, (StatusCode, &'static str)> {
+    let Some(mut feed) = read_feed(connection, account_id).await? else {
+        return Ok(None);
+    };
+
+    if is_stale(algorithm_configuration, &feed)? {
+        trace!("generating new feed for {}", account_id);
+
+        let (_tag_locks, candidates) = lock_feed(lock_map, connection, account_id).await?;
+
+        // another request may have regenerated the feed while this one was waiting
+        let Some(current_feed) = read_feed(connection, account_id).await? else {
+            return Ok(None);
+        };
+
+        if !is_stale(algorithm_configuration, &current_feed)? {
+            return Ok(Some(current_feed));
+        }
+
+        let entries = generate_feed(
+            algorithm_configuration,
+            sqlite.acquire().await.map_err(|_| {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to convert data to messagepack",
+                    "unable to acquire a db connection",
                 )
-            })?;
+            })?,
+            account_id,
+            &candidates,
+            &HashSet::new(),
+        )
+        .await?;
+
+        record_deliveries(
+            algorithm_configuration,
+            connection,
+            account_id,
+            entries.iter().map(|(link_id, _, _)| link_id.as_str()),
+        )
+        .await?;
+
+        feed = model::Feed::new(
+            SystemTime::UNIX_EPOCH
+                .elapsed()
+                .map_err(|_| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "unable to calculate the amount of time that has passed since the unix epoch",
+                    )
+                })?
+                .as_secs(),
+            entries,
+        );
+
+        debug!("new feed for {}: {:?}", account_id, feed);
+
+        replace_feed(algorithm_configuration, connection, account_id, &feed).await?;
+    }
+
+    Ok(Some(feed))
+}
+
+/// Read an account's current feed from the db, returning `None` if the account doesn't exist
+///
+/// Accounts which have never had a feed generated have an empty one, refreshed at the unix epoch
+pub async fn read_feed(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+) -> Result<Option<model::Feed>, (StatusCode, &'static str)> {
+    let Some(generated_at) = sqlx::query_scalar!(
+        r#"SELECT (SELECT MAX(generated_at) FROM feeds WHERE account_id = ?1) as "generated_at?: i64" FROM accounts WHERE account_id = ?1"#,
+        account_id
+    )
+    .fetch_optional(&mut **connection)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to query the db"))?
+    else {
+        return Ok(None);
+    };
+
+    let feed = match generated_at {
+        Some(generated_at) => read_snapshot(connection, account_id, generated_at).await?,
+        None => model::Feed::new(0, Vec::new()),
+    };
+
+    debug!("read feed for {}: {:?}", account_id, feed);
+
+    Ok(Some(feed))
+}
+
+/// Read every feed of an account's that is still kept, from the most to the least recent
+pub async fn read_history(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+) -> Result<Vec<model::Feed>, (StatusCode, &'static str)> {
+    let generated_at = sqlx::query_scalar!(
+        r#"SELECT generated_at as "generated_at!: i64" FROM feeds WHERE account_id = ? ORDER BY generated_at DESC"#,
+        account_id
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's feeds",
+        )
+    })?;
+
+    let mut feeds = Vec::with_capacity(generated_at.len());
+    for generated_at in generated_at {
+        feeds.push(read_snapshot(connection, account_id, generated_at).await?);
+    }
+
+    Ok(feeds)
+}
+
+/// Read a page of the links which were in an account's past feeds but aren't in its current
+/// one, from the most to the least recently delivered
+///
+/// Links which were delivered in more than one feed are listed once, as of the last feed they
+/// were in
+pub async fn read_archive(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+    page: usize,
+) -> Result<Vec<ArchivedLink>, (StatusCode, &'static str)> {
+    let offset = page
+        .checked_mul(ARCHIVE_PAGE_LENGTH)
+        .and_then(|offset| i64::try_from(offset).ok())
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            "the requested page is out of range",
+        ))?;
+    let limit = ARCHIVE_PAGE_LENGTH as i64;
+
+    Ok(sqlx::query!(
+        r#"WITH current_feed AS (
+                    SELECT MAX(generated_at) AS generated_at
+                      FROM feeds
+                     WHERE account_id = ?1
+                ),
+                archived AS (
+                    SELECT feed_items.link_id, MAX(feed_items.generated_at) AS generated_at
+                      FROM feed_items, current_feed
+                     WHERE feed_items.account_id = ?1
+                       AND feed_items.generated_at < current_feed.generated_at
+                       AND feed_items.link_id NOT IN (
+                               SELECT current_items.link_id
+                                 FROM feed_items AS current_items, current_feed
+                                WHERE current_items.account_id = ?1
+                                  AND current_items.generated_at = current_feed.generated_at
+                           )
+                  GROUP BY feed_items.link_id
+                )
+           SELECT archived.link_id as "link_id!",
+                  links.description as "description!",
+                  archived.generated_at as "generated_at!: i64",
+                  seen.rated as "rated?: bool"
+             FROM archived
+       INNER JOIN links ON links.link_id = archived.link_id
+       INNER JOIN feed_items ON feed_items.account_id = ?1
+                            AND feed_items.generated_at = archived.generated_at
+                            AND feed_items.link_id = archived.link_id
+        LEFT JOIN seen ON seen.account_id = ?1 AND seen.link_id = archived.link_id
+            WHERE archived.link_id NOT IN (SELECT link_id FROM hidden_links)
+         ORDER BY archived.generated_at DESC, feed_items.position
+            LIMIT ?2 OFFSET ?3"#,
+        account_id,
+        limit,
+        offset
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's past feeds",
+        )
+    })?
+    .into_iter()
+    .map(|link| ArchivedLink {
+        id: link.link_id,
+        description: link.description,
+        delivered: link.generated_at as u64,
+        visited: link.rated.is_some(),
+        rated: link.rated.unwrap_or(false),
+    })
+    .collect())
+}
+
+/// Read the feed an account had generated at the provided time
+async fn read_snapshot(
+    connection: &mut SqliteConnection,
+    account_id: &str,
+    generated_at: i64,
+) -> Result<model::Feed, (StatusCode, &'static str)> {
+    let items = sqlx::query!(
+        r#"SELECT position as "position!: i64", link_id as "link_id!", rating as "rating!: f64", deviation as "deviation!: f64", volatility as "volatility!: f64", segment as "segment?: i64" FROM feed_items WHERE account_id = ? AND generated_at = ? ORDER BY position"#,
+        account_id,
+        generated_at
+    )
+    .fetch_all(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the feed's links",
+        )
+    })?;
+
+    let mut tags: HashMap<i64, Vec<model::TagExplanation>> = HashMap::new();
+    for tag in sqlx::query!(
+        r#"SELECT feed_item_tags.position as "position!: i64",
+                  feed_item_tags.tag_id as "tag_id!",
+                  feed_item_tags.importance_rating as "importance_rating!: f64",
+                  feed_item_tags.importance_deviation as "importance_deviation!: f64",
+                  feed_item_tags.importance_volatility as "importance_volatility!: f64",
+                  feed_item_tags.score_rating as "score_rating!: f64",
+                  feed_item_tags.score_deviation as "score_deviation!: f64",
+                  feed_item_tags.score_volatility as "score_volatility!: f64"
+             FROM feed_item_tags
+       INNER JOIN tags ON feed_item_tags.tag_id = tags.tag_id
+            WHERE feed_item_tags.account_id = ?
+              AND feed_item_tags.generated_at = ?
+         ORDER BY feed_item_tags.position, tags.name"#,
+        account_id,
+        generated_at
+    )
+    .fetch_all(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the feed's explanations",
+        )
+    })? {
+        tags.entry(tag.position)
+            .or_default()
+            .push(model::TagExplanation {
+                tag_id: tag.tag_id,
+                importance: ScaledRatingData {
+                    rating: tag.importance_rating,
+                    deviation: tag.importance_deviation,
+                    volatility: tag.importance_volatility,
+                },
+                score: ScaledRatingData {
+                    rating: tag.score_rating,
+                    deviation: tag.score_deviation,
+                    volatility: tag.score_volatility,
+                },
+            });
+    }
+
+    // links picked before explanations were recorded have no segment, and a feed only has
+    // explanations if every one of its links does
+    let explained = items.iter().all(|item| item.segment.is_some());
+
+    let mut links = Vec::with_capacity(items.len());
+    let mut explanations = Vec::with_capacity(if explained { items.len() } else { 0 });
+    for item in items {
+        if explained && let Some(segment) = item.segment {
+            explanations.push(model::FeedExplanation {
+                segment: segment as usize,
+                tags: tags.remove(&item.position).unwrap_or_default(),
+            });
+        }
+
+        links.push((
+            item.link_id,
+            ScaledRatingData {
+                rating: item.rating,
+                deviation: item.deviation,
+                volatility: item.volatility,
+            },
+        ));
+    }
+
+    Ok(model::Feed {
+        refreshed: generated_at as u64,
+        links,
+        explanations,
+    })
+}
+
+/// Write a feed to the db, replacing any feed the account had generated at the same time
+pub async fn store_feed(
+    connection: &mut SqliteConnection,
+    account_id: &str,
+    feed: &model::Feed,
+) -> Result<(), (StatusCode, &'static str)> {
+    let generated_at = feed.refreshed as i64;
+
+    sqlx::query!(
+        "DELETE FROM feeds WHERE account_id = ? AND generated_at = ?",
+        account_id,
+        generated_at
+    )
+    .execute(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to remove a feed generated at the same time",
+        )
+    })?;
+
+    sqlx::query!(
+        "INSERT INTO feeds (account_id, generated_at) VALUES (?, ?)",
+        account_id,
+        generated_at
+    )
+    .execute(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to insert the feed into the db",
+        )
+    })?;
+
+    // feeds generated before explanations were recorded have none
+    let mut explanations = feed.explanations.iter();
+    for (position, (link_id, overall_score)) in feed.links.iter().enumerate() {
+        insert_item(
+            connection,
+            account_id,
+            generated_at,
+            position as i64,
+            link_id,
+            overall_score,
+            explanations.next(),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Make a newly generated feed an account's current one, forgetting any of its feeds which were
+/// generated longer ago than the configured retention
+pub async fn replace_feed(
+    algorithm_configuration: &AlgorithmConfiguration,
+    connection: &mut SqliteConnection,
+    account_id: &str,
+    feed: &model::Feed,
+) -> Result<(), (StatusCode, &'static str)> {
+    let mut transaction = connection.begin().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to begin a transaction",
+        )
+    })?;
+
+    store_feed(&mut transaction, account_id, feed).await?;
+
+    let cutoff = feed
+        .refreshed
+        .saturating_sub(algorithm_configuration.feed_retention.as_secs()) as i64;
+
+    sqlx::query!(
+        "DELETE FROM feeds WHERE account_id = ? AND generated_at < ?",
+        account_id,
+        cutoff
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to remove old feeds",
+        )
+    })?;
+
+    transaction.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to commit the new feed",
+        )
+    })
+}
+
+/// Append newly generated links to the end of the account's feed generated at the provided time
+pub async fn append_to_feed(
+    connection: &mut SqliteConnection,
+    account_id: &str,
+    generated_at: u64,
+    entries: &[(String, ScaledRatingData, model::FeedExplanation)],
+) -> Result<(), (StatusCode, &'static str)> {
+    let generated_at = generated_at as i64;
+
+    let mut transaction = connection.begin().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to begin a transaction",
+        )
+    })?;
+
+    // accounts which have never had a feed generated have no row for it yet
+    sqlx::query!(
+        "INSERT OR IGNORE INTO feeds (account_id, generated_at) VALUES (?, ?)",
+        account_id,
+        generated_at
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to insert the feed into the db",
+        )
+    })?;
+
+    let next_position = sqlx::query_scalar!(
+        r#"SELECT COALESCE(MAX(position) + 1, 0) as "next_position!: i64" FROM feed_items WHERE account_id = ? AND generated_at = ?"#,
+        account_id,
+        generated_at
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the end of the feed",
+        )
+    })?;
+
+    for (offset, (link_id, overall_score, explanation)) in entries.iter().enumerate() {
+        insert_item(
+            &mut transaction,
+            account_id,
+            generated_at,
+            next_position + offset as i64,
+            link_id,
+            overall_score,
+            Some(explanation),
+        )
+        .await?;
+    }
+
+    transaction.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to commit the appended links",
+        )
+    })
+}
+
+/// Insert a single link of a feed, along with the explanation of why it was picked
+async fn insert_item(
+    connection: &mut SqliteConnection,
+    account_id: &str,
+    generated_at: i64,
+    position: i64,
+    link_id: &str,
+    overall_score: &ScaledRatingData,
+    explanation: Option<&model::FeedExplanation>,
+) -> Result<(), (StatusCode, &'static str)> {
+    let segment = explanation.map(|explanation| explanation.segment as i64);
+
+    sqlx::query!(
+        "INSERT INTO feed_items (account_id, generated_at, position, link_id, rating, deviation, volatility, segment) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        account_id,
+        generated_at,
+        position,
+        link_id,
+        overall_score.rating,
+        overall_score.deviation,
+        overall_score.volatility,
+        segment
+    )
+    .execute(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to insert a link into the feed",
+        )
+    })?;
+
+    for tag in explanation.into_iter().flat_map(|explanation| &explanation.tags) {
+        sqlx::query!(
+            "INSERT INTO feed_item_tags (account_id, generated_at, position, tag_id, importance_rating, importance_deviation, importance_volatility, score_rating, score_deviation, score_volatility) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            account_id,
+            generated_at,
+            position,
+            tag.tag_id,
+            tag.importance.rating,
+            tag.importance.deviation,
+            tag.importance.volatility,
+            tag.score.rating,
+            tag.score.deviation,
+            tag.score.volatility
+        )
+        .execute(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to insert the explanation of a link in the feed",
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Determine whether a feed has outlived the refresh period
+pub fn is_stale(
+    algorithm_configuration: &AlgorithmConfiguration,
+    feed: &model::Feed,
+) -> Result<bool, (StatusCode, &'static str)> {
+    Ok((SystemTime::UNIX_EPOCH + Duration::from_secs(feed.refreshed))
+        .elapsed()
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to calculate the amount of time that has passed since the last time the feed was refreshed",
+            )
+        })?
+        > algorithm_configuration.feed_refresh_period)
+}
+
+/// Record that links were delivered in a newly refreshed feed, treating those which have been
+/// delivered in enough refreshes as seen if the configuration asks for it
+pub async fn record_deliveries<'a>(
+    algorithm_configuration: &AlgorithmConfiguration,
+    connection: &mut SqliteConnection,
+    account_id: &str,
+    link_ids: impl Iterator<Item = &'a str>,
+) -> Result<(), (StatusCode, &'static str)> {
+    let Some(delivered_as_seen_after) = algorithm_configuration.delivered_as_seen_after else {
+        return Ok(());
+    };
+
+    let delivered_as_seen_after = delivered_as_seen_after as i64;
+
+    for link_id in link_ids {
+        let refreshes = sqlx::query_scalar!(
+            r#"INSERT INTO deliveries (account_id, link_id, refreshes) VALUES (?, ?, 1) ON CONFLICT (account_id, link_id) DO UPDATE SET refreshes = refreshes + 1 RETURNING refreshes as "refreshes!: i64""#,
+            account_id,
+            link_id
+        )
+        .fetch_one(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to record a link's delivery",
+            )
+        })?;
+
+        if refreshes >= delivered_as_seen_after {
+            debug!(
+                "link {} delivered to {} in {} refreshes, treating it as seen",
+                link_id, account_id, refreshes
+            );
 
             sqlx::query!(
-                r"UPDATE scores SET score = ? WHERE id = ? AND tag_id = ?",
-                score,
+                "INSERT OR IGNORE INTO seen (account_id, link_id, rated) VALUES (?, ?, false)",
                 account_id,
-                tag
+                link_id
             )
             .execute(&mut *connection)
             .await
             .map_err(|_| {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to update a score",
+                    "unable to mark a link as seen",
                 )
             })?;
         }
+    }
+
+    Ok(())
+}
 
-        tag_sum += ScaledRatingWrapper(score.score).abs();
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use sqlx::{SqliteConnection, SqlitePool};
+    use std::{
+        collections::HashSet,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{
+        candidates, generate_feed, lock_feed, read_archive, read_feed, record_deliveries,
+        replace_feed, ARCHIVE_PAGE_LENGTH,
+    };
+    use crate::{configuration::Algorithm, locks::LockMap, model, schema, util::ScaledRatingData};
+
+    fn now() -> u64 {
+        SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs()
     }
 
-    let mut tag_importance = HashMap::with_capacity(tags.len());
-    for (tag, score) in tags {
-        let importance = (ScaledRatingWrapper(score.score).abs() / tag_sum).prune_nan();
-        debug!(
-            "inserting tag importance for {} with score {:?}: {:?}",
-            tag, score, importance
+    /// Give an account or link a score for a tag, creating the tag if it doesn't exist
+    async fn score(connection: &mut SqliteConnection, id: &str, tag: &str, rating: f64) {
+        sqlx::query("INSERT OR IGNORE INTO tags VALUES (?1, ?1)")
+            .bind(tag)
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO scores VALUES (?, ?, ?, 2, 0.06, ?)")
+            .bind(id)
+            .bind(tag)
+            .bind(rating)
+            .bind(now() as i64)
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+    }
+
+    async fn post(connection: &mut SqliteConnection, link_id: &str, tags: &[(&str, f64)]) {
+        sqlx::query("INSERT INTO links VALUES (?, 'https://example.com', ?)")
+            .bind(link_id)
+            .bind(link_id)
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+
+        for (tag, rating) in tags {
+            score(connection, link_id, tag, *rating).await;
+        }
+    }
+
+    /// Build a feed of the provided links, picked without any explanation
+    fn feed(refreshed: u64, link_ids: &[&str]) -> model::Feed {
+        model::Feed::new(
+            refreshed,
+            link_ids
+                .iter()
+                .map(|link_id| {
+                    (
+                        link_id.to_string(),
+                        ScaledRatingData {
+                            rating: 1500.0,
+                            deviation: 350.0,
+                            volatility: 0.06,
+                        },
+                        model::FeedExplanation {
+                            segment: 0,
+                            tags: Vec::new(),
+                        },
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Generate a feed for an account from all of its candidates
+    ///
+    /// The pool only has a single connection, which generating a feed takes for itself
+    async fn generate(
+        sqlite: &SqlitePool,
+        account_id: &str,
+    ) -> Vec<(String, ScaledRatingData, model::FeedExplanation)> {
+        let candidates = candidates(&mut sqlite.acquire().await.unwrap(), account_id)
+            .await
+            .unwrap();
+
+        generate_feed(
+            &Algorithm::default(),
+            sqlite.acquire().await.unwrap(),
+            account_id,
+            &candidates,
+            &HashSet::new(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn explanations_cover_the_tags_shared_with_the_account() {
+        let sqlite = schema::memory().await;
+        {
+            let mut connection = sqlite.acquire().await.unwrap();
+            sqlx::query("INSERT INTO accounts (account_id) VALUES ('account')")
+                .execute(&mut *connection)
+                .await
+                .unwrap();
+            score(&mut connection, "account", "cats", 2.0).await;
+            score(&mut connection, "account", "dogs", 1.0).await;
+
+            post(&mut connection, "best", &[("cats", 2.0), ("birds", 5.0)]).await;
+            post(&mut connection, "worst", &[("dogs", 1.0)]).await;
+        }
+
+        let mut entries = generate(&sqlite, "account").await;
+        entries.sort_unstable_by(|(left, _, _), (right, _, _)| left.cmp(right));
+
+        let explanations = entries
+            .iter()
+            .map(|(link_id, _, explanation)| {
+                (
+                    link_id.as_str(),
+                    explanation.segment,
+                    explanation
+                        .tags
+                        .iter()
+                        .map(|tag| (tag.tag_id.as_str(), tag.importance.rating))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // tags the account doesn't have (birds) say nothing about why a link was picked
+        assert_eq!(
+            explanations,
+            [
+                ("best", 0, vec![("cats", 2.0 / 3.0)]),
+                ("worst", 1, vec![("dogs", 1.0 / 3.0)])
+            ]
+        );
+
+        // and they're kept alongside the feed
+        let mut connection = sqlite.acquire().await.unwrap();
+        let feed = model::Feed::new(now(), entries);
+        replace_feed(&Algorithm::default(), &mut connection, "account", &feed)
+            .await
+            .unwrap();
+
+        let stored = read_feed(&mut connection, "account")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.links, feed.links);
+        assert_eq!(
+            stored
+                .explanations
+                .iter()
+                .map(|explanation| (explanation.segment, explanation.tags.len()))
+                .collect::<Vec<_>>(),
+            feed.explanations
+                .iter()
+                .map(|explanation| (explanation.segment, explanation.tags.len()))
+                .collect::<Vec<_>>()
         );
-        tag_importance.insert(tag, importance);
     }
 
-    let mut candidate_scores = Vec::new();
-    for candidate in candidates {
-        //TODO(superwhiskers): perhaps we should factor this out into a function, given that
-        //                     we do this twice (and will probably do it more times)
-        //TODO(superwhiskers): the same note about using fetch over fetch_all applies here
-        //                     as well
-        let candidate_tags = sqlx::query!(
-            r#"SELECT tag_id as "tag_id!", score as "score!" FROM scores WHERE id = ?"#,
-            candidate,
+    #[tokio::test]
+    async fn links_delivered_often_enough_are_treated_as_seen() {
+        let sqlite = schema::memory().await;
+        let mut connection = sqlite.acquire().await.unwrap();
+
+        score(&mut connection, "account", "cats", 1.0).await;
+        post(&mut connection, "first", &[("cats", 1.0)]).await;
+        post(&mut connection, "second", &[("cats", 1.0)]).await;
+
+        let algorithm_configuration = Algorithm {
+            delivered_as_seen_after: Some(2),
+            ..Algorithm::default()
+        };
+
+        record_deliveries(
+            &algorithm_configuration,
+            &mut connection,
+            "account",
+            ["first", "second"].into_iter(),
+        )
+        .await
+        .unwrap();
+        record_deliveries(
+            &algorithm_configuration,
+            &mut connection,
+            "account",
+            ["first"].into_iter(),
+        )
+        .await
+        .unwrap();
+
+        // deliveries don't count as ratings
+        let seen = sqlx::query_as::<_, (String, bool)>(
+            "SELECT link_id, rated FROM seen WHERE account_id = 'account'",
         )
         .fetch_all(&mut *connection)
         .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to query a candidate link's tags from the db",
+        .unwrap();
+        assert_eq!(seen, [("first".to_string(), false)]);
+
+        assert_eq!(
+            candidates(&mut connection, "account").await.unwrap(),
+            HashSet::from(["second".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_only_recorded_if_configured() {
+        let sqlite = schema::memory().await;
+        let mut connection = sqlite.acquire().await.unwrap();
+
+        for _ in 0..2 {
+            record_deliveries(
+                &Algorithm::default(),
+                &mut connection,
+                "account",
+                ["link"].into_iter(),
             )
-        })?
-        .into_iter()
-        .map(|tag| {
-            rmp_serde::from_slice(&tag.score)
-                .map(|score| (tag.tag_id, score))
-                .map_err(|_| {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "unable to deserialize the score data for a tag",
-                    )
-                })
-        })
-        .collect::<Result<Vec<(String, model::Score)>, _>>()?;
+            .await
+            .unwrap();
+        }
+
+        let deliveries = sqlx::query_scalar::<_, i64>("SELECT COUNT(1) FROM deliveries")
+            .fetch_one(&mut *connection)
+            .await
+            .unwrap();
+        assert_eq!(deliveries, 0);
+    }
+
+    #[tokio::test]
+    async fn locked_feeds_hold_the_account_and_its_candidates() {
+        let sqlite = schema::memory().await;
+        let mut connection = sqlite.acquire().await.unwrap();
 
-        let mut scaled_avg = ScaledRatingData {
-            rating: 0.0,
-            deviation: 0.0,
-            volatility: 0.0,
+        score(&mut connection, "account", "cats", 1.0).await;
+        post(&mut connection, "cat", &[("cats", 1.0)]).await;
+        post(&mut connection, "dog", &[("dogs", 1.0)]).await;
+
+        let lock_map = LockMap::new(Duration::from_millis(20));
+        let (guards, locked) = lock_feed(lock_map, &mut connection, "account")
+            .await
+            .unwrap();
+        assert_eq!(locked, HashSet::from(["cat".to_string()]));
+
+        // links which can't be picked for the feed are left alone
+        assert!(lock_map.lock("account").is_none());
+        assert!(lock_map.lock("cat").is_none());
+        assert!(lock_map.lock("dog").is_some());
+
+        assert_eq!(
+            lock_feed(lock_map, &mut connection, "account")
+                .await
+                .err()
+                .map(|(status, _)| status),
+            Some(StatusCode::SERVICE_UNAVAILABLE)
+        );
+
+        drop(guards);
+        assert!(
+            lock_feed(lock_map, &mut connection, "account")
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn replaced_feeds_are_kept_for_the_retention_period() {
+        let sqlite = schema::memory().await;
+        let mut connection = sqlite.acquire().await.unwrap();
+
+        let algorithm_configuration = Algorithm {
+            feed_retention: Duration::from_secs(100),
+            ..Algorithm::default()
         };
-        let mut overlap = 0.0; // there will always be overlap.
-        for (tag, mut score) in candidate_tags {
-            if let Some(percentage) = tag_importance.get(&tag) {
-                if util::decay_score(algorithm_configuration, &mut score, 12)? {
-                    let score = rmp_serde::to_vec(&score).map_err(|_| {
-                        (
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            "unable to convert data to messagepack",
-                        )
-                    })?;
-
-                    sqlx::query!(
-                        r"UPDATE scores SET score = ? WHERE id = ? AND tag_id = ?",
-                        score,
-                        candidate,
-                        tag
-                    )
-                    .execute(&mut *connection)
-                    .await
-                    .map_err(|_| {
-                        (
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            "unable to update a score",
-                        )
-                    })?;
-                }
-
-                scaled_avg += *percentage * ScaledRatingWrapper(score.score);
-                overlap += 1.0;
-            }
-        }
 
-        scaled_avg /= overlap;
-        if scaled_avg.rating.is_nan()
-            || scaled_avg.deviation.is_nan()
-            || scaled_avg.volatility.is_nan()
-        {
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "invariant violation lmao (nan)",
-            ));
+        for (refreshed, link_id) in [(1000, "oldest"), (1050, "older"), (1200, "current")] {
+            replace_feed(
+                &algorithm_configuration,
+                &mut connection,
+                "account",
+                &feed(refreshed, &[link_id]),
+            )
+            .await
+            .unwrap();
         }
-        candidate_scores.push((candidate, scaled_avg));
-    }
+        replace_feed(
+            &algorithm_configuration,
+            &mut connection,
+            "other",
+            &feed(1000, &["other"]),
+        )
+        .await
+        .unwrap();
 
-    candidate_scores.sort_unstable_by(|(_, ref left), (_, ref right)| {
-        left.partial_cmp(right)
-            .expect("invariant violation lmao (nan)")
-    });
-
-    let mut rng = pcg_thread_rng();
-    let mut feed = Vec::with_capacity(10);
-    let segment_length = candidate_scores.len().div_ceil(4);
-    for (i, segment) in candidate_scores.rchunks_mut(segment_length).enumerate() {
-        feed.extend(
-            segment
-                .choose_multiple(&mut rng, 4 - i)
-                .map(|(id, overall_score)| (id.to_string(), *overall_score)),
+        let feeds = sqlx::query_as::<_, (String, i64)>(
+            "SELECT account_id, generated_at FROM feeds ORDER BY account_id, generated_at",
+        )
+        .fetch_all(&mut *connection)
+        .await
+        .unwrap();
+        assert_eq!(
+            feeds,
+            [("account".to_string(), 1200), ("other".to_string(), 1000)]
         );
+
+        // the links of removed feeds go with them
+        let items = sqlx::query_scalar::<_, String>(
+            "SELECT link_id FROM feed_items WHERE account_id = 'account'",
+        )
+        .fetch_all(&mut *connection)
+        .await
+        .unwrap();
+        assert_eq!(items, ["current"]);
+
+        // while feeds within the retention period are kept
+        replace_feed(
+            &algorithm_configuration,
+            &mut connection,
+            "account",
+            &feed(1300, &["newest"]),
+        )
+        .await
+        .unwrap();
+
+        let feeds = sqlx::query_scalar::<_, i64>(
+            "SELECT generated_at FROM feeds WHERE account_id = 'account' ORDER BY generated_at",
+        )
+        .fetch_all(&mut *connection)
+        .await
+        .unwrap();
+        assert_eq!(feeds, [1200, 1300]);
     }
 
-    feed.shuffle(&mut rng);
+    #[tokio::test]
+    async fn archives_list_each_past_link_once_by_its_latest_delivery() {
+        let sqlite = schema::memory().await;
+        let mut connection = sqlite.acquire().await.unwrap();
 
-    Ok(feed)
+        let link_ids = (0..=30)
+            .map(|link| format!("link-{:02}", link))
+            .collect::<Vec<_>>();
+        for link_id in &link_ids {
+            post(&mut connection, link_id, &[]).await;
+        }
+
+        for (refreshed, link_ids) in [
+            (1000, link_ids[..30].iter().map(String::as_str).collect()),
+            (2000, vec!["link-00", "link-30"]),
+            (3000, vec!["link-29"]),
+        ] {
+            replace_feed(
+                &Algorithm::default(),
+                &mut connection,
+                "account",
+                &feed(refreshed, &link_ids),
+            )
+            .await
+            .unwrap();
+        }
+
+        sqlx::query(
+            "INSERT INTO hidden_links VALUES ('link-28');
+             INSERT INTO seen VALUES ('account', 'link-00', true), ('account', 'link-01', false);",
+        )
+        .execute(&mut *connection)
+        .await
+        .unwrap();
+
+        let first = read_archive(&mut connection, "account", 0).await.unwrap();
+        let second = read_archive(&mut connection, "account", 1).await.unwrap();
+        assert_eq!(first.len(), ARCHIVE_PAGE_LENGTH);
+        assert_eq!(second.len(), 4);
+        assert!(
+            read_archive(&mut connection, "account", 2)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            read_archive(&mut connection, "account", usize::MAX)
+                .await
+                .is_err()
+        );
+
+        // newest deliveries first, leaving out the current feed and hidden links
+        let archived = first
+            .iter()
+            .chain(&second)
+            .map(|link| (link.id.as_str(), link.delivered))
+            .collect::<Vec<_>>();
+        let mut expected = vec![("link-00", 2000), ("link-30", 2000)];
+        expected.extend(
+            link_ids[1..28]
+                .iter()
+                .map(|link_id| (link_id.as_str(), 1000)),
+        );
+        assert_eq!(archived, expected);
+
+        assert!(first[0].visited && first[0].rated);
+        assert!(!first[1].visited && !first[1].rated);
+        assert!(first[2].visited && !first[2].rated);
+    }
 }
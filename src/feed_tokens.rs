@@ -0,0 +1,291 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use anyhow::Context;
+use axum::http::StatusCode;
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+use sqlx::{pool::PoolConnection, Sqlite, SqlitePool};
+use std::time::SystemTime;
+use tracing::debug;
+
+use crate::model;
+
+/// The maximum number of feed tokens a single account may hold at once
+pub const MAX_FEED_TOKENS: i64 = 16;
+
+/// A feed token belonging to an account, as shown on the profile page
+#[derive(Debug)]
+pub struct FeedToken {
+    pub token: String,
+
+    /// Seconds since the unix epoch at which the token was created
+    pub created: i64,
+}
+
+/// The key used to sign urls that act on behalf of the account a feed token belongs to
+///
+/// The key is generated once and kept in the db, so signed urls handed out in feeds remain valid
+/// across restarts
+pub struct SigningKey(Vec<u8>);
+
+impl SigningKey {
+    pub async fn load(sqlite: &SqlitePool) -> anyhow::Result<&'static Self> {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        let secret = secret.as_slice();
+
+        // only the first instance to start will have its secret stored
+        sqlx::query!(
+            "INSERT INTO secrets (name, secret) VALUES ('feed-signing-key', ?) ON CONFLICT (name) DO NOTHING",
+            secret
+        )
+        .execute(sqlite)
+        .await
+        .context("unable to store the feed signing key")?;
+
+        let secret = sqlx::query_scalar!(
+            r#"SELECT secret as "secret!" FROM secrets WHERE name = 'feed-signing-key'"#
+        )
+        .fetch_one(sqlite)
+        .await
+        .context("unable to load the feed signing key")?;
+
+        Ok(Box::leak(Box::new(Self(secret))))
+    }
+
+    fn mac(&self, token: &str, link_id: &str, rating: model::Rating) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("hmac accepts keys of any length");
+
+        // nul bytes can't appear in any of these, so they unambiguously separate the fields
+        mac.update(token.as_bytes());
+        mac.update(b"\0");
+        mac.update(link_id.as_bytes());
+        mac.update(b"\0");
+        mac.update(rating.as_str().as_bytes());
+
+        mac
+    }
+
+    /// Sign a url rating a link on behalf of the account a feed token belongs to
+    pub fn sign_rating(&self, token: &str, link_id: &str, rating: model::Rating) -> String {
+        hex::encode(self.mac(token, link_id, rating).finalize().into_bytes())
+    }
+
+    /// Check the signature of a rating url in constant time
+    pub fn verify_rating(
+        &self,
+        token: &str,
+        link_id: &str,
+        rating: model::Rating,
+        signature: &str,
+    ) -> bool {
+        hex::decode(signature).is_ok_and(|signature| {
+            self.mac(token, link_id, rating)
+                .verify_slice(&signature)
+                .is_ok()
+        })
+    }
+}
+
+/// Generate a new feed token
+///
+/// Unlike account ids, feed tokens are drawn from the operating system's random number
+/// generator, as they are intended to be handed out to third parties and must not be guessable
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+
+    hex::encode(bytes)
+}
+
+/// Look up the account a feed token grants access to, if the token exists
+pub async fn account_from_token(
+    connection: &mut PoolConnection<Sqlite>,
+    token: &str,
+) -> Result<Option<String>, (StatusCode, &'static str)> {
+    sqlx::query_scalar!(
+        r#"SELECT account_id as "account_id!" FROM feed_tokens WHERE token = ?"#,
+        token
+    )
+    .fetch_optional(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the db for the feed token",
+        )
+    })
+}
+
+/// Retrieve all of an account's feed tokens, newest first
+pub async fn account_tokens(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+) -> Result<Vec<FeedToken>, (StatusCode, &'static str)> {
+    Ok(sqlx::query!(
+        r#"SELECT token as "token!", created as "created!: i64" FROM feed_tokens WHERE account_id = ? ORDER BY created DESC, token"#,
+        account_id
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's feed tokens",
+        )
+    })?
+    .into_iter()
+    .map(|token| FeedToken {
+        token: token.token,
+        created: token.created,
+    })
+    .collect())
+}
+
+/// Create a new feed token for an account, failing if the account already holds the maximum
+/// number of tokens
+pub async fn create_token(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+) -> Result<String, (StatusCode, &'static str)> {
+    let token = generate_token();
+    let created = SystemTime::UNIX_EPOCH
+        .elapsed()
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to calculate the amount of time that has passed since the unix epoch",
+            )
+        })?
+        .as_secs() as i64;
+
+    // the limit is checked by the insert itself, so concurrent requests can't both slip under it
+    if sqlx::query!(
+        r"INSERT INTO feed_tokens (token, account_id, created)
+               SELECT ?1, ?2, ?3
+                WHERE (SELECT COUNT(1) FROM feed_tokens WHERE account_id = ?2) < ?4",
+        token,
+        account_id,
+        created,
+        MAX_FEED_TOKENS
+    )
+    .execute(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to insert the feed token into the db",
+        )
+    })?
+    .rows_affected()
+        == 0
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "you have too many feed tokens. revoke one before creating another",
+        ));
+    }
+
+    debug!("created a new feed token for account {}", account_id);
+
+    Ok(token)
+}
+
+/// Revoke one of an account's feed tokens, returning whether a token was actually revoked
+pub async fn revoke_token(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+    token: &str,
+) -> Result<bool, (StatusCode, &'static str)> {
+    let revoked = sqlx::query!(
+        "DELETE FROM feed_tokens WHERE token = ? AND account_id = ?",
+        token,
+        account_id
+    )
+    .execute(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to delete the feed token from the db",
+        )
+    })?
+    .rows_affected()
+        > 0;
+
+    if revoked {
+        debug!("revoked a feed token for account {}", account_id);
+    }
+
+    Ok(revoked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{account_tokens, create_token, revoke_token, SigningKey, MAX_FEED_TOKENS};
+    use crate::{model::Rating, schema};
+
+    #[test]
+    fn rating_signatures_only_cover_what_was_signed() {
+        let key = SigningKey(b"key".to_vec());
+        let signature = key.sign_rating("token", "link", Rating::Promote);
+
+        assert!(key.verify_rating("token", "link", Rating::Promote, &signature));
+        assert!(!key.verify_rating("other", "link", Rating::Promote, &signature));
+        assert!(!key.verify_rating("token", "other", Rating::Promote, &signature));
+        assert!(!key.verify_rating("token", "link", Rating::Demote, &signature));
+        assert!(!key.verify_rating("token", "link", Rating::Promote, "not hex"));
+        assert!(!key.verify_rating("token", "link", Rating::Promote, &signature[2..]));
+
+        // the fields are separated, so they can't be shifted into one another
+        let shifted = key.sign_rating("tokenl", "ink", Rating::Promote);
+        assert!(!key.verify_rating("token", "link", Rating::Promote, &shifted));
+
+        let other_key = SigningKey(b"other key".to_vec());
+        assert!(!other_key.verify_rating("token", "link", Rating::Promote, &signature));
+    }
+
+    #[tokio::test]
+    async fn tokens_are_limited_per_account() {
+        let sqlite = schema::memory().await;
+        let mut connection = sqlite.acquire().await.unwrap();
+
+        let mut tokens = Vec::new();
+        for _ in 0..MAX_FEED_TOKENS {
+            tokens.push(create_token(&mut connection, "account").await.unwrap());
+        }
+
+        assert!(create_token(&mut connection, "account").await.is_err());
+        assert!(create_token(&mut connection, "other").await.is_ok());
+
+        assert!(revoke_token(&mut connection, "account", &tokens[0])
+            .await
+            .unwrap());
+        assert!(create_token(&mut connection, "account").await.is_ok());
+        assert_eq!(
+            account_tokens(&mut connection, "account")
+                .await
+                .unwrap()
+                .len() as i64,
+            MAX_FEED_TOKENS
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use axum::http::StatusCode;
+use parking_lot::RwLock;
+use sqlx::{pool::PoolConnection, Sqlite, SqlitePool};
+use std::time::{Duration, SystemTime};
+use tokio::time;
+use tracing::{debug, trace, warn};
+use ulid::Ulid;
+
+use crate::{configuration::Algorithm as AlgorithmConfiguration, templates};
+
+/// The gravity applied to the age of a link when ranking the hot links, as done by hacker
+/// news
+const GRAVITY: f64 = 1.8;
+
+/// How far back to look for hot links
+const HOT_WINDOW: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+/// A cache of the links shown on the front page
+pub struct FrontPage(RwLock<templates::FrontPage>);
+
+impl FrontPage {
+    pub fn new() -> &'static Self {
+        Box::leak(Box::new(Self(RwLock::new(templates::FrontPage::default()))))
+    }
+
+    /// Retrieve a copy of the currently cached front page
+    pub fn get(&self) -> templates::FrontPage {
+        self.0.read().clone()
+    }
+
+    /// Regenerate the front page, replacing the cached one
+    pub async fn refresh(
+        &self,
+        algorithm_configuration: &AlgorithmConfiguration,
+        connection: &mut PoolConnection<Sqlite>,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        let front_page = generate_front_page(algorithm_configuration, connection).await?;
+
+        *self.0.write() = front_page;
+
+        Ok(())
+    }
+
+    /// Remove a link from the cached front page, so that a link hidden by a moderator doesn't
+    /// linger on it until the next refresh
+    pub fn remove_link(&self, link_id: &str) {
+        let mut front_page = self.0.write();
+
+        front_page.hot.retain(|link| link.id != link_id);
+        front_page.new.retain(|link| link.id != link_id);
+    }
+}
+
+/// Periodically refresh the provided front page cache, forever
+pub async fn refresh_periodically(
+    front_page: &'static FrontPage,
+    sqlite: SqlitePool,
+    algorithm_configuration: AlgorithmConfiguration,
+) {
+    let mut interval = time::interval(algorithm_configuration.front_page_refresh_period);
+
+    loop {
+        interval.tick().await;
+
+        trace!("refreshing the front page");
+
+        let mut connection = match sqlite.acquire().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!(
+                    "unable to acquire a db connection to refresh the front page: {}",
+                    e
+                );
+                continue;
+            }
+        };
+
+        if let Err((_, message)) = front_page
+            .refresh(&algorithm_configuration, &mut connection)
+            .await
+        {
+            warn!("unable to refresh the front page: {}", message);
+        }
+    }
+}
+
+/// Generate the front page, consisting of the hot links (ranked by their average tag score
+/// and decayed by age) and the newest links
+pub async fn generate_front_page(
+    algorithm_configuration: &AlgorithmConfiguration,
+    connection: &mut PoolConnection<Sqlite>,
+) -> Result<templates::FrontPage, (StatusCode, &'static str)> {
+    let length = algorithm_configuration.front_page_length as i64;
+
+    let new = sqlx::query!(
+        r#"SELECT link_id as "link_id!", description as "description!" FROM links WHERE link_id NOT IN (SELECT link_id FROM hidden_links) ORDER BY link_id DESC LIMIT ?"#,
+        length
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the newest links",
+        )
+    })?
+    .into_iter()
+    .map(|link| templates::FrontPageLink {
+        id: link.link_id,
+        description: link.description,
+    })
+    .collect();
+
+    let now = SystemTime::UNIX_EPOCH
+        .elapsed()
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to calculate the amount of time that has passed since the unix epoch",
+            )
+        })?
+        .as_millis() as u64;
+
+    // ulids sort by the time they were generated, so this selects every link posted after
+    // the cutoff
+    let cutoff = Ulid::from_parts(now.saturating_sub(HOT_WINDOW.as_millis() as u64), 0).to_string();
+
+    // ratings with results pending for them are used as they are, without incorporating
+    // those results, which happens the next time the link is rated or enters a feed
+    let candidates = sqlx::query!(
+        r#"SELECT links.link_id as "link_id!",
+                  links.description as "description!",
+                  AVG(scores.rating) as "rating?: f64"
+             FROM links
+        LEFT JOIN scores ON scores.id = links.link_id
+            WHERE links.link_id > ?
+              AND links.link_id NOT IN (SELECT link_id FROM hidden_links)
+         GROUP BY links.link_id"#,
+        cutoff
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the recent links",
+        )
+    })?;
+
+    let mut hot = Vec::with_capacity(candidates.len());
+    for candidate in candidates {
+        let rating = candidate.rating.unwrap_or(0.0);
+
+        let age = now.saturating_sub(
+            Ulid::from_string(&candidate.link_id)
+                .map_err(|_| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "unable to parse a link id",
+                    )
+                })?
+                .timestamp_ms(),
+        ) as f64
+            / (1000.0 * 60.0 * 60.0);
+
+        // scaled ratings are centered around zero, so they're exponentiated to keep the
+        // numerator positive
+        hot.push((
+            rating.exp() / (age + 2.0).powf(GRAVITY),
+            templates::FrontPageLink {
+                id: candidate.link_id,
+                description: candidate.description,
+            },
+        ));
+    }
+
+    hot.sort_unstable_by(|(left, _), (right, _)| {
+        right
+            .partial_cmp(left)
+            .expect("invariant violation lmao (nan)")
+    });
+    hot.truncate(algorithm_configuration.front_page_length);
+
+    debug!("generated a front page with {} hot links", hot.len());
+
+    Ok(templates::FrontPage {
+        hot: hot.into_iter().map(|(_, link)| link).collect(),
+        new,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::SqliteConnection;
+    use std::time::{Duration, SystemTime};
+    use ulid::Ulid;
+
+    use super::{generate_front_page, FrontPage};
+    use crate::{configuration::Algorithm, schema};
+
+    /// Post a link the provided amount of time ago, with the provided tag score
+    async fn post(connection: &mut SqliteConnection, age: Duration, rating: Option<f64>) -> String {
+        let posted = SystemTime::UNIX_EPOCH.elapsed().unwrap() - age;
+        let link_id = Ulid::from_parts(posted.as_millis() as u64, 0).to_string();
+
+        sqlx::query("INSERT INTO links VALUES (?, 'https://example.com', ?)")
+            .bind(&link_id)
+            .bind(&link_id)
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+
+        if let Some(rating) = rating {
+            sqlx::query("INSERT INTO scores VALUES (?, 'tag', ?, 2, 0.06, ?)")
+                .bind(&link_id)
+                .bind(rating)
+                .bind(posted.as_secs() as i64)
+                .execute(&mut *connection)
+                .await
+                .unwrap();
+        }
+
+        link_id
+    }
+
+    #[tokio::test]
+    async fn hot_links_are_ranked_by_score_and_age() {
+        let mut connection = schema::memory().await.acquire().await.unwrap();
+        let hour = Duration::from_secs(60 * 60);
+
+        let old_good = post(&mut connection, hour * 48, Some(2.0)).await;
+        let second = Duration::from_secs(1);
+        let new_bad = post(&mut connection, hour + second * 3, Some(-2.0)).await;
+        let new_unrated = post(&mut connection, hour + second * 2, None).await;
+        let new_good = post(&mut connection, hour + second, Some(2.0)).await;
+        let expired = post(&mut connection, hour * 24 * 8, Some(5.0)).await;
+        let hidden = post(&mut connection, hour, Some(3.0)).await;
+
+        sqlx::query("INSERT INTO hidden_links VALUES (?)")
+            .bind(&hidden)
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+
+        let front_page = generate_front_page(&Algorithm::default(), &mut connection)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            front_page
+                .hot
+                .into_iter()
+                .map(|link| link.id)
+                .collect::<Vec<_>>(),
+            [
+                new_good.clone(),
+                new_unrated.clone(),
+                new_bad.clone(),
+                old_good.clone()
+            ]
+        );
+        assert_eq!(
+            front_page
+                .new
+                .into_iter()
+                .map(|link| link.id)
+                .collect::<Vec<_>>(),
+            [new_good, new_unrated, new_bad, old_good, expired]
+        );
+    }
+
+    #[tokio::test]
+    async fn removed_links_leave_the_cached_front_page() {
+        let mut connection = schema::memory().await.acquire().await.unwrap();
+        let hour = Duration::from_secs(60 * 60);
+
+        let kept = post(&mut connection, hour * 2, Some(0.0)).await;
+        let removed = post(&mut connection, hour, Some(0.0)).await;
+
+        let front_page = FrontPage::new();
+        front_page
+            .refresh(&Algorithm::default(), &mut connection)
+            .await
+            .unwrap();
+        front_page.remove_link(&removed);
+
+        let front_page = front_page.get();
+        assert_eq!(
+            front_page
+                .hot
+                .into_iter()
+                .map(|link| link.id)
+                .collect::<Vec<_>>(),
+            [kept.as_str()]
+        );
+        assert_eq!(
+            front_page
+                .new
+                .into_iter()
+                .map(|link| link.id)
+                .collect::<Vec<_>>(),
+            [kept.as_str()]
+        );
+    }
+}
@@ -0,0 +1,563 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use axum::http::StatusCode;
+use instant_glicko_2::ScaledRating;
+use regex::Regex;
+use serde_json::Value;
+use sqlx::{pool::PoolConnection, Connection, Sqlite, SqliteConnection};
+use std::{collections::HashMap, sync::LazyLock, time::SystemTime};
+use tracing::{debug, trace};
+
+use crate::{
+    routes::{self, TAG_REGEX},
+    scores, search,
+};
+
+/// The maximum number of links accepted from a single import
+pub const MAX_IMPORTED_LINKS: usize = 1000;
+
+/// The maximum number of tags an import may seed scores for, taken in order of frequency
+pub const MAX_SEEDED_TAGS: usize = 50;
+
+/// The maximum number of tags kept for any one imported link
+pub const MAX_LINK_TAGS: usize = 16;
+
+static NETSCAPE_LINK_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<a\s([^>]*)>(.*?)</a>").expect("unable to compile a regex"));
+
+static OPML_OUTLINE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<outline\s([^>]*?)/?>").expect("unable to compile a regex"));
+
+static ATTRIBUTE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)([a-z_:\-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+        .expect("unable to compile a regex")
+});
+
+static MARKUP_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<[^>]*>").expect("unable to compile a regex"));
+
+static TAG_SEPARATOR_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[\s_]+").expect("unable to compile a regex"));
+
+/// A link read from an import, prior to being posted
+#[derive(Debug)]
+pub struct ImportedLink {
+    pub url: String,
+    pub title: String,
+    pub tags: Vec<String>,
+}
+
+/// What came of an import
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    /// The number of links posted
+    pub posted: usize,
+
+    /// The number of links which were already present
+    pub existing: usize,
+
+    /// The number of tags the account had no score for that were seeded
+    pub seeded_tags: usize,
+}
+
+/// Parse an export from another service, guessing its format from its contents
+///
+/// Netscape bookmark files (as exported by most browsers), Pinboard and Shiori json exports,
+/// and opml subscription lists are understood
+pub fn parse(contents: &str) -> Result<Vec<ImportedLink>, (StatusCode, &'static str)> {
+    let trimmed = contents.trim_start();
+
+    let mut links = if trimmed.starts_with('[') {
+        trace!("parsing an import as json");
+
+        parse_json(trimmed)?
+    } else if trimmed.contains("<opml") {
+        trace!("parsing an import as opml");
+
+        parse_opml(trimmed)
+    } else if trimmed.contains("NETSCAPE-Bookmark-file") || trimmed.contains("<DT>") {
+        trace!("parsing an import as a netscape bookmark file");
+
+        parse_netscape(trimmed)
+    } else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "the format of the import was not recognized",
+        ));
+    };
+
+    // browsers export all sorts of internal urls (e.g. `place:` queries) that aren't useful
+    links.retain(|link| link.url.starts_with("http://") || link.url.starts_with("https://"));
+
+    if links.len() > MAX_IMPORTED_LINKS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "the import contains too many links",
+        ));
+    }
+
+    Ok(links)
+}
+
+fn parse_json(contents: &str) -> Result<Vec<ImportedLink>, (StatusCode, &'static str)> {
+    let Value::Array(entries) = serde_json::from_str(contents)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "the import is not valid json"))?
+    else {
+        return Err((StatusCode::BAD_REQUEST, "the import is not a json array"));
+    };
+
+    Ok(entries
+        .iter()
+        .filter_map(|entry| {
+            // pinboard uses `href` and `description`, while shiori uses `url` and `title`
+            let url = entry
+                .get("href")
+                .or_else(|| entry.get("url"))
+                .and_then(Value::as_str)?;
+            let title = entry
+                .get("description")
+                .or_else(|| entry.get("title"))
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+
+            // pinboard delimits tags with spaces, while shiori gives a list of objects
+            let tags = match entry.get("tags") {
+                Some(Value::String(tags)) => {
+                    tags.split_whitespace().filter_map(normalize_tag).collect()
+                }
+                Some(Value::Array(tags)) => tags
+                    .iter()
+                    .filter_map(|tag| tag.get("name").or(Some(tag)).and_then(Value::as_str))
+                    .filter_map(normalize_tag)
+                    .collect(),
+                _ => Vec::new(),
+            };
+
+            Some(imported_link(url.to_string(), title.to_string(), tags))
+        })
+        .collect())
+}
+
+fn parse_opml(contents: &str) -> Vec<ImportedLink> {
+    OPML_OUTLINE_REGEX
+        .captures_iter(contents)
+        .filter_map(|outline| {
+            let attributes = attributes(&outline[1]);
+
+            // subscriptions are better represented by the site than by the feed itself
+            let url = attributes
+                .get("htmlurl")
+                .or_else(|| attributes.get("xmlurl"))?
+                .clone();
+            let title = attributes
+                .get("title")
+                .or_else(|| attributes.get("text"))
+                .cloned()
+                .unwrap_or_default();
+            let tags = attributes
+                .get("category")
+                .map(|categories| {
+                    categories
+                        .split([',', '/'])
+                        .filter_map(normalize_tag)
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            Some(imported_link(url, title, tags))
+        })
+        .collect()
+}
+
+fn parse_netscape(contents: &str) -> Vec<ImportedLink> {
+    NETSCAPE_LINK_REGEX
+        .captures_iter(contents)
+        .filter_map(|link| {
+            let attributes = attributes(&link[1]);
+
+            let url = attributes.get("href")?.clone();
+            let title = unescape(MARKUP_REGEX.replace_all(&link[2], "").trim());
+            let tags = attributes
+                .get("tags")
+                .map(|tags| tags.split(',').filter_map(normalize_tag).collect())
+                .unwrap_or_default();
+
+            Some(imported_link(url, title, tags))
+        })
+        .collect()
+}
+
+/// Collect the attributes of an html or xml element, with their names lowercased
+fn attributes(element: &str) -> HashMap<String, String> {
+    ATTRIBUTE_REGEX
+        .captures_iter(element)
+        .map(|attribute| {
+            (
+                attribute[1].to_ascii_lowercase(),
+                unescape(
+                    attribute
+                        .get(2)
+                        .or_else(|| attribute.get(3))
+                        .map(|value| value.as_str())
+                        .unwrap_or_default(),
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Replace the handful of entities that show up in exported attributes and titles
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Convert a tag from another service into a flock tag, if possible
+fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = TAG_SEPARATOR_REGEX
+        .replace_all(tag.trim(), "-")
+        .to_ascii_lowercase();
+
+    TAG_REGEX.is_match(&tag).then_some(tag)
+}
+
+fn imported_link(url: String, title: String, mut tags: Vec<String>) -> ImportedLink {
+    tags.sort_unstable();
+    tags.dedup();
+    tags.truncate(MAX_LINK_TAGS);
+
+    ImportedLink {
+        title: if title.is_empty() { url.clone() } else { title },
+        url,
+        tags,
+    }
+}
+
+/// Post the imported links which aren't already present and seed the account's tag scores
+/// according to how often each tag appears in the import
+///
+/// The imported links are marked as seen for the account, as there's no point in recommending
+/// someone their own bookmarks
+pub async fn import(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+    links: Vec<ImportedLink>,
+) -> Result<ImportSummary, (StatusCode, &'static str)> {
+    // an import which fails partway through leaves nothing behind, so it can simply be retried
+    let mut transaction = connection.begin().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to begin a transaction",
+        )
+    })?;
+
+    let summary = import_links(&mut transaction, account_id, links).await?;
+
+    transaction.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to commit the import",
+        )
+    })?;
+
+    Ok(summary)
+}
+
+/// Post the imported links which haven't been already and seed the account's tag scores,
+/// as described by [`import`]
+async fn import_links(
+    connection: &mut SqliteConnection,
+    account_id: &str,
+    links: Vec<ImportedLink>,
+) -> Result<ImportSummary, (StatusCode, &'static str)> {
+    let mut summary = ImportSummary::default();
+    let mut frequencies = HashMap::<String, usize>::new();
+
+    for link in links {
+        for tag in &link.tags {
+            *frequencies.entry(tag.clone()).or_default() += 1;
+        }
+
+        let link_id = if let Some(link_id) = sqlx::query_scalar!(
+            r#"SELECT link_id as "link_id!" FROM links WHERE link = ?"#,
+            link.url
+        )
+        .fetch_optional(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to check if a link exists",
+            )
+        })? {
+            summary.existing += 1;
+
+            link_id
+        } else {
+            let tag_ids = if link.tags.is_empty() {
+                Vec::new()
+            } else {
+                routes::retrieve_tags_from_string(connection, link.tags.join(",")).await?
+            };
+
+            summary.posted += 1;
+
+            let link_id = routes::create_link(
+                connection,
+                Some(account_id),
+                &link.url,
+                &link.title,
+                tag_ids,
+            )
+            .await?;
+            search::index_link(connection, &link_id).await?;
+
+            link_id
+        };
+
+        sqlx::query!(
+            "INSERT OR IGNORE INTO seen (account_id, link_id, rated) VALUES (?, ?, false)",
+            account_id,
+            link_id
+        )
+        .execute(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to mark an imported link as seen",
+            )
+        })?;
+    }
+
+    let mut frequencies = frequencies.into_iter().collect::<Vec<_>>();
+    frequencies.sort_unstable_by(|(left_tag, left), (right_tag, right)| {
+        right.cmp(left).then_with(|| left_tag.cmp(right_tag))
+    });
+    frequencies.truncate(MAX_SEEDED_TAGS);
+
+    let Some(&(_, most_frequent)) = frequencies.first() else {
+        return Ok(summary);
+    };
+
+    let last_period = SystemTime::UNIX_EPOCH
+        .elapsed()
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to calculate the amount of time that has passed since the unix epoch",
+            )
+        })?
+        .as_secs();
+
+    for (tag, frequency) in frequencies {
+        let tag_id = routes::retrieve_tags_from_string(connection, tag.clone())
+            .await?
+            .pop()
+            .ok_or((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to retrieve an imported tag",
+            ))?;
+
+        // the most frequent tags start out a few hundred points above a fresh account's tags,
+        // and tags seen more often start out with more certainty
+        let score = ScaledRating::new(
+            (300.0 * frequency as f64 / most_frequent as f64)
+                / instant_glicko_2::constants::RATING_SCALING_RATIO,
+            f64::max(350.0 - 10.0 * frequency as f64, 200.0)
+                / instant_glicko_2::constants::RATING_SCALING_RATIO,
+            0.06,
+        );
+
+        // scores the account already has were learned from its ratings, which are worth more
+        // than anything inferred from an import
+        let seeded = scores::insert(connection, account_id, &tag_id, score, last_period).await?;
+
+        if seeded {
+            debug!(
+                "seeded tag {} for account {} from an import",
+                tag, account_id
+            );
+
+            summary.seeded_tags += 1;
+        }
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        normalize_tag, parse, parse_json, parse_netscape, parse_opml, unescape, ImportedLink,
+        MAX_IMPORTED_LINKS, MAX_LINK_TAGS,
+    };
+    use axum::http::StatusCode;
+
+    fn fields(links: &[ImportedLink]) -> Vec<(&str, &str, Vec<&str>)> {
+        links
+            .iter()
+            .map(|link| {
+                (
+                    link.url.as_str(),
+                    link.title.as_str(),
+                    link.tags.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tags_are_normalized() {
+        assert_eq!(normalize_tag(" Web Dev "), Some("web-dev".to_string()));
+        assert_eq!(normalize_tag("rust_lang"), Some("rust-lang".to_string()));
+        assert_eq!(normalize_tag("c++"), None);
+        assert_eq!(normalize_tag(""), None);
+    }
+
+    #[test]
+    fn entities_are_unescaped_once() {
+        assert_eq!(
+            unescape("Tom &amp; Jerry &lt;3 &quot;x&quot; &#39;y&apos;"),
+            "Tom & Jerry <3 \"x\" 'y'"
+        );
+        assert_eq!(unescape("&amp;lt;"), "&lt;");
+    }
+
+    #[test]
+    fn netscape_bookmarks_are_parsed() {
+        let links = parse_netscape(
+            r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<DL><p>
+    <DT><A HREF="https://example.com/a?x=1&amp;y=2" ADD_DATE="0" TAGS="Rust,web dev,c++">A <b>bold</b> &amp; brave page</A>
+    <DT><a href='https://example.com/b'></a>
+    <DT><A>no url</A>
+</DL><p>"#,
+        );
+
+        assert_eq!(
+            fields(&links),
+            [
+                (
+                    "https://example.com/a?x=1&y=2",
+                    "A bold & brave page",
+                    vec!["rust", "web-dev"]
+                ),
+                ("https://example.com/b", "https://example.com/b", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn json_exports_are_parsed() {
+        let links = parse_json(
+            r#"[
+                {"href": "https://example.com/a", "description": "A", "tags": "rust Rust web_dev"},
+                {"url": "https://example.com/b", "title": "B", "tags": [{"name": "Go"}, "zig"]},
+                {"description": "no url"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            fields(&links),
+            [
+                ("https://example.com/a", "A", vec!["rust", "web-dev"]),
+                ("https://example.com/b", "B", vec!["go", "zig"]),
+            ]
+        );
+
+        assert!(parse_json("[").is_err());
+        assert!(parse_json("{}").is_err());
+    }
+
+    #[test]
+    fn opml_subscriptions_are_parsed() {
+        let links = parse_opml(
+            r#"<?xml version="1.0"?>
+<opml version="2.0"><body>
+    <outline text="Folder">
+        <outline type="rss" text="A" xmlUrl="https://example.com/a.xml" htmlUrl="https://example.com/a" category="/Tech/News,Rust"/>
+        <outline type="rss" title="B" text="ignored" xmlUrl="https://example.com/b.xml" />
+    </outline>
+</body></opml>"#,
+        );
+
+        assert_eq!(
+            fields(&links),
+            [
+                ("https://example.com/a", "A", vec!["news", "rust", "tech"]),
+                ("https://example.com/b.xml", "B", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn link_tags_are_limited() {
+        let tags = (0..MAX_LINK_TAGS + 4)
+            .map(|tag| format!("tag{:02}", tag))
+            .collect::<Vec<_>>()
+            .join(",");
+        let links = parse_netscape(&format!(
+            r#"<DT><A HREF="https://example.com" TAGS="{}">A</A>"#,
+            tags
+        ));
+
+        assert_eq!(links[0].tags.len(), MAX_LINK_TAGS);
+        assert_eq!(links[0].tags[0], "tag00");
+    }
+
+    #[test]
+    fn formats_are_detected() {
+        let netscape = parse(r#"<DT><A HREF="https://example.com/a">A</A>"#).unwrap();
+        assert_eq!(netscape[0].url, "https://example.com/a");
+
+        let json = parse(r#"  [{"href": "https://example.com/b"}]"#).unwrap();
+        assert_eq!(json[0].url, "https://example.com/b");
+
+        let opml = parse(r#"<opml><outline xmlUrl="https://example.com/c"/></opml>"#).unwrap();
+        assert_eq!(opml[0].url, "https://example.com/c");
+
+        assert_eq!(
+            parse("https://example.com").unwrap_err().0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn unusable_links_are_dropped() {
+        let links = parse(
+            r#"<DT><A HREF="place:sort=8">Recent</A>
+<DT><A HREF="javascript:void(0)">Bookmarklet</A>
+<DT><A HREF="http://example.com">Plain</A>"#,
+        )
+        .unwrap();
+        assert_eq!(fields(&links), [("http://example.com", "Plain", vec![])]);
+
+        let too_many = format!(
+            "[{}]",
+            vec![r#"{"href": "https://example.com"}"#; MAX_IMPORTED_LINKS + 1].join(",")
+        );
+        assert!(parse(&too_many).is_err());
+    }
+}
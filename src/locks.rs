@@ -16,91 +16,325 @@
 //  along with this program.  If not, see <https://www.gnu.org/licenses/>.
 //
 
-use parking_lot::RwLock;
-use std::{
-    collections::HashMap,
-    sync::atomic::{AtomicBool, Ordering},
+use parking_lot::Mutex;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{
+    sync::{Mutex as AsyncMutex, OwnedMutexGuard},
+    time::Instant,
 };
 use tracing::{debug, trace};
 
-//TODO(superwhiskers): provide a means to set a handler if a thread panics to clean up
-//                     resources/revert changes
-//TODO(superwhiskers): provide a way to garbage collect unlocked entries within the map
+//TODO(superwhiskers): provide a means to set a handler if a thread panics to revert changes.
+//                     locks themselves are released as the guard is dropped while unwinding
 
 /// A structure providing a means to associate strings with locks on some external data
 ///
-/// If the data is locked, a lock operation returns immediately
-pub struct LockMap(RwLock<HashMap<String, AtomicBool>>);
+/// Entries are created when a key is first locked and removed once nothing holds or is
+/// waiting on them, so the map only ever contains keys which are in use. Waiters are granted
+/// the lock in the order they began waiting
+///
+/// Anything which needs to hold more than one lock at once must acquire them all with
+/// [`LockMap::lock_many`], rather than waiting for one while holding another
+pub struct LockMap {
+    locks: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+
+    /// How long [`LockMap::wait`] and [`LockMap::lock_many`] wait for locks before giving up
+    timeout: Duration,
+}
 
 impl LockMap {
-    pub fn new() -> &'static Self {
-        Box::leak(Box::new(Self(RwLock::new(HashMap::new()))))
+    pub fn new(timeout: Duration) -> &'static Self {
+        Box::leak(Box::new(Self {
+            locks: Mutex::new(HashMap::new()),
+            timeout,
+        }))
     }
 
-    pub fn with_capacity(capacity: usize) -> &'static Self {
-        Box::leak(Box::new(Self(RwLock::new(HashMap::with_capacity(
-            capacity,
-        )))))
+    pub fn with_capacity(capacity: usize, timeout: Duration) -> &'static Self {
+        Box::leak(Box::new(Self {
+            locks: Mutex::new(HashMap::with_capacity(capacity)),
+            timeout,
+        }))
     }
 
+    /// Lock the data associated with a key, returning immediately if it is already locked
     pub fn lock(&'static self, key: &str) -> Option<LockMapGuard> {
         trace!("requested to lock for key \"{}\"", key);
 
-        let inner = self.0.read();
+        let entry = self.entry(key);
 
-        if let Some(lock) = inner.get(key) {
-            debug!("requesting existing lock for key \"{}\"", key);
+        if let Ok(guard) = Arc::clone(&entry.lock).try_lock_owned() {
+            debug!("successfully locked for key \"{}\"", key);
 
-            if lock
-                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
-                .is_err()
-            {
-                debug!("failed to lock for key \"{}\"", key);
+            Some(LockMapGuard::new(entry, guard))
+        } else {
+            debug!("failed to lock for key \"{}\"", key);
 
-                None
-            } else {
-                debug!("successfully locked for key \"{}\"", key);
+            None
+        }
+    }
 
-                Some(LockMapGuard(self, key.to_string()))
-            }
-        } else {
-            trace!("creating new lock for key \"{}\"", key);
+    /// Lock the data associated with a key, waiting for it to be unlocked if it is already
+    /// locked
+    ///
+    /// If the lock isn't acquired within the timeout the map was created with, `None` is
+    /// returned. Nothing else may be locked while waiting, use [`LockMap::lock_many`] to lock
+    /// multiple keys
+    pub async fn wait(&'static self, key: &str) -> Option<LockMapGuard> {
+        self.wait_until(key, Instant::now() + self.timeout).await
+    }
+
+    /// Lock the data associated with each of a set of keys, waiting for them to be unlocked if
+    /// they are already locked
+    ///
+    /// Keys are always locked in the same (sorted) order, so that two requests locking
+    /// overlapping sets of keys can't each end up waiting on a key the other holds. If all of
+    /// them aren't locked within the timeout the map was created with, the ones which were are
+    /// unlocked and `None` is returned
+    pub async fn lock_many<'a>(
+        &'static self,
+        keys: impl IntoIterator<Item = &'a str>,
+    ) -> Option<Vec<LockMapGuard>> {
+        let mut keys = keys.into_iter().collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.dedup();
+
+        trace!("requested to lock {} keys", keys.len());
+
+        let deadline = Instant::now() + self.timeout;
+        let mut guards = Vec::with_capacity(keys.len());
 
-            drop(inner); // we don't want to deadlock
-            self.new_lock(key)
+        for key in keys {
+            guards.push(self.wait_until(key, deadline).await?);
         }
+
+        Some(guards)
     }
 
-    fn new_lock(&'static self, key: &str) -> Option<LockMapGuard> {
-        trace!("requested to create a new lock for key \"{}\"", key);
+    /// Lock the data associated with a key, waiting until the deadline for it to be unlocked
+    /// if it is already locked
+    async fn wait_until(&'static self, key: &str, deadline: Instant) -> Option<LockMapGuard> {
+        trace!("requested to wait for a lock for key \"{}\"", key);
+
+        let entry = self.entry(key);
 
-        let mut inner = self.0.write();
+        // declared after the entry so that, if this future is dropped while waiting, the
+        // reference held by the pending acquisition is given up before the entry is released
+        let acquire = tokio::time::timeout_at(deadline, Arc::clone(&entry.lock).lock_owned());
 
-        debug!("creating new lock for key \"{}\"", key);
+        if let Ok(guard) = acquire.await {
+            debug!("successfully locked for key \"{}\"", key);
 
-        if inner
-            .try_insert(key.to_string(), AtomicBool::new(true))
-            .is_ok()
-        {
-            Some(LockMapGuard(self, key.to_string()))
+            Some(LockMapGuard::new(entry, guard))
         } else {
+            debug!("timed out waiting for a lock for key \"{}\"", key);
+
             None
         }
     }
+
+    /// Retrieve the lock associated with a key, creating it if it doesn't exist
+    ///
+    /// The returned entry keeps the lock from being removed from the map until it is dropped
+    fn entry(&'static self, key: &str) -> LockMapEntry {
+        let mut locks = self.locks.lock();
+
+        let lock = if let Some(lock) = locks.get(key) {
+            Arc::clone(lock)
+        } else {
+            trace!("creating new lock for key \"{}\"", key);
+
+            let lock = Arc::new(AsyncMutex::new(()));
+            locks.insert(key.to_string(), Arc::clone(&lock));
+
+            lock
+        };
+
+        LockMapEntry {
+            map: self,
+            key: key.to_string(),
+            lock,
+        }
+    }
+
+    /// Give up a reference to a lock obtained from [`LockMap::entry`], removing it from the
+    /// map if nothing else holds or is waiting on it
+    fn release(&self, key: &str, lock: &Arc<AsyncMutex<()>>) {
+        let mut locks = self.locks.lock();
+
+        // references are only created while the map is locked, so if the only others are
+        // this one and the map's, nothing can be holding or waiting on the lock
+        if Arc::strong_count(lock) == 2 {
+            trace!("removing unused lock for key \"{}\"", key);
+
+            locks.remove(key);
+        }
+    }
+}
+
+/// A reference to a lock in the [`LockMap`], which is released when dropped
+///
+/// This is what ensures that entries are removed even if a future waiting on them is
+/// cancelled
+struct LockMapEntry {
+    map: &'static LockMap,
+    key: String,
+    lock: Arc<AsyncMutex<()>>,
+}
+
+impl Drop for LockMapEntry {
+    fn drop(&mut self) {
+        self.map.release(&self.key, &self.lock);
+    }
 }
 
 /// A guard over data locked by the [`LockMap`]
-pub struct LockMapGuard(&'static LockMap, String);
+pub struct LockMapGuard {
+    // the guard holds its own reference to the lock, so it must be dropped before the entry
+    guard: Option<OwnedMutexGuard<()>>,
+    entry: LockMapEntry,
+}
+
+impl LockMapGuard {
+    fn new(entry: LockMapEntry, guard: OwnedMutexGuard<()>) -> Self {
+        Self {
+            guard: Some(guard),
+            entry,
+        }
+    }
+}
 
 impl Drop for LockMapGuard {
     fn drop(&mut self) {
-        trace!("requested to drop lock for key \"{}\"", self.1.as_str());
+        debug!("dropping lock for key \"{}\"", self.entry.key);
+
+        drop(self.guard.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LockMap;
+    use std::time::Duration;
+
+    const KEYS: [&str; 6] = ["a", "b", "c", "d", "e", "f"];
 
-        let inner = self.0 .0.read();
+    fn is_empty(map: &LockMap) -> bool {
+        map.locks.lock().is_empty()
+    }
+
+    #[tokio::test]
+    async fn lock_fails_fast() {
+        let map = LockMap::new(Duration::from_secs(5));
+
+        let guard = map.lock("a").unwrap();
+        assert!(map.lock("a").is_none());
+        assert!(map.lock("b").is_some());
+
+        drop(guard);
+        assert!(map.lock("a").is_some());
+        assert!(is_empty(map));
+    }
+
+    #[tokio::test]
+    async fn timed_out_waits_release_entries() {
+        let map = LockMap::new(Duration::from_millis(20));
+
+        let guard = map.lock("b").unwrap();
+        assert!(map.wait("b").await.is_none());
+        assert!(map.lock_many(["a", "b", "c"]).await.is_none());
+
+        // the keys locked before timing out are unlocked again
+        assert!(map.lock("a").is_some());
+        assert!(map.lock("c").is_some());
+
+        drop(guard);
+        assert!(is_empty(map));
+    }
 
-        debug!("dropping lock for key \"{}\"", self.1.as_str());
+    #[tokio::test]
+    async fn cancelled_waits_release_entries() {
+        let map = LockMap::new(Duration::from_secs(60));
+
+        let guard = map.lock("a").unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), map.wait("a"))
+                .await
+                .is_err()
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), map.lock_many(["b", "a"]))
+                .await
+                .is_err()
+        );
+
+        assert_eq!(map.locks.lock().len(), 1);
+
+        // the waiters are woken by the unlock, but cancelled before they get a chance to run
+        let waiters = [
+            tokio::spawn(async move { map.wait("a").await.is_some() }),
+            tokio::spawn(async move { map.lock_many(["a", "b"]).await.is_some() }),
+        ];
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        drop(guard);
+        for waiter in &waiters {
+            waiter.abort();
+        }
+
+        for waiter in waiters {
+            assert!(matches!(waiter.await, Err(error) if error.is_cancelled()));
+        }
+
+        assert!(is_empty(map));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn contended_locks_release_entries() {
+        let map = LockMap::new(Duration::from_millis(50));
+
+        let tasks = (0..64)
+            .map(|task: usize| {
+                tokio::spawn(async move {
+                    for i in 0..50 {
+                        let key = KEYS[(task + i) % KEYS.len()];
+
+                        match (task + i) % 4 {
+                            0 => drop(map.lock(key)),
+                            1 => {
+                                let _guard = map.wait(key).await;
+                                tokio::task::yield_now().await;
+                            }
+                            2 => {
+                                let keys = [key, KEYS[(task * i) % KEYS.len()], "a"];
+                                let _guards = map.lock_many(keys).await;
+                                tokio::task::yield_now().await;
+                            }
+                            _ => {
+                                let _ = tokio::time::timeout(
+                                    Duration::from_micros(100),
+                                    map.lock_many(KEYS),
+                                )
+                                .await;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // abort some of the tasks partway through, while they may be waiting on a lock
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        for task in tasks.iter().step_by(3) {
+            task.abort();
+        }
+
+        for task in tasks {
+            if let Err(error) = task.await {
+                assert!(error.is_cancelled());
+            }
+        }
 
-        //SAFETY: there's no way this isn't in the map as we never remove anything
-        unsafe { inner.get(self.1.as_str()).unwrap_unchecked() }.store(false, Ordering::Release);
+        assert!(is_empty(map));
     }
 }
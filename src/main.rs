@@ -47,35 +47,63 @@
 #![feature(let_chains)]
 #![feature(int_roundings)]
 #![feature(iter_intersperse)]
-#![feature(map_try_insert)]
 
+mod accounts;
+mod admin;
+mod backup;
+mod cli;
 mod configuration;
+mod css;
 mod feed;
+mod feed_tokens;
+mod front_page;
+mod import;
 mod locks;
 mod model;
+mod moderation;
 mod rand;
+mod rate_limit;
 mod routes;
+mod schema;
+mod scores;
+mod search;
+mod signup;
+mod styles;
+mod syndication;
+mod tags;
 mod templates;
 mod util;
 
-use anyhow::Context;
+use anyhow::{bail, Context};
+use clap::Parser;
 use axum::{
     extract::Extension,
+    handler::Handler,
     http::{header, HeaderValue},
     middleware,
-    routing::get,
+    routing::{get, post},
     Router,
 };
 use sqlx::{
     sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
-    ConnectOptions,
+    ConnectOptions, SqlitePool,
 };
+use std::net::SocketAddr;
 use tower_http::{set_header::SetResponseHeaderLayer, trace::TraceLayer};
 use tracing::{info, log::LevelFilter, trace, warn};
 use tracing_log::LogTracer;
 use tracing_subscriber::FmtSubscriber;
 
-use crate::{configuration::Configuration, locks::LockMap};
+use crate::{
+    cli::{AccountCommand, Cli, Command, LinkCommand, TagCommand},
+    configuration::Configuration,
+    feed_tokens::SigningKey,
+    front_page::FrontPage,
+    locks::LockMap,
+    rate_limit::{RateLimiter, RouteClass},
+    signup::ChallengeKey,
+    styles::{CreatorKey, StyleCache},
+};
 
 #[cfg(feature = "dhat")]
 #[global_allocator]
@@ -90,6 +118,7 @@ async fn main() -> anyhow::Result<()> {
     #[cfg(feature = "dhat")]
     let _dhat_profiler = dhat::Profiler::new_heap();
 
+    let cli = Cli::parse();
     let config = Configuration::new().context("failed to load the configuration")?;
 
     tracing::subscriber::set_global_default(
@@ -100,6 +129,92 @@ async fn main() -> anyhow::Result<()> {
 
     LogTracer::init()?;
 
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config).await,
+        Command::Backup { path } => {
+            let sqlite = connect(&config).await?;
+
+            backup::backup(&sqlite, &path).await?;
+
+            info!("backed up the db to {}", path.display());
+
+            sqlite.close().await;
+
+            Ok(())
+        }
+        Command::Restore { path } => backup::restore(&config.sqlite, &path).await,
+        Command::Migrate => {
+            let sqlite = connect(&config).await?;
+
+            let previous_version = schema::migrate(&mut *sqlite.acquire().await?).await?;
+
+            info!(
+                "migrated the db from schema version {} to {}",
+                previous_version,
+                schema::SCHEMA_VERSION
+            );
+
+            sqlite.close().await;
+
+            Ok(())
+        }
+        command => {
+            let sqlite = connect(&config).await?;
+            check_schema_version(&sqlite).await?;
+
+            match command {
+                Command::Account {
+                    command: AccountCommand::Show { account_id },
+                } => admin::show_account(&sqlite, &account_id).await?,
+                Command::Account {
+                    command: AccountCommand::Delete { account_id },
+                } => admin::delete_account(&sqlite, &account_id).await?,
+                Command::Account {
+                    command: AccountCommand::Role { account_id, role },
+                } => admin::set_role(&sqlite, &account_id, &role).await?,
+                Command::Link {
+                    command: LinkCommand::Delete { link_id },
+                } => admin::delete_link(&sqlite, &link_id).await?,
+                Command::Tag {
+                    command: TagCommand::Merge { from, into },
+                } => admin::merge_tags(&sqlite, &from, &into).await?,
+                Command::RecomputeScores => {
+                    admin::recompute_scores(&sqlite, &config.algorithm).await?
+                }
+                Command::Invite => admin::create_invite(&sqlite).await?,
+                Command::Stats => admin::stats(&sqlite).await?,
+                Command::Serve
+                | Command::Backup { .. }
+                | Command::Restore { .. }
+                | Command::Migrate => unreachable!("these commands are handled above"),
+            }
+
+            sqlite.close().await;
+
+            Ok(())
+        }
+    }
+}
+
+/// Make sure the database was created with the schema this build expects
+async fn check_schema_version(sqlite: &SqlitePool) -> anyhow::Result<()> {
+    let version = schema::version(&mut *sqlite.acquire().await?)
+        .await
+        .context("unable to read the db's schema version")?;
+
+    if version != schema::SCHEMA_VERSION {
+        bail!(
+            "the db has schema version {}, but this build of flock expects schema version {}. run `flock migrate` first",
+            version,
+            schema::SCHEMA_VERSION
+        );
+    }
+
+    Ok(())
+}
+
+/// Open a connection pool to the configured database
+async fn connect(config: &Configuration) -> anyhow::Result<SqlitePool> {
     trace!("opening a db connection pool");
 
     let mut sqlite_pool_options = SqlitePoolOptions::new();
@@ -112,13 +227,12 @@ async fn main() -> anyhow::Result<()> {
         sqlite_pool_options = sqlite_pool_options.max_connections(max_connections);
     }
 
-    let sqlite = sqlite_pool_options
+    sqlite_pool_options
         .connect_with({
             SqliteConnectOptions::new()
                 .filename(&config.sqlite.path)
                 .log_statements(LevelFilter::Debug)
-                //TODO(superwhiskers): we don't apply the schema yet
-                //.create_if_missing(config.sqlite.create_if_missing)
+                .create_if_missing(config.sqlite.create_if_missing)
                 // performance
                 // (from https://phiresky.github.io/blog/2020/sqlite-performance-tuning/)
                 .journal_mode(SqliteJournalMode::Wal)
@@ -126,43 +240,138 @@ async fn main() -> anyhow::Result<()> {
                 .optimize_on_close(true, None)
         })
         .await
-        .context("unable to open a db connection pool")?;
+        .context("unable to open a db connection pool")
+}
+
+/// Run the server until it receives a signal to stop
+async fn serve(config: Configuration) -> anyhow::Result<()> {
+    let sqlite = connect(&config).await?;
+    check_schema_version(&sqlite).await?;
+
+    let lock_map = LockMap::new(config.routes.lock_timeout);
+
+    let signing_key = SigningKey::load(&sqlite).await?;
+
+    let challenge_key = ChallengeKey::load(&sqlite).await?;
+
+    let creator_key = CreatorKey::load(&sqlite).await?;
+
+    let front_page = FrontPage::new();
+
+    let rate_limiter = RateLimiter::new(&config.rate_limits, sqlite.clone());
 
-    let lock_map = LockMap::new();
+    let style_cache = StyleCache::new(
+        config.routes.style_cache_capacity,
+        config.routes.account_style_cache_capacity,
+    );
+
+    trace!("spawning the front page refresh task");
+
+    tokio::spawn(front_page::refresh_periodically(
+        front_page,
+        sqlite.clone(),
+        config.algorithm.clone(),
+    ));
+
+    if let Some(backup_directory) = config.sqlite.backup_directory.clone() {
+        trace!("spawning the periodic backup task");
+
+        tokio::spawn(backup::backup_periodically(
+            sqlite.clone(),
+            backup_directory,
+            config.sqlite.clone(),
+        ));
+    }
 
     trace!("initializing the server");
 
     let app = Router::new()
         .route("/", get(routes::get_index))
         .route("/login", get(routes::get_login).post(routes::post_login))
-        .route("/signup", get(routes::get_signup).post(routes::post_signup))
+        .route(
+            "/signup",
+            get(routes::get_signup)
+                .post(routes::post_signup.layer(rate_limiter.layer(RouteClass::Signup))),
+        )
         .route("/logout", get(routes::get_logout))
-        .route("/post", get(routes::get_post).post(routes::post_post))
-        .route("/post-style", get(routes::get_post_style).post(routes::post_post_style))
+        .route(
+            "/post",
+            get(routes::get_post).post(routes::post_post.layer(rate_limiter.layer(RouteClass::Post))),
+        )
+        .route(
+            "/post-style",
+            get(routes::get_post_style)
+                .post(routes::post_post_style.layer(rate_limiter.layer(RouteClass::PostStyle))),
+        )
+        .route(
+            "/import",
+            get(routes::get_import)
+                .post(routes::post_import.layer(rate_limiter.layer(RouteClass::Import))),
+        )
         .route("/tags", get(routes::get_tags))
+        .route("/tags/:tag_name", get(routes::get_tag))
+        .route("/search", get(routes::get_search))
+        .route("/styles", get(routes::get_styles))
+        .route("/styles/:style_id/use", post(routes::post_use_style))
+        .route(
+            "/styles/:style_id/edit",
+            get(routes::get_edit_style)
+                .post(routes::post_edit_style.layer(rate_limiter.layer(RouteClass::PostStyle))),
+        )
+        .route("/styles/:style_id/delete", post(routes::post_delete_style))
         .route("/welcome", get(routes::get_welcome))
+        .route("/feed/explain", get(routes::get_feed_explanation))
+        .route("/feed/history", get(routes::get_feed_history))
+        .route("/feed/archive", get(routes::get_feed_archive))
+        .route("/feed/refresh", post(routes::post_refresh_feed))
+        .route("/feed/more", post(routes::post_load_more_feed))
+        .route("/report", get(routes::get_report).post(routes::post_report))
+        .route("/moderation", get(routes::get_moderation).post(routes::post_moderation))
         .nest(
             "/profile",
             Router::new()
                 .route("/", get(routes::get_profile).post(routes::post_profile))
-                .route("/tags", get(routes::get_profile_tags)),
+                .route("/tags", get(routes::get_profile_tags))
+                .route("/export", get(routes::get_profile_export))
+                .route("/delete", post(routes::post_profile_delete))
+                .route("/feed-tokens", post(routes::post_create_feed_token))
+                .route("/feed-tokens/revoke", post(routes::post_revoke_feed_token))
+                .route("/invites", post(routes::post_create_invite)),
         )
         .nest(
             "/links/:link_id",
             Router::new()
                 .route("/", get(routes::link))
-                .route("/promote", get(routes::get_promote_link))
-                .route("/neutral", get(routes::get_neutral_link))
-                .route("/demote", get(routes::get_demote_link)), // .route("/edit", get(routes::get_edit_link).post(routes::post_edit_link)),
+                .route(
+                    "/promote",
+                    get(routes::get_promote_link.layer(rate_limiter.layer(RouteClass::Rating))),
+                )
+                .route(
+                    "/neutral",
+                    get(routes::get_neutral_link.layer(rate_limiter.layer(RouteClass::Rating))),
+                )
+                .route(
+                    "/demote",
+                    get(routes::get_demote_link.layer(rate_limiter.layer(RouteClass::Rating))),
+                ), // .route("/edit", get(routes::get_edit_link).post(routes::post_edit_link)),
         )
         .layer(middleware::from_fn(util::apply_style_id_extension))
+        .layer(middleware::from_fn(moderation::reject_suspended_accounts))
         .route("/feed.xml", get(routes::get_feed_xml))
+        .route("/feed.atom", get(routes::get_feed_atom))
+        .route("/feed.json", get(routes::get_feed_json))
+        .route("/tags/:tag_name/feed.xml", get(routes::get_tag_feed_xml))
         .route("/styles/:style_id", get(routes::get_style))
         .layer(Extension(sqlite.clone()))
         .layer(Extension(config.routes))
         .layer(Extension(config.algorithm))
         .layer(Extension(config.http.clone()))
         .layer(Extension(lock_map))
+        .layer(Extension(signing_key))
+        .layer(Extension(challenge_key))
+        .layer(Extension(creator_key))
+        .layer(Extension(style_cache))
+        .layer(Extension(front_page))
         .layer(SetResponseHeaderLayer::appending(
             header::CONTENT_SECURITY_POLICY,
             HeaderValue::from_static(
@@ -176,7 +385,7 @@ async fn main() -> anyhow::Result<()> {
     info!("listening at http://{}", &config.http.address);
 
     axum::Server::bind(&config.http.address)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .with_graceful_shutdown(util::signal_handler())
         .await?;
 
@@ -28,10 +28,20 @@ pub struct PostLogin {
     pub account_id: String,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Signup {
+    pub invite: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct PostSignup {
     pub tags: String,
+    #[serde(default)]
+    pub invite_code: String,
+    #[serde(default)]
+    pub stamp: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -46,6 +56,16 @@ pub struct PostProfile {
     pub new_style_id: String,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DeleteAccount {
+    #[serde(
+        default = "default_checkbox",
+        deserialize_with = "deserialize_checkbox"
+    )]
+    pub confirm: bool,
+}
+
 fn default_checkbox() -> bool {
     false
 }
@@ -57,13 +77,59 @@ where
     Ok(true)
 }
 
+/// A snapshot of one of an account's feeds, as stored across the `feeds`, `feed_items` and
+/// `feed_item_tags` tables
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Feed {
-    /// The duration since the feed was last refreshed expressed in seconds since unix epoch
+    /// The time the feed was generated expressed in seconds since unix epoch
     pub refreshed: u64,
 
     // A vector of link ids and their overall scores selected to be in the feed
     pub links: Vec<(String, ScaledRatingData)>,
+
+    /// The reasoning behind each link's selection, in the same order as `links`
+    ///
+    /// This is empty for feeds generated before explanations were recorded
+    #[serde(default)]
+    pub explanations: Vec<FeedExplanation>,
+}
+
+impl Feed {
+    pub fn new(refreshed: u64, entries: Vec<(String, ScaledRatingData, FeedExplanation)>) -> Self {
+        let mut links = Vec::with_capacity(entries.len());
+        let mut explanations = Vec::with_capacity(entries.len());
+
+        for (link_id, overall_score, explanation) in entries {
+            links.push((link_id, overall_score));
+            explanations.push(explanation);
+        }
+
+        Self {
+            refreshed,
+            links,
+            explanations,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FeedExplanation {
+    /// The segment of the sorted candidates the link was picked from, where 0 is the top
+    pub segment: usize,
+
+    /// The tags shared by the account and the link
+    pub tags: Vec<TagExplanation>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TagExplanation {
+    pub tag_id: String,
+
+    /// The tag's share of the sum of the account's tag scores
+    pub importance: ScaledRatingData,
+
+    /// The link's score for the tag
+    pub score: ScaledRatingData,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -75,7 +141,26 @@ pub struct Score {
     pub last_period: u64,
 
     /// The queue of results that haven't been incorporated into the score
-    pub result_queue: Vec<ScaledPlayerResult>,
+    pub result_queue: Vec<QueuedResult>,
+}
+
+/// The result of a rating which hasn't been incorporated into a [`Score`] yet
+///
+/// This is laid out the same way as the Glicko-2 implementation's own player results, which
+/// scores stored as messagepack contain
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct QueuedResult {
+    /// The score of the other side of the rating, at the time of the rating
+    pub opponent: ScaledRating,
+
+    /// 1 for a win, 0.5 for a draw and 0 for a loss
+    pub score: f64,
+}
+
+impl From<QueuedResult> for ScaledPlayerResult {
+    fn from(result: QueuedResult) -> Self {
+        ScaledPlayerResult::new(result.opponent, result.score)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -105,10 +190,70 @@ pub struct Style {
     pub style: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct StyleVersion {
+    #[serde(rename = "v")]
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FeedArchive {
+    #[serde(default)]
+    pub page: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Styles {
+    #[serde(default)]
+    pub sort: StyleSort,
+    #[serde(default)]
+    pub page: usize,
+}
+
+/// The order in which the styles in the gallery are listed
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StyleSort {
+    /// Sorted by the number of accounts using the styles
+    #[default]
+    Popular,
+
+    /// Sorted by the time the styles were uploaded
+    New,
+}
+
+impl StyleSort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Popular => "popular",
+            Self::New => "new",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DeleteStyle {
+    #[serde(
+        default = "default_checkbox",
+        deserialize_with = "deserialize_checkbox"
+    )]
+    pub confirm: bool,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct FeedXml {
-    pub account_id: String,
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RevokeFeedToken {
+    pub token: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -117,11 +262,206 @@ pub struct Tags {
     pub after: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TagLinks {
+    #[serde(default)]
+    pub sort: TagLinksSort,
+    #[serde(default)]
+    pub page: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TagFeedXml {
+    #[serde(default = "default_tag_feed_sort")]
+    pub sort: TagLinksSort,
+}
+
+fn default_tag_feed_sort() -> TagLinksSort {
+    TagLinksSort::New
+}
+
+/// The order in which the links carrying a tag are listed
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TagLinksSort {
+    /// Sorted by the links' scores for the tag
+    #[default]
+    Top,
+
+    /// Sorted by the time the links were posted
+    New,
+}
+
+impl TagLinksSort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Top => "top",
+            Self::New => "new",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Link {
+    #[serde(rename = "t")]
+    pub token: Option<String>,
+}
+
+/// The parameters of a signed rating url, as embedded in syndicated feed items
+#[derive(Debug, Deserialize)]
+pub struct SignedRating {
+    #[serde(rename = "t")]
+    pub token: Option<String>,
+    #[serde(rename = "s")]
+    pub signature: Option<String>,
+}
+
+/// Where to send the client once a link has been rated, for ratings made from somewhere other
+/// than the feed
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RatingRedirect {
+    pub redirect_to: Option<String>,
+}
+
+/// A rating an account can give a link
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rating {
+    Promote,
+    Neutral,
+    Demote,
+}
+
+impl Rating {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Promote => "promote",
+            Self::Neutral => "neutral",
+            Self::Demote => "demote",
+        }
+    }
+
+    /// The outcome of the matches played between an account and a link before it is adjusted
+    /// according to how their scores overlap
+    pub fn base_outcome(&self) -> f64 {
+        match self {
+            Self::Promote => 0.75,
+            Self::Neutral => 0.5,
+            Self::Demote => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Search {
+    #[serde(default)]
+    pub q: String,
+    #[serde(default)]
+    pub tags: String,
+    #[serde(default)]
+    pub page: usize,
+}
+
+/// The kind of thing a report is about
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReportKind {
+    Link,
+    Style,
+}
+
+impl ReportKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Link => "link",
+            Self::Style => "style",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Report {
+    pub kind: ReportKind,
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PostReport {
+    pub kind: ReportKind,
+    pub id: String,
+    pub reason: String,
+}
+
+/// An action a moderator can take
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModerationAction {
+    /// Hide a link from feeds, tag pages, search and the front page
+    HideLink,
+
+    /// Remove a tag from a link
+    RemoveTag,
+
+    /// Delete a style, unsetting it for every account using it
+    DeleteStyle,
+
+    /// Suspend an account, refusing everything but logging out, exporting its data and
+    /// deleting itself
+    SuspendAccount,
+
+    /// Resolve a report without taking any action
+    DismissReport,
+}
+
+impl ModerationAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::HideLink => "hide-link",
+            Self::RemoveTag => "remove-tag",
+            Self::DeleteStyle => "delete-style",
+            Self::SuspendAccount => "suspend-account",
+            Self::DismissReport => "dismiss-report",
+        }
+    }
+    /// The kind of report about the action's target which taking it resolves, if it resolves
+    /// reports about its target at all
+    pub fn report_kind(&self) -> Option<ReportKind> {
+        match self {
+            Self::HideLink | Self::RemoveTag => Some(ReportKind::Link),
+            Self::DeleteStyle => Some(ReportKind::Style),
+            Self::SuspendAccount | Self::DismissReport => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PostModeration {
+    pub action: ModerationAction,
+    pub target: String,
+    #[serde(default)]
+    pub tag: String,
+}
+
 #[derive(Debug, FromRow)]
 pub struct TagRow {
     pub id: String,
     pub name: String,
 }
 
+/// The style applied to a page, if any
 #[derive(Debug, Clone)]
-pub struct StyleId(pub Option<String>);
+pub struct StyleId(pub Option<AppliedStyle>);
+
+/// A style alongside its current version, which is included in the url it is linked to with so
+/// that it can be cached indefinitely
+#[derive(Debug, Clone)]
+pub struct AppliedStyle {
+    pub id: String,
+    pub version: String,
+}
@@ -0,0 +1,793 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use axum::{
+    headers::Cookie,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::Response,
+    Extension, TypedHeader,
+};
+use sqlx::{pool::PoolConnection, Connection, Sqlite, SqliteConnection, SqlitePool};
+use std::time::SystemTime;
+use tracing::{debug, info, trace};
+use ulid::Ulid;
+
+use crate::{
+    front_page::FrontPage, locks::LockMap, model, rand::pcg_thread_rng, search, styles::StyleCache,
+    templates,
+};
+
+/// The maximum length of the reason given for a report, in bytes
+pub const MAX_REASON_LENGTH: usize = 1000;
+
+/// The number of audit log entries shown on the moderation page
+const MODERATION_LOG_LENGTH: i64 = 50;
+
+/// The paths a suspended account may still visit, so that it can leave or take its data
+/// with it
+const SUSPENDED_ALLOWED_PATHS: [&str; 3] = ["/logout", "/profile/export", "/profile/delete"];
+
+/// A role granting an account elevated privileges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// May take every moderation action, including suspending moderators
+    Admin,
+
+    /// May take every moderation action against accounts without a role
+    Moderator,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Admin => "admin",
+            Self::Moderator => "moderator",
+        }
+    }
+
+    pub fn from_str(role: &str) -> Option<Self> {
+        match role {
+            "admin" => Some(Self::Admin),
+            "moderator" => Some(Self::Moderator),
+            _ => None,
+        }
+    }
+}
+
+/// Retrieve the role of an account, if it has one
+pub async fn role(
+    connection: &mut SqliteConnection,
+    account_id: &str,
+) -> Result<Option<Role>, (StatusCode, &'static str)> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT role as "role!" FROM roles WHERE account_id = ?"#,
+        account_id
+    )
+    .fetch_optional(connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's role",
+        )
+    })?
+    .and_then(|role| Role::from_str(&role)))
+}
+
+/// Grant an account a role, or take its role away if `None` is provided
+pub async fn set_role(
+    connection: &mut SqliteConnection,
+    account_id: &str,
+    role: Option<Role>,
+) -> Result<(), (StatusCode, &'static str)> {
+    if let Some(role) = role {
+        let role = role.as_str();
+
+        sqlx::query!(
+            "INSERT OR REPLACE INTO roles (account_id, role) VALUES (?, ?)",
+            account_id,
+            role
+        )
+        .execute(connection)
+        .await
+    } else {
+        sqlx::query!("DELETE FROM roles WHERE account_id = ?", account_id)
+            .execute(connection)
+            .await
+    }
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to update the account's role",
+        )
+    })?;
+
+    Ok(())
+}
+
+/// Determine whether an account has been suspended
+pub async fn is_suspended(
+    connection: &mut SqliteConnection,
+    account_id: &str,
+) -> Result<bool, (StatusCode, &'static str)> {
+    Ok(
+        sqlx::query_scalar!("SELECT 1 FROM suspensions WHERE account_id = ?", account_id)
+            .fetch_optional(connection)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to check if the account is suspended",
+                )
+            })?
+            .is_some(),
+    )
+}
+
+/// Determine whether a link has been hidden by a moderator
+pub async fn is_hidden(
+    connection: &mut SqliteConnection,
+    link_id: &str,
+) -> Result<bool, (StatusCode, &'static str)> {
+    Ok(
+        sqlx::query_scalar!("SELECT 1 FROM hidden_links WHERE link_id = ?", link_id)
+            .fetch_optional(connection)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to check if the link is hidden",
+                )
+            })?
+            .is_some(),
+    )
+}
+
+/// Refuse every request made by a suspended account, besides those needed to log out,
+/// export its data, or delete itself
+pub async fn reject_suspended_accounts<B>(
+    Extension(sqlite): Extension<SqlitePool>,
+    cookies: Option<TypedHeader<Cookie>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, (StatusCode, &'static str)> {
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id")
+        && !SUSPENDED_ALLOWED_PATHS.contains(&request.uri().path()) {
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
+
+        if is_suspended(&mut connection, account_id).await? {
+            debug!("refusing a request from suspended account {}", account_id);
+
+            return Err((
+                StatusCode::FORBIDDEN,
+                "your account has been suspended. you may still export your data or delete your account from your profile",
+            ));
+        }
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Report a link or style to the moderators
+///
+/// Reporting something the account already has an unresolved report for does nothing
+pub async fn report(
+    connection: &mut PoolConnection<Sqlite>,
+    reporter: &str,
+    kind: model::ReportKind,
+    target_id: &str,
+    reason: &str,
+) -> Result<(), (StatusCode, &'static str)> {
+    trace!(
+        "account {} reporting {} {}",
+        reporter,
+        kind.as_str(),
+        target_id
+    );
+
+    if reason.len() > MAX_REASON_LENGTH {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "the provided reason is too long",
+        ));
+    }
+
+    let exists = match kind {
+        model::ReportKind::Link => {
+            sqlx::query_scalar!("SELECT 1 FROM links WHERE link_id = ?", target_id)
+                .fetch_optional(&mut **connection)
+                .await
+        }
+        model::ReportKind::Style => {
+            sqlx::query_scalar!("SELECT 1 FROM styles WHERE style_id = ?", target_id)
+                .fetch_optional(&mut **connection)
+                .await
+        }
+    }
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to check if the reported item exists",
+        )
+    })?
+    .is_some();
+
+    if !exists {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "the reported item does not exist",
+        ));
+    }
+
+    let kind = kind.as_str();
+    let report_id = Ulid::with_source(&mut pcg_thread_rng()).to_string();
+
+    sqlx::query!(
+        "INSERT INTO reports (report_id, kind, target_id, reporter, reason, resolved)
+              SELECT ?, ?, ?, ?, ?, false
+               WHERE NOT EXISTS (
+                         SELECT 1
+                           FROM reports
+                          WHERE kind = ?
+                            AND target_id = ?
+                            AND reporter = ?
+                            AND NOT resolved
+                     )",
+        report_id,
+        kind,
+        target_id,
+        reporter,
+        reason,
+        kind,
+        target_id,
+        reporter
+    )
+    .execute(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to record the report",
+        )
+    })?;
+
+    Ok(())
+}
+
+/// Format the time encoded in a ulid for display
+fn ulid_time(id: &str) -> String {
+    Ulid::from_string(id)
+        .map(|id| humantime::format_rfc3339_seconds(id.datetime()).to_string())
+        .unwrap_or_default()
+}
+
+/// Retrieve every unresolved report, oldest first
+pub async fn open_reports(
+    connection: &mut PoolConnection<Sqlite>,
+) -> Result<Vec<templates::ModerationReport>, (StatusCode, &'static str)> {
+    Ok(sqlx::query!(
+        r#"SELECT reports.report_id as "report_id!",
+                  reports.kind as "kind!",
+                  reports.target_id as "target_id!",
+                  reports.reporter as "reporter!",
+                  reports.reason as "reason!",
+                  COALESCE(links.description, styles.name) as "description?: String",
+                  COALESCE(posts.account_id, styles.creator) as "owner?: String",
+                  (
+                      SELECT group_concat(tags.name, ',')
+                        FROM scores
+                  INNER JOIN tags ON scores.tag_id = tags.tag_id
+                       WHERE scores.id = reports.target_id
+                  ) as "tags?: String"
+             FROM reports
+        LEFT JOIN links ON reports.kind = 'link' AND links.link_id = reports.target_id
+        LEFT JOIN posts ON reports.kind = 'link' AND posts.link_id = reports.target_id
+        LEFT JOIN styles ON reports.kind = 'style' AND styles.style_id = reports.target_id
+            WHERE NOT reports.resolved
+         ORDER BY reports.report_id"#
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the open reports",
+        )
+    })?
+    .into_iter()
+    .map(|report| templates::ModerationReport {
+        created: ulid_time(&report.report_id),
+        id: report.report_id,
+        is_link: report.kind == "link",
+        target_id: report.target_id,
+        description: report.description.unwrap_or_default(),
+        owner: report.owner.filter(|owner| !owner.is_empty()),
+        tags: report
+            .tags
+            .map(|tags| tags.split(',').map(str::to_string).collect())
+            .unwrap_or_default(),
+        reporter: report.reporter,
+        reason: report.reason,
+    })
+    .collect())
+}
+
+/// Retrieve the most recent entries of the moderation log, newest first
+pub async fn recent_log(
+    connection: &mut PoolConnection<Sqlite>,
+) -> Result<Vec<templates::ModerationLogEntry>, (StatusCode, &'static str)> {
+    Ok(sqlx::query!(
+        r#"SELECT entry_id as "entry_id!",
+                  moderator as "moderator!",
+                  action as "action!",
+                  target_id as "target_id!",
+                  details as "details!"
+             FROM moderation_log
+         ORDER BY entry_id DESC
+            LIMIT ?"#,
+        MODERATION_LOG_LENGTH
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the moderation log",
+        )
+    })?
+    .into_iter()
+    .map(|entry| templates::ModerationLogEntry {
+        created: ulid_time(&entry.entry_id),
+        moderator: entry.moderator,
+        action: entry.action,
+        target_id: entry.target_id,
+        details: entry.details,
+    })
+    .collect())
+}
+
+/// Resolve the reports about something and record the action taken in the moderation log
+async fn resolve(
+    connection: &mut SqliteConnection,
+    moderator: &str,
+    action: model::ModerationAction,
+    target_id: &str,
+    details: &str,
+) -> Result<(), (StatusCode, &'static str)> {
+    // dismissals target a report, while everything else targets the subject of the reports it
+    // resolves, which ids of other kinds of subjects could collide with
+    if action == model::ModerationAction::DismissReport {
+        sqlx::query!(
+            "UPDATE reports SET resolved = true WHERE report_id = ?",
+            target_id
+        )
+        .execute(&mut *connection)
+        .await
+    } else if let Some(kind) = action.report_kind() {
+        let kind = kind.as_str();
+
+        sqlx::query!(
+            "UPDATE reports SET resolved = true WHERE kind = ? AND target_id = ?",
+            kind,
+            target_id
+        )
+        .execute(&mut *connection)
+        .await
+    } else {
+        Ok(Default::default())
+    }
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to resolve the reports",
+        )
+    })?;
+
+    let entry_id = Ulid::with_source(&mut pcg_thread_rng()).to_string();
+    let action = action.as_str();
+
+    sqlx::query!(
+        "INSERT INTO moderation_log (entry_id, moderator, action, target_id, details) VALUES (?, ?, ?, ?, ?)",
+        entry_id,
+        moderator,
+        action,
+        target_id,
+        details
+    )
+    .execute(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to write to the moderation log",
+        )
+    })?;
+
+    info!(
+        "moderator {} took action {} against {} ({})",
+        moderator, action, target_id, details
+    );
+
+    Ok(())
+}
+
+/// Take a moderation action, resolving any reports about its target and recording it in the
+/// moderation log
+///
+/// The caller is expected to have checked that the moderator has a role
+#[allow(clippy::too_many_arguments)]
+pub async fn act(
+    connection: &mut PoolConnection<Sqlite>,
+    lock_map: &'static LockMap,
+    style_cache: &StyleCache,
+    front_page: &FrontPage,
+    moderator: &str,
+    moderator_role: Role,
+    action: model::ModerationAction,
+    target_id: &str,
+    tag: &str,
+) -> Result<(), (StatusCode, &'static str)> {
+    trace!(
+        "moderator {} taking action {} against {}",
+        moderator,
+        action.as_str(),
+        target_id
+    );
+
+    // held until the search index is rebuilt, after the transaction is committed
+    let _link_tag_lock = if action == model::ModerationAction::RemoveTag {
+        Some(lock_map.wait(target_id).await.ok_or((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "timed out waiting for a lock on the link's tag information, try again in a few seconds",
+        ))?)
+    } else {
+        None
+    };
+
+    let mut transaction = connection.begin().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to begin a transaction",
+        )
+    })?;
+
+    let details = match action {
+        model::ModerationAction::HideLink => {
+            if sqlx::query_scalar!("SELECT 1 FROM links WHERE link_id = ?", target_id)
+                .fetch_optional(&mut *transaction)
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to query the db"))?
+                .is_none()
+            {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "the requested link does not exist",
+                ));
+            }
+
+            for (query, error) in [
+                (
+                    sqlx::query!(
+                        "INSERT OR IGNORE INTO hidden_links (link_id) VALUES (?)",
+                        target_id
+                    ),
+                    "unable to hide the link",
+                ),
+                (
+                    sqlx::query!("DELETE FROM links_search WHERE link_id = ?", target_id),
+                    "unable to remove the link from the search index",
+                ),
+            ] {
+                query
+                    .execute(&mut *transaction)
+                    .await
+                    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, error))?;
+            }
+
+            String::new()
+        }
+        model::ModerationAction::RemoveTag => {
+            let removed = sqlx::query!(
+                "DELETE FROM scores
+                       WHERE id = ?
+                         AND id IN (SELECT link_id FROM links)
+                         AND tag_id = (SELECT tag_id FROM tags WHERE name = ?)",
+                target_id,
+                tag
+            )
+            .execute(&mut *transaction)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to remove the tag from the link",
+                )
+            })?
+            .rows_affected();
+
+            if removed == 0 {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "the requested link does not carry the requested tag",
+                ));
+            }
+
+            format!("removed tag {}", tag)
+        }
+        model::ModerationAction::DeleteStyle => {
+            let name = sqlx::query_scalar!(
+                r#"SELECT name as "name!" FROM styles WHERE style_id = ?"#,
+                target_id
+            )
+            .fetch_optional(&mut *transaction)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to query the db"))?
+            .ok_or((
+                StatusCode::BAD_REQUEST,
+                "the requested style does not exist",
+            ))?;
+
+            for (query, error) in [
+                (
+                    sqlx::query!(
+                        "UPDATE accounts SET style_id = NULL WHERE style_id = ?",
+                        target_id
+                    ),
+                    "unable to remove the style from the accounts using it",
+                ),
+                (
+                    sqlx::query!("DELETE FROM style_versions WHERE style_id = ?", target_id),
+                    "unable to delete the style's version",
+                ),
+                (
+                    sqlx::query!("DELETE FROM styles WHERE style_id = ?", target_id),
+                    "unable to delete the style",
+                ),
+            ] {
+                query
+                    .execute(&mut *transaction)
+                    .await
+                    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, error))?;
+            }
+
+            format!("deleted style {}", name)
+        }
+        model::ModerationAction::SuspendAccount => {
+            if sqlx::query_scalar!("SELECT 1 FROM accounts WHERE account_id = ?", target_id)
+                .fetch_optional(&mut *transaction)
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to query the db"))?
+                .is_none()
+            {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "the requested account does not exist",
+                ));
+            }
+
+            match (moderator_role, role(&mut transaction, target_id).await?) {
+                (_, Some(Role::Admin)) => {
+                    return Err((StatusCode::FORBIDDEN, "admins can't be suspended"))
+                }
+                (Role::Moderator, Some(Role::Moderator)) => {
+                    return Err((
+                        StatusCode::FORBIDDEN,
+                        "only admins can suspend moderators",
+                    ))
+                }
+                _ => {}
+            }
+
+            let now = SystemTime::UNIX_EPOCH
+                .elapsed()
+                .map_err(|_| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "unable to calculate the amount of time that has passed since the unix epoch",
+                    )
+                })?
+                .as_secs() as i64;
+
+            sqlx::query!(
+                "INSERT OR IGNORE INTO suspensions (account_id, moderator, suspended) VALUES (?, ?, ?)",
+                target_id,
+                moderator,
+                now
+            )
+            .execute(&mut *transaction)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to suspend the account",
+                )
+            })?;
+
+            String::new()
+        }
+        model::ModerationAction::DismissReport => {
+            if sqlx::query_scalar!(
+                "SELECT 1 FROM reports WHERE report_id = ? AND NOT resolved",
+                target_id
+            )
+            .fetch_optional(&mut *transaction)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to query the db"))?
+            .is_none()
+            {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "the requested report does not exist or was already resolved",
+                ));
+            }
+
+            String::new()
+        }
+    };
+
+    resolve(&mut transaction, moderator, action, target_id, &details).await?;
+
+    transaction.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to commit the transaction",
+        )
+    })?;
+
+    match action {
+        model::ModerationAction::HideLink => front_page.remove_link(target_id),
+        model::ModerationAction::RemoveTag => search::index_link(connection, target_id).await?,
+        model::ModerationAction::DeleteStyle => style_cache.invalidate(target_id),
+        _ => (),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use sqlx::{pool::PoolConnection, Sqlite};
+    use std::time::Duration;
+
+    use super::{act, report, Role};
+    use crate::{front_page::FrontPage, locks::LockMap, model, schema, styles::StyleCache};
+
+    async fn suspend(
+        connection: &mut PoolConnection<Sqlite>,
+        moderator: &str,
+        moderator_role: Role,
+        target_id: &str,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        act(
+            connection,
+            LockMap::new(Duration::from_secs(1)),
+            StyleCache::new(1, 1),
+            FrontPage::new(),
+            moderator,
+            moderator_role,
+            model::ModerationAction::SuspendAccount,
+            target_id,
+            "",
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn moderators_can_only_suspend_accounts_below_them() {
+        let mut connection = schema::memory().await.acquire().await.unwrap();
+
+        sqlx::query(
+            "INSERT INTO accounts (account_id) VALUES ('admin'), ('first'), ('second'), ('user');
+             INSERT INTO roles VALUES ('admin', 'admin'), ('first', 'moderator'),
+                                      ('second', 'moderator');",
+        )
+        .execute(&mut *connection)
+        .await
+        .unwrap();
+
+        assert_eq!(
+            suspend(&mut connection, "first", Role::Moderator, "admin")
+                .await
+                .unwrap_err()
+                .0,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            suspend(&mut connection, "first", Role::Moderator, "second")
+                .await
+                .unwrap_err()
+                .0,
+            StatusCode::FORBIDDEN
+        );
+        suspend(&mut connection, "first", Role::Moderator, "user")
+            .await
+            .unwrap();
+        suspend(&mut connection, "admin", Role::Admin, "second")
+            .await
+            .unwrap();
+
+        let suspended = sqlx::query_scalar::<_, String>(
+            "SELECT account_id FROM suspensions ORDER BY account_id",
+        )
+        .fetch_all(&mut *connection)
+        .await
+        .unwrap();
+        assert_eq!(suspended, ["second", "user"]);
+    }
+
+    #[tokio::test]
+    async fn actions_only_resolve_reports_of_their_kind() {
+        let mut connection = schema::memory().await.acquire().await.unwrap();
+
+        // a link and a style which happen to share an id
+        sqlx::query(
+            "INSERT INTO links VALUES ('shared', 'https://example.com', 'a link');
+             INSERT INTO styles VALUES ('shared', 'a style', 'creator', '');",
+        )
+        .execute(&mut *connection)
+        .await
+        .unwrap();
+
+        report(
+            &mut connection,
+            "reporter",
+            model::ReportKind::Link,
+            "shared",
+            "",
+        )
+        .await
+        .unwrap();
+        report(
+            &mut connection,
+            "reporter",
+            model::ReportKind::Style,
+            "shared",
+            "",
+        )
+        .await
+        .unwrap();
+
+        act(
+            &mut connection,
+            LockMap::new(Duration::from_secs(1)),
+            StyleCache::new(1, 1),
+            FrontPage::new(),
+            "moderator",
+            Role::Moderator,
+            model::ModerationAction::HideLink,
+            "shared",
+            "",
+        )
+        .await
+        .unwrap();
+
+        let open = sqlx::query_scalar::<_, String>("SELECT kind FROM reports WHERE NOT resolved")
+            .fetch_all(&mut *connection)
+            .await
+            .unwrap();
+        assert_eq!(open, ["style"]);
+    }
+}
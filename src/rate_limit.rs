@@ -0,0 +1,573 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use axum::{
+    extract::ConnectInfo,
+    headers::{Cookie, HeaderMapExt},
+    http::{header::RETRY_AFTER, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use sqlx::SqlitePool;
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, SocketAddr},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tower::{Layer, Service};
+use tracing::{debug, warn};
+
+use crate::configuration::{
+    Bucket as BucketConfiguration, RateLimit as RateLimitConfiguration,
+    RateLimits as RateLimitsConfiguration,
+};
+
+/// A class of routes sharing a set of rate limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteClass {
+    /// Creating accounts
+    Signup,
+
+    /// Posting links
+    Post,
+
+    /// Importing links
+    Import,
+
+    /// Uploading styles
+    PostStyle,
+
+    /// Rating links
+    Rating,
+}
+
+/// The state of a single token bucket
+struct TokenBucket {
+    /// The number of requests that can currently be made, which may be fractional while the
+    /// bucket is refilling
+    tokens: f64,
+
+    /// The time `tokens` was last brought up to date
+    updated: Instant,
+
+    /// The bucket's key in [`BucketMap::filling`]
+    full_at: (Instant, u64),
+}
+
+/// The buckets of a [`Buckets`], alongside the order in which they will have refilled
+#[derive(Default)]
+struct BucketMap {
+    buckets: HashMap<String, TokenBucket>,
+
+    /// The key of each bucket, keyed by the time at which it will be full again (which only
+    /// changes when a token is taken) and a sequence number to tell apart buckets becoming
+    /// full at the same time
+    filling: BTreeMap<(Instant, u64), String>,
+    sequence: u64,
+}
+
+/// A set of token buckets sharing a configuration, keyed by the client they belong to
+///
+/// The number of buckets is bounded, with the bucket which is closest to being full evicted
+/// to make room for another. Full buckets are indistinguishable from missing ones, and
+/// otherwise the client which is forgotten is the one that loses the least from it, which a
+/// client being limited can't change by making requests from other addresses
+struct Buckets {
+    configuration: BucketConfiguration,
+    capacity: usize,
+    buckets: Mutex<BucketMap>,
+}
+
+impl Buckets {
+    fn new(configuration: BucketConfiguration, capacity: usize) -> Self {
+        Self {
+            configuration,
+            capacity,
+            buckets: Mutex::new(BucketMap::default()),
+        }
+    }
+
+    /// Bring a bucket's tokens up to date, returning the number available
+    fn refill(&self, bucket: &mut TokenBucket, now: Instant) -> f64 {
+        let refilled = now.saturating_duration_since(bucket.updated).as_secs_f64()
+            / self.configuration.period.as_secs_f64();
+
+        bucket.tokens = (bucket.tokens + refilled).min(f64::from(self.configuration.burst));
+        bucket.updated = now;
+
+        bucket.tokens
+    }
+
+    /// Determine whether the client may make a request, returning how long it must wait
+    /// otherwise. No token is taken
+    fn check(&self, buckets: &mut BucketMap, key: &str, now: Instant) -> Result<(), Duration> {
+        let Some(bucket) = buckets.buckets.get_mut(key) else {
+            return if self.configuration.burst > 0 {
+                Ok(())
+            } else {
+                Err(self.configuration.period)
+            };
+        };
+
+        let tokens = self.refill(bucket, now);
+
+        if tokens >= 1.0 {
+            Ok(())
+        } else {
+            Err(self.configuration.period.mul_f64(1.0 - tokens))
+        }
+    }
+
+    /// Take a token from the client's bucket, which [`Buckets::check`] must have said was
+    /// possible
+    fn take(&self, buckets: &mut BucketMap, key: &str, now: Instant) {
+        let burst = f64::from(self.configuration.burst);
+
+        let (tokens, updated) = if let Some(bucket) = buckets.buckets.remove(key) {
+            buckets.filling.remove(&bucket.full_at);
+
+            (bucket.tokens - 1.0, bucket.updated)
+        } else {
+            if buckets.buckets.len() >= self.capacity
+                && let Some((_, closest)) = buckets.filling.pop_first()
+            {
+                debug!("evicting the rate limit bucket for {}", closest);
+
+                buckets.buckets.remove(&closest);
+            }
+
+            (burst - 1.0, now)
+        };
+
+        buckets.sequence += 1;
+        let full_at = (
+            updated + self.configuration.period.mul_f64(burst - tokens),
+            buckets.sequence,
+        );
+
+        buckets.filling.insert(full_at, key.to_string());
+        buckets.buckets.insert(
+            key.to_string(),
+            TokenBucket {
+                tokens,
+                updated,
+                full_at,
+            },
+        );
+    }
+}
+
+/// The rate limits applied to a single class of routes
+struct ClassLimiter {
+    per_ip: Option<Buckets>,
+    per_account: Option<Buckets>,
+}
+
+impl ClassLimiter {
+    fn new(configuration: &RateLimitConfiguration, capacity: usize) -> Self {
+        Self {
+            per_ip: configuration
+                .per_ip
+                .clone()
+                .map(|bucket| Buckets::new(bucket, capacity)),
+            per_account: configuration
+                .per_account
+                .clone()
+                .map(|bucket| Buckets::new(bucket, capacity)),
+        }
+    }
+
+    /// Take a token from each of the client's buckets if all of them have one available,
+    /// returning how long the client must wait otherwise
+    fn acquire(&self, ip: Option<IpAddr>, account_id: Option<&str>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let ip = ip.map(|ip| ip.to_string());
+
+        let limits = [
+            self.per_ip.as_ref().zip(ip.as_deref()),
+            self.per_account.as_ref().zip(account_id),
+        ];
+
+        // the buckets stay locked between checking and taking so that concurrent requests
+        // can't both take the last token
+        let mut locked = limits
+            .iter()
+            .flatten()
+            .map(|(buckets, key)| (*buckets, *key, buckets.buckets.lock()))
+            .collect::<Vec<_>>();
+
+        for (buckets, key, locked) in &mut locked {
+            buckets.check(locked, key, now)?;
+        }
+
+        for (buckets, key, locked) in &mut locked {
+            buckets.take(locked, key, now);
+        }
+
+        Ok(())
+    }
+}
+
+/// The rate limits applied to every class of routes
+pub struct RateLimiter {
+    sqlite: SqlitePool,
+    trust_forwarded_for: bool,
+    signup: ClassLimiter,
+    post: ClassLimiter,
+    import: ClassLimiter,
+    post_style: ClassLimiter,
+    rating: ClassLimiter,
+}
+
+impl RateLimiter {
+    pub fn new(configuration: &RateLimitsConfiguration, sqlite: SqlitePool) -> &'static Self {
+        let capacity = configuration.max_tracked_clients;
+
+        Box::leak(Box::new(Self {
+            sqlite,
+            trust_forwarded_for: configuration.trust_forwarded_for,
+            signup: ClassLimiter::new(&configuration.signup, capacity),
+            post: ClassLimiter::new(&configuration.post, capacity),
+            import: ClassLimiter::new(&configuration.import, capacity),
+            post_style: ClassLimiter::new(&configuration.post_style, capacity),
+            rating: ClassLimiter::new(&configuration.rating, capacity),
+        }))
+    }
+
+    /// Create a layer applying the rate limits of a class of routes
+    pub fn layer(&'static self, class: RouteClass) -> RateLimitLayer {
+        RateLimitLayer {
+            limiter: self,
+            class,
+        }
+    }
+
+    fn class(&self, class: RouteClass) -> &ClassLimiter {
+        match class {
+            RouteClass::Signup => &self.signup,
+            RouteClass::Post => &self.post,
+            RouteClass::Import => &self.import,
+            RouteClass::PostStyle => &self.post_style,
+            RouteClass::Rating => &self.rating,
+        }
+    }
+
+    /// Determine the address of the client making a request
+    ///
+    /// Behind a reverse proxy, the address the request came from is the proxy's, so the
+    /// address the proxy appended to `X-Forwarded-For` is used instead if it is trusted
+    fn client_ip<B>(&self, request: &Request<B>) -> Option<IpAddr> {
+        if self.trust_forwarded_for
+            && let Some(forwarded_for) = request
+                .headers()
+                .get_all("x-forwarded-for")
+                .iter()
+                .next_back()
+                .and_then(|value| value.to_str().ok())
+        {
+            return forwarded_for
+                .rsplit(',')
+                .next()
+                .and_then(|ip| ip.trim().parse().ok());
+        }
+
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip())
+    }
+
+    /// Determine the account making a request, if the class of routes is limited per account
+    ///
+    /// The `flock.id` cookie is whatever the client sends, so it is only used if the account
+    /// exists. Otherwise, a client could make up a new id (and be given a new bucket) for each
+    /// request, leaving it limited only by its address
+    async fn client_account(
+        &self,
+        class: &ClassLimiter,
+        account_id: Option<String>,
+    ) -> Option<String> {
+        class.per_account.as_ref()?;
+
+        let account_id = account_id?;
+
+        match sqlx::query_scalar!("SELECT 1 FROM accounts WHERE account_id = ?", account_id)
+            .fetch_optional(&self.sqlite)
+            .await
+        {
+            Ok(Some(_)) => Some(account_id),
+            Ok(None) => {
+                debug!(
+                    "rate limiting a request from nonexistent account {} by address",
+                    account_id
+                );
+
+                None
+            }
+            Err(error) => {
+                warn!(
+                    "unable to check if account {} exists, rate limiting it by address: {}",
+                    account_id, error
+                );
+
+                None
+            }
+        }
+    }
+}
+
+/// A layer applying the rate limits of a class of routes to a service
+#[derive(Clone, Copy)]
+pub struct RateLimitLayer {
+    limiter: &'static RateLimiter,
+    class: RouteClass,
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limiter: self.limiter,
+            class: self.class,
+        }
+    }
+}
+
+/// A service refusing requests from clients which have exceeded the rate limits of a class of
+/// routes with `429 Too Many Requests`
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    limiter: &'static RateLimiter,
+    class: RouteClass,
+}
+
+impl<S, B> Service<Request<B>> for RateLimit<S>
+where
+    S: Service<Request<B>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send,
+    B: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let limiter = self.limiter;
+        let class = limiter.class(self.class);
+
+        // the service which was polled for readiness is the one that must be called, so a
+        // clone of it is left in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let ip = limiter.client_ip(&request);
+        let cookies = request.headers().typed_get::<Cookie>();
+        let account_id = cookies
+            .as_ref()
+            .and_then(|cookies| cookies.get("flock.id"))
+            .map(str::to_string);
+
+        Box::pin(async move {
+            let account_id = limiter.client_account(class, account_id).await;
+
+            match class.acquire(ip, account_id.as_deref()) {
+                Ok(()) => inner.call(request).await,
+                Err(retry_after) => {
+                    debug!(
+                        "rate limiting a request to {} from {:?} / account {:?}",
+                        request.uri(),
+                        ip,
+                        account_id
+                    );
+
+                    Ok((
+                        StatusCode::TOO_MANY_REQUESTS,
+                        [(
+                            RETRY_AFTER,
+                            retry_after.as_secs_f64().ceil().max(1.0).to_string(),
+                        )],
+                        "you're doing that too often, try again later",
+                    )
+                        .into_response())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BucketMap, Buckets, ClassLimiter};
+    use crate::configuration::{Bucket, RateLimit};
+    use std::time::{Duration, Instant};
+
+    fn buckets(burst: u32, capacity: usize) -> Buckets {
+        Buckets::new(
+            Bucket {
+                burst,
+                period: Duration::from_secs(10),
+            },
+            capacity,
+        )
+    }
+
+    /// Take a token from a client's bucket if [`Buckets::check`] allows it
+    fn request(
+        buckets: &Buckets,
+        map: &mut BucketMap,
+        key: &str,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        buckets.check(map, key, now)?;
+        buckets.take(map, key, now);
+
+        Ok(())
+    }
+
+    #[test]
+    fn bursts_then_refills() {
+        let buckets = buckets(2, 16);
+        let mut map = BucketMap::default();
+        let start = Instant::now();
+
+        assert_eq!(request(&buckets, &mut map, "a", start), Ok(()));
+        assert_eq!(request(&buckets, &mut map, "a", start), Ok(()));
+        assert_eq!(
+            request(&buckets, &mut map, "a", start),
+            Err(Duration::from_secs(10))
+        );
+
+        // other clients have their own buckets
+        assert_eq!(request(&buckets, &mut map, "b", start), Ok(()));
+
+        let later = start + Duration::from_secs(5);
+        assert_eq!(
+            request(&buckets, &mut map, "a", later),
+            Err(Duration::from_secs(5))
+        );
+        assert_eq!(
+            request(&buckets, &mut map, "a", later + Duration::from_secs(5)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn refills_up_to_the_burst() {
+        let buckets = buckets(2, 16);
+        let mut map = BucketMap::default();
+        let start = Instant::now();
+
+        request(&buckets, &mut map, "a", start).unwrap();
+        request(&buckets, &mut map, "a", start).unwrap();
+
+        let later = start + Duration::from_secs(60 * 60);
+        assert_eq!(request(&buckets, &mut map, "a", later), Ok(()));
+        assert_eq!(request(&buckets, &mut map, "a", later), Ok(()));
+        assert!(request(&buckets, &mut map, "a", later).is_err());
+    }
+
+    #[test]
+    fn empty_bursts_refuse_everything() {
+        let buckets = buckets(0, 16);
+        let mut map = BucketMap::default();
+
+        assert_eq!(
+            request(&buckets, &mut map, "a", Instant::now()),
+            Err(Duration::from_secs(10))
+        );
+        assert!(map.buckets.is_empty());
+    }
+
+    #[test]
+    fn evicts_the_bucket_closest_to_full() {
+        let buckets = buckets(3, 2);
+        let mut map = BucketMap::default();
+        let start = Instant::now();
+
+        // "a" is limited, while "b" has only made one request
+        for _ in 0..3 {
+            request(&buckets, &mut map, "a", start).unwrap();
+        }
+        request(&buckets, &mut map, "b", start).unwrap();
+
+        request(&buckets, &mut map, "c", start).unwrap();
+        assert!(!map.buckets.contains_key("b"));
+
+        // making requests from other addresses doesn't get "a" forgotten
+        for client in 0..64 {
+            let now = start + Duration::from_millis(client);
+            request(&buckets, &mut map, &client.to_string(), now).unwrap();
+
+            assert_eq!(map.buckets.len(), 2);
+            assert!(request(&buckets, &mut map, "a", now).is_err());
+        }
+
+        assert_eq!(map.buckets.len(), map.filling.len());
+    }
+
+    #[test]
+    fn full_buckets_are_evicted_first() {
+        let buckets = buckets(3, 2);
+        let mut map = BucketMap::default();
+        let start = Instant::now();
+
+        request(&buckets, &mut map, "a", start).unwrap();
+        for _ in 0..3 {
+            request(&buckets, &mut map, "b", start + Duration::from_secs(5)).unwrap();
+        }
+
+        // "a" has been full again since its request was made
+        request(&buckets, &mut map, "c", start + Duration::from_secs(60)).unwrap();
+        assert!(!map.buckets.contains_key("a"));
+        assert!(map.buckets.contains_key("b"));
+    }
+
+    #[test]
+    fn tokens_are_only_taken_if_every_bucket_allows_it() {
+        let limiter = ClassLimiter::new(
+            &RateLimit {
+                per_ip: Some(Bucket {
+                    burst: 2,
+                    period: Duration::from_secs(60),
+                }),
+                per_account: Some(Bucket {
+                    burst: 1,
+                    period: Duration::from_secs(60),
+                }),
+            },
+            16,
+        );
+        let ip = Some("127.0.0.1".parse().unwrap());
+
+        assert!(limiter.acquire(ip, Some("account")).is_ok());
+        assert!(limiter.acquire(ip, Some("account")).is_err());
+
+        // the address' second token wasn't taken by the refused request
+        assert!(limiter.acquire(ip, None).is_ok());
+        assert!(limiter.acquire(ip, None).is_err());
+    }
+}
@@ -23,15 +23,15 @@ use askama::Template;
 use axum::{
     body::Bytes,
     extract::{Form, Multipart, Path, Query},
-    headers::Cookie,
+    headers::{Cookie, Header, IfModifiedSince, IfNoneMatch, LastModified},
     http::{header::SET_COOKIE, Response, StatusCode},
     response::{AppendHeaders, IntoResponse, Redirect},
     Extension, TypedHeader,
 };
 use http_body::combinators::UnsyncBoxBody;
-use instant_glicko_2::{algorithm::ScaledPlayerResult, ScaledRating};
+use instant_glicko_2::ScaledRating;
 use regex::Regex;
-use sqlx::{pool::PoolConnection, Sqlite, SqlitePool};
+use sqlx::{Connection, SqliteConnection, SqlitePool};
 use std::{
     cmp::Ordering,
     collections::{HashMap, HashSet},
@@ -42,14 +42,26 @@ use tracing::{debug, trace};
 use ulid::Ulid;
 
 use crate::{
+    accounts,
     configuration::{
         Algorithm as AlgorithmConfiguration, Http as HttpConfiguration,
-        Routes as RouteConfiguration,
+        Routes as RouteConfiguration, SignupMode,
     },
+    css,
     feed,
+    feed_tokens::{self, SigningKey},
+    front_page::FrontPage,
+    import,
     locks::LockMap,
     model,
+    moderation,
     rand::pcg_thread_rng,
+    scores,
+    search,
+    signup::{self, ChallengeKey},
+    styles::{self, CreatorKey, StyleCache},
+    syndication,
+    tags,
     templates::{self, Link},
     util::{self, ScaledRatingData, ScaledRatingWrapper},
 };
@@ -57,7 +69,7 @@ use crate::{
 static TAG_DELIMITER_REGEX: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"\s*,\s*").expect("unable to compile a regex"));
 
-static TAG_REGEX: LazyLock<Regex> =
+pub static TAG_REGEX: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"\A[[:alnum:]\-]+\z").expect("unable to compile a regex"));
 
 /// Shorthand for checking if the feature gate is enabled
@@ -88,16 +100,25 @@ pub fn string_to_tags(tags: &mut str) -> Result<HashSet<&'_ str>, (StatusCode, &
 //TODO(superwhiskers): remove when the heuristics are corrected and/or fix
 #[allow(clippy::needless_pass_by_ref_mut)]
 pub async fn retrieve_tags_from_string(
-    connection: &mut PoolConnection<Sqlite>,
+    connection: &mut SqliteConnection,
     mut names: String,
 ) -> Result<Vec<String>, (StatusCode, &'static str)> {
     trace!("retrieving tags from \"{}\"", names);
 
     let names = string_to_tags(&mut names)?;
-    let mut ids = Vec::with_capacity(names.len());
 
     debug!("parsed tag names as {:?}", names);
 
+    retrieve_tags(connection, names).await
+}
+
+/// Look up the ids of the tags with the provided names, creating any which don't exist yet
+pub async fn retrieve_tags(
+    connection: &mut SqliteConnection,
+    names: HashSet<&str>,
+) -> Result<Vec<String>, (StatusCode, &'static str)> {
+    let mut ids = Vec::with_capacity(names.len());
+
     for name in names {
         let id = Ulid::with_source(&mut pcg_thread_rng()).to_string();
 
@@ -106,7 +127,7 @@ pub async fn retrieve_tags_from_string(
             id,
             name
         )
-        .execute(&mut **connection)
+        .execute(&mut *connection)
         .await
         .map_err(|_| {
             (
@@ -122,7 +143,7 @@ pub async fn retrieve_tags_from_string(
                 r#"SELECT tag_id as "tag_id!" FROM tags WHERE name = ?"#,
                 name
             )
-            .fetch_one(&mut **connection)
+            .fetch_one(&mut *connection)
             .await
             .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to query the db"))?,
         );
@@ -131,11 +152,82 @@ pub async fn retrieve_tags_from_string(
     Ok(ids)
 }
 
+/// Insert a new link carrying the provided tags, returning its id
+///
+/// If the link was posted by an account, this is recorded so that it can be included in the
+/// account's data export
+pub async fn create_link(
+    connection: &mut SqliteConnection,
+    account_id: Option<&str>,
+    link: &str,
+    description: &str,
+    tags: Vec<String>,
+) -> Result<String, (StatusCode, &'static str)> {
+    let link_id = Ulid::with_source(&mut pcg_thread_rng()).to_string();
+
+    debug!("link id generated: {}", link_id);
+
+    //TODO(superwhiskers): this and the similar loop used in account creation (and likely
+    //                     account tag modification) could be factored out
+    for tag in tags {
+        let score = ScaledRating::new(
+            0.0,
+            350.0 / instant_glicko_2::constants::RATING_SCALING_RATIO,
+            0.06,
+        );
+        let last_period = SystemTime::UNIX_EPOCH
+            .elapsed()
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to calculate the amount of time that has passed since the unix epoch",
+                )
+            })?
+            .as_secs();
+
+        scores::insert(connection, &link_id, &tag, score, last_period).await?;
+    }
+
+    sqlx::query!(
+        "INSERT INTO links (link_id, link, description) VALUES (?, ?, ?)",
+        link_id,
+        link,
+        description
+    )
+    .execute(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to insert the link into the db",
+        )
+    })?;
+
+    if let Some(account_id) = account_id {
+        sqlx::query!(
+            "INSERT INTO posts (link_id, account_id) VALUES (?, ?)",
+            link_id,
+            account_id
+        )
+        .execute(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to record the link's poster",
+            )
+        })?;
+    }
+
+    Ok(link_id)
+}
+
 pub async fn get_index(
     Extension(style_id): Extension<model::StyleId>,
     Extension(sqlite): Extension<SqlitePool>,
     Extension(algorithm_configuration): Extension<AlgorithmConfiguration>,
     Extension(lock_map): Extension<&'static LockMap>,
+    Extension(front_page): Extension<&'static FrontPage>,
     cookies: Option<TypedHeader<Cookie>>,
 ) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
     trace!("index requested, cookies: {:?}", cookies);
@@ -153,94 +245,32 @@ pub async fn get_index(
             )
         })?;
 
-        if let Some(feed) = sqlx::query_scalar!(
-            r#"SELECT feed as "feed!" FROM accounts WHERE account_id = ?"#,
-            account_id
+        if let Some(feed) = feed::load_feed(
+            &algorithm_configuration,
+            &sqlite,
+            lock_map,
+            &mut connection,
+            account_id,
         )
-        .fetch_optional(&mut *connection)
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to query the db"))?
+        .await?
         {
-            let mut feed = rmp_serde::from_slice::<model::Feed>(&feed).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to deserialize the feed"))?;
-
-            debug!("deserialized feed for {}: {:?}", account_id, feed);
-
-            if (SystemTime::UNIX_EPOCH + Duration::from_secs(feed.refreshed))
-                .elapsed()
-                .map_err(|_| {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "unable to calculate the amount of time that has passed since the last time the feed was refreshed",
-                    )
-                })?
-                    > algorithm_configuration.feed_refresh_period {
-                trace!("generating new feed for {}", account_id);
-
-                trace!("locking the account's tags");
-
-                let _tag_lock = lock_map.lock(account_id).ok_or((
-                    StatusCode::SERVICE_UNAVAILABLE,
-                    "a lock is currently held on your account's tag information. try again in a few seconds",
-                ))?;
-
-                feed = model::Feed {
-                    links: feed::generate_feed(&algorithm_configuration, sqlite.acquire().await.map_err(|_| {
-                        (
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            "unable to acquire a db connection",
-                        )
-                    })?, account_id).await?,
-                    refreshed: SystemTime::UNIX_EPOCH
-                        .elapsed()
-                        .map_err(|_| {
-                            (
-                                StatusCode::INTERNAL_SERVER_ERROR,
-                                "unable to calculate the amount of time that has passed since the unix epoch",
-                            )
-                        })?
-                        .as_secs(),
-                };
-
-                debug!("new feed for {}: {:?}", account_id, feed);
-
-                let serialized_feed = rmp_serde::to_vec(&feed)
-                    .map_err(|_| {
-                        (
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            "unable to serialize the new feed",
-                        )
-                    })?;
-
-                sqlx::query!(
-                    r"UPDATE accounts SET feed = ? WHERE account_id = ?",
-                    serialized_feed,
-                    account_id
-                )
-                .execute(&mut *connection)
-                .await
-                .map_err(|_| {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "unable to update the feed",
-                    )
-                })?;
-            }
-
             let mut links = Vec::with_capacity(feed.links.len());
             for (link_id, overall_score) in feed.links {
-                let description = sqlx::query!(
-                    r#"SELECT description as "description!" FROM links WHERE link_id = ?"#,
+                // links hidden since the feed was generated are skipped
+                let Some(description) = sqlx::query_scalar!(
+                    r#"SELECT description as "description!" FROM links WHERE link_id = ? AND link_id NOT IN (SELECT link_id FROM hidden_links)"#,
                     link_id,
                 )
-                .fetch_one(&mut *connection)
+                .fetch_optional(&mut *connection)
                 .await
                 .map_err(|_| {
                     (
                         StatusCode::INTERNAL_SERVER_ERROR,
                         "unable to query for a link's information",
                     )
-                })?
-                .description;
+                })? else {
+                    continue;
+                };
 
                 let (visited, rated) = sqlx::query!(
                     r#"SELECT rated as "rated!" FROM seen WHERE account_id = ? AND link_id = ?"#,
@@ -272,9 +302,14 @@ pub async fn get_index(
                 templates::Index {
                     style_id,
                     account: Some(templates::Account {
-                        id: account_id.to_string(),
                         links,
+                        feed_token: feed_tokens::account_tokens(&mut connection, account_id)
+                            .await?
+                            .into_iter()
+                            .next()
+                            .map(|feed_token| feed_token.token),
                     }),
+                    front_page: None,
                 },
             ))
         } else {
@@ -288,353 +323,363 @@ pub async fn get_index(
 
         Ok((
             [("Content-Type", "application/xhtml+xml"), ("Cache-Control", "private, no-store")],
-            templates::Index { style_id, account: None },
+            templates::Index {
+                style_id,
+                account: None,
+                front_page: Some(front_page.get()),
+            },
         ))
     }
 }
 
-pub async fn get_login(
+pub async fn get_feed_explanation(
     Extension(style_id): Extension<model::StyleId>,
-    Query(model::Login { redirect_to }): Query<model::Login>,
+    Extension(sqlite): Extension<SqlitePool>,
     cookies: Option<TypedHeader<Cookie>>,
-) -> Response<UnsyncBoxBody<Bytes, axum::Error>> {
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("feed explanation requested, cookies: {:?}", cookies);
+
     coz_progress!();
 
     if let Some(cookies) = cookies
-        && cookies.get("flock.id").is_some() {
-        if let Some(url) = redirect_to {
-            Redirect::to(&url)
-        } else {
-            Redirect::to("/")
-        }
-        .into_response()
-    } else {
-        (
-            [("Content-Type", "application/xhtml+xml")],
-            templates::Login { style_id, redirect_to },
-        )
-        .into_response()
-    }
-}
+        && let Some(account_id) = cookies.get("flock.id") {
+        debug!("account {} requesting an explanation of their feed", account_id);
 
-pub async fn post_login(
-    Extension(sqlite): Extension<SqlitePool>,
-    Extension(route_configuration): Extension<RouteConfiguration>,
-    Query(model::Login { redirect_to }): Query<model::Login>,
-    Form(model::PostLogin { account_id }): Form<model::PostLogin>,
-) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
-    trace!("login post-ed with account id {}", account_id);
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
 
-    coz_progress!();
+        let feed = feed::read_feed(&mut connection, account_id)
+            .await?
+            .ok_or((
+                StatusCode::BAD_REQUEST,
+                "the requested account does not exist",
+            ))?;
 
-    let mut connection = sqlite.acquire().await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "unable to acquire a db connection",
-        )
-    })?;
+        // feeds generated before explanations were recorded have none
+        let mut explanations = feed.explanations.into_iter();
 
-    if sqlx::query!(
-        r"SELECT COUNT(1) as count FROM accounts WHERE account_id = ?",
-        account_id
-    )
-    .fetch_one(&mut *connection)
-    .await
-    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to query the db"))?
-    .count
-        == 1
-    {
-        Ok((
-            AppendHeaders([(
-                SET_COOKIE,
-                if route_configuration.secure_cookies {
-                    format!(
-                        "flock.id={}; SameSite=Strict; Expires={}; Max-Age=172800; HttpOnly; Secure",
-                        account_id,
-                        // lmao old browsers but why the heck not, it's hardly any effort
-                        httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(172800))
-                    )
-                } else {
-                    format!(
-                        "flock.id={}; SameSite=Strict; Expires={}; Max-Age=172800; HttpOnly",
-                        account_id,
-                        // lmao old browsers but why the heck not, it's hardly any effort
-                        httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(172800))
-                    )
-                },
-            )]),
-            if let Some(url) = redirect_to {
-                Redirect::to(&url)
+        let mut links = Vec::with_capacity(feed.links.len());
+        for (link_id, overall_score) in feed.links {
+            let explanation = explanations.next();
+
+            // links hidden since the feed was generated are skipped
+            let Some(description) = sqlx::query_scalar!(
+                r#"SELECT description as "description!" FROM links WHERE link_id = ? AND link_id NOT IN (SELECT link_id FROM hidden_links)"#,
+                link_id,
+            )
+            .fetch_optional(&mut *connection)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to query for a link's information",
+                )
+            })? else {
+                continue;
+            };
+
+            let explanation = if let Some(explanation) = explanation {
+                let mut tags = Vec::with_capacity(explanation.tags.len());
+                for tag in explanation.tags {
+                    tags.push(templates::ExplainedTag {
+                        name: sqlx::query_scalar!(
+                            r#"SELECT name as "name!" FROM tags WHERE tag_id = ?"#,
+                            tag.tag_id
+                        )
+                        .fetch_one(&mut *connection)
+                        .await
+                        .map_err(|_| {
+                            (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                "unable to query for a tag's name",
+                            )
+                        })?,
+                        importance: format!("{:.1}%", tag.importance.rating * 100.0),
+                        score: tag.score.to_string(),
+                    });
+                }
+
+                Some(templates::LinkExplanation {
+                    segment: explanation.segment + 1,
+                    tags,
+                })
             } else {
-                Redirect::to("/")
-            },
+                None
+            };
+
+            links.push(templates::ExplainedLink {
+                id: link_id,
+                description,
+                overall_score: overall_score.to_string(),
+                explanation,
+            });
+        }
+
+        Ok((
+            [("Content-Type", "application/xhtml+xml"), ("Cache-Control", "private, no-store")],
+            templates::FeedExplanation { style_id, links },
         ))
     } else {
         Err((
             StatusCode::BAD_REQUEST,
-            "the requested account does not exist",
+            "you are not logged in",
         ))
     }
 }
 
-pub async fn get_signup(Extension(style_id): Extension<model::StyleId>) -> impl IntoResponse {
-    coz_progress!();
-
-    (
-        [("Content-Type", "application/xhtml+xml")],
-        templates::Signup { style_id },
-    )
-}
-
-pub async fn post_signup(
+pub async fn get_feed_history(
+    Extension(style_id): Extension<model::StyleId>,
     Extension(sqlite): Extension<SqlitePool>,
-    Extension(route_configuration): Extension<RouteConfiguration>,
-    Form(model::PostSignup { tags }): Form<model::PostSignup>,
+    cookies: Option<TypedHeader<Cookie>>,
 ) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
-    trace!("signup post-ed, tags: \"{}\"", tags);
+    trace!("feed history requested, cookies: {:?}", cookies);
 
     coz_progress!();
 
-    let mut connection = sqlite.acquire().await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "unable to acquire a db connection",
-        )
-    })?;
-
-    let account_id = Ulid::with_source(&mut pcg_thread_rng()).to_string();
-
-    debug!("account id generated: {}", account_id);
-
-    for tag in retrieve_tags_from_string(&mut connection, tags).await? {
-        let score = ScaledRating::new(
-            0.0,
-            350.0 / instant_glicko_2::constants::RATING_SCALING_RATIO,
-            0.06,
-        );
-        let last_period = SystemTime::UNIX_EPOCH
-            .elapsed()
-            .map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to calculate the amount of time that has passed since the unix epoch",
-                )
-            })?
-            .as_secs();
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        debug!("account {} requesting their feed history", account_id);
 
-        let score = rmp_serde::to_vec(&model::Score {
-            score,
-            last_period,
-            result_queue: vec![],
-        })
-        .map_err(|_| {
+        let mut connection = sqlite.acquire().await.map_err(|_| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to convert data to messagepack",
+                "unable to acquire a db connection",
             )
         })?;
 
-        sqlx::query!(
-            r"INSERT INTO scores (id, tag_id, score) VALUES (?, ?, ?)",
-            account_id,
-            tag,
-            score,
+        if sqlx::query_scalar!(
+            r#"SELECT 1 FROM accounts WHERE account_id = ?"#,
+            account_id
         )
-        .execute(&mut *connection)
+        .fetch_optional(&mut *connection)
         .await
         .map_err(|_| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to insert a tag score into the db",
+                "unable to check if an account exists",
             )
-        })?;
-    }
-
-    let feed = rmp_serde::to_vec(&model::Feed {
-        refreshed: 0,
-        links: Default::default(),
-    })
-    .map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "unable to convert data to messagepack",
-        )
-    })?;
+        })?
+        .is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "the requested account does not exist",
+            ));
+        }
 
-    sqlx::query!(
-        r"INSERT INTO accounts (account_id, feed, style_id) VALUES (?, ?, null)",
-        account_id,
-        feed
-    )
-    .execute(&mut *connection)
-    .await
-    .map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "unable to insert a new account into the db",
-        )
-    })?;
+        let history = feed::read_history(&mut connection, account_id).await?;
 
-    Ok((
-        AppendHeaders([(
-            SET_COOKIE,
-            if route_configuration.secure_cookies {
-                format!(
-                    "flock.id={}; SameSite=Strict; Expires={}; Max-Age=172800; HttpOnly; Secure",
-                    account_id,
-                    // lmao old browsers but why the heck not, it's hardly any effort
-                    httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(172800))
-                )
-            } else {
-                format!(
-                    "flock.id={}; SameSite=Strict; Expires={}; Max-Age=172800; HttpOnly",
-                    account_id,
-                    // lmao old browsers but why the heck not, it's hardly any effort
-                    httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(172800))
+        let mut feeds = Vec::with_capacity(history.len());
+        for (i, past_feed) in history.into_iter().enumerate() {
+            let mut links = Vec::with_capacity(past_feed.links.len());
+            for (link_id, overall_score) in past_feed.links {
+                // links hidden since the feed was generated are skipped
+                let Some(description) = sqlx::query_scalar!(
+                    r#"SELECT description as "description!" FROM links WHERE link_id = ? AND link_id NOT IN (SELECT link_id FROM hidden_links)"#,
+                    link_id,
                 )
-            },
-        )]),
-        Redirect::to("/welcome"),
-    ))
-}
+                .fetch_optional(&mut *connection)
+                .await
+                .map_err(|_| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "unable to query for a link's information",
+                    )
+                })? else {
+                    continue;
+                };
 
-pub async fn get_logout(
-    cookies: Option<TypedHeader<Cookie>>,
-    Extension(route_configuration): Extension<RouteConfiguration>,
-) -> Response<UnsyncBoxBody<Bytes, axum::Error>> {
-    trace!("logout requested, cookies: {:?}", cookies);
+                links.push(templates::PastFeedLink {
+                    id: link_id,
+                    description,
+                    overall_score: overall_score.to_string(),
+                });
+            }
 
-    coz_progress!();
+            feeds.push(templates::PastFeed {
+                generated: humantime::format_rfc3339_seconds(
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(past_feed.refreshed),
+                )
+                .to_string(),
+                // feeds are read from the most recent
+                current: i == 0,
+                links,
+            });
+        }
 
-    if let Some(cookies) = cookies
-        && let Some(account_id) = cookies.get("flock.id") {
-        (
-            AppendHeaders([
-                (
-                    SET_COOKIE,
-                    if route_configuration.secure_cookies {
-                        format!(
-                            "flock.id={}; SameSite=Strict; Expires={}; Max-Age=0; HttpOnly; Secure",
-                            account_id,
-                            // lmao old browsers but why the heck not, it's hardly any effort
-                            httpdate::fmt_http_date(SystemTime::UNIX_EPOCH)
-                        )
-                    } else {
-                        format!(
-                            "flock.id={}; SameSite=Strict; Expires={}; Max-Age=0; HttpOnly",
-                            account_id,
-                            // lmao old browsers but why the heck not, it's hardly any effort
-                            httpdate::fmt_http_date(SystemTime::UNIX_EPOCH)
-                        )
-                    },
-                ),
-            ]),
-            Redirect::to("/"),
-        )
-            .into_response()
+        Ok((
+            [("Content-Type", "application/xhtml+xml"), ("Cache-Control", "private, no-store")],
+            templates::FeedHistory { style_id, feeds },
+        ))
     } else {
-        Redirect::to("/").into_response()
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
     }
 }
 
-pub async fn get_tags(
+pub async fn get_feed_archive(
     Extension(style_id): Extension<model::StyleId>,
     Extension(sqlite): Extension<SqlitePool>,
-    Query(model::Tags { after }): Query<model::Tags>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Query(model::FeedArchive { page }): Query<model::FeedArchive>,
 ) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("feed archive requested, page: {}, cookies: {:?}", page, cookies);
+
     coz_progress!();
 
-    let mut connection = sqlite.acquire().await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "unable to acquire a db connection",
-        )
-    })?;
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        debug!("account {} requesting page {} of their feed archive", account_id, page);
 
-    let tags = if let Some(after) = after {
-        sqlx::query_as!(
-            model::TagRow,
-            r#"SELECT name as "name!", tag_id as "id!" FROM tags WHERE tag_id > ? ORDER BY tag_id LIMIT 100"#,
-            after
-        )
-        .fetch_all(&mut *connection)
-        .await
-        .map_err(|_| {
+        let mut connection = sqlite.acquire().await.map_err(|_| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to query the tags",
+                "unable to acquire a db connection",
             )
-        })?
-    } else {
-        sqlx::query_as!(
-            model::TagRow,
-            r#"SELECT name as "name!", tag_id as "id!" FROM tags ORDER BY tag_id LIMIT 100"#
+        })?;
+
+        if sqlx::query_scalar!(
+            r#"SELECT 1 FROM accounts WHERE account_id = ?"#,
+            account_id
         )
-        .fetch_all(&mut *connection)
+        .fetch_optional(&mut *connection)
         .await
         .map_err(|_| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to query the tags",
+                "unable to check if an account exists",
             )
         })?
-    };
+        .is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "the requested account does not exist",
+            ));
+        }
 
-    Ok((
-        [("Content-Type", "application/xhtml+xml")],
-        templates::Tags {
-            style_id,
-            after: if tags.len() == 100 {
-                tags.last().map(|t| t.id.clone())
-            } else {
-                None
+        let links = feed::read_archive(&mut connection, account_id, page)
+            .await?
+            .into_iter()
+            .map(|link| templates::ArchivedLink {
+                id: link.id,
+                description: link.description,
+                delivered: humantime::format_rfc3339_seconds(
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(link.delivered),
+                )
+                .to_string(),
+                visited: link.visited,
+                rated: link.rated,
+            })
+            .collect::<Vec<_>>();
+
+        Ok((
+            [("Content-Type", "application/xhtml+xml"), ("Cache-Control", "private, no-store")],
+            templates::FeedArchive {
+                style_id,
+                page,
+                next_page: (links.len() == feed::ARCHIVE_PAGE_LENGTH).then_some(page + 1),
+                links,
             },
-            tags,
-        },
-    ))
+        ))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
+    }
 }
 
-pub async fn get_post(Extension(style_id): Extension<model::StyleId>) -> impl IntoResponse {
-    coz_progress!();
-
-    (
-        [("Content-Type", "application/xhtml+xml")],
-        templates::Post { style_id },
-    )
+pub async fn post_refresh_feed(
+    Extension(algorithm_configuration): Extension<AlgorithmConfiguration>,
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(lock_map): Extension<&'static LockMap>,
+    cookies: Option<TypedHeader<Cookie>>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    update_feed_manually(algorithm_configuration, sqlite, lock_map, cookies, false).await
 }
 
-pub async fn post_post(
+pub async fn post_load_more_feed(
+    Extension(algorithm_configuration): Extension<AlgorithmConfiguration>,
     Extension(sqlite): Extension<SqlitePool>,
-    Form(model::PostPost {
-        link,
-        description,
-        tags,
-    }): Form<model::PostPost>,
+    Extension(lock_map): Extension<&'static LockMap>,
+    cookies: Option<TypedHeader<Cookie>>,
 ) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
-    trace!("post post-ed, tags: \"{}\"", tags);
-
-    coz_progress!();
+    update_feed_manually(algorithm_configuration, sqlite, lock_map, cookies, true).await
+}
 
-    let mut connection = sqlite.acquire().await.map_err(|_| {
+/// Check that an account hasn't used up its manual feed refreshes, either by refreshing too
+/// recently or too often over the past day
+async fn check_refresh_quota(
+    algorithm_configuration: &AlgorithmConfiguration,
+    connection: &mut SqliteConnection,
+    account_id: &str,
+    now: i64,
+) -> Result<(), (StatusCode, &'static str)> {
+    let day_ago = now - 60 * 60 * 24;
+
+    let recent_refreshes = sqlx::query!(
+        r#"SELECT COUNT(1) as "count!: i64", MAX(refreshed) as "last?: i64" FROM feed_refreshes WHERE account_id = ? AND refreshed > ?"#,
+        account_id,
+        day_ago
+    )
+    .fetch_one(&mut *connection)
+    .await
+    .map_err(|_| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
-            "unable to acquire a db connection",
+            "unable to query the account's recent feed refreshes",
         )
     })?;
 
-    let link_id = Ulid::with_source(&mut pcg_thread_rng()).to_string();
+    if recent_refreshes.count as usize >= algorithm_configuration.manual_refresh_quota {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "you've used all of today's manual feed refreshes. try again tomorrow",
+        ));
+    }
 
-    debug!("link id generated: {}", link_id);
+    if let Some(last) = recent_refreshes.last
+        && now - last < algorithm_configuration.manual_refresh_interval.as_secs() as i64 {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "your feed was refreshed too recently. try again later",
+        ));
+    }
 
-    //TODO(superwhiskers): this and the similar loop used in account creation (and likely
-    //                     account tag modification) could be factored out
-    for tag in retrieve_tags_from_string(&mut connection, tags).await? {
-        let score = ScaledRating::new(
-            0.0,
-            350.0 / instant_glicko_2::constants::RATING_SCALING_RATIO,
-            0.06,
-        );
-        let last_period = SystemTime::UNIX_EPOCH
+    Ok(())
+}
+
+/// Regenerate an account's feed at their request, either replacing it or appending another
+/// batch of links to it, subject to the configured interval and daily quota
+#[inline(always)]
+pub async fn update_feed_manually(
+    algorithm_configuration: AlgorithmConfiguration,
+    sqlite: SqlitePool,
+    lock_map: &'static LockMap,
+    cookies: Option<TypedHeader<Cookie>>,
+    append: bool,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("manual feed update requested, append: {}, cookies: {:?}", append, cookies);
+
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        debug!("account {} manually updating their feed, append: {}", account_id, append);
+
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
+
+        let now = SystemTime::UNIX_EPOCH
             .elapsed()
             .map_err(|_| {
                 (
@@ -642,58 +687,1028 @@ pub async fn post_post(
                     "unable to calculate the amount of time that has passed since the unix epoch",
                 )
             })?
-            .as_secs();
+            .as_secs() as i64;
 
-        let score = rmp_serde::to_vec(&model::Score {
-            score,
-            last_period,
-            result_queue: vec![],
-        })
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to convert data to messagepack",
-            )
-        })?;
+        // checked before taking any locks, so that requests over the quota don't hold up
+        // everything else which needs the account or its candidate links
+        check_refresh_quota(&algorithm_configuration, &mut connection, account_id, now).await?;
 
-        sqlx::query!(
-            r"INSERT INTO scores (id, tag_id, score) VALUES (?, ?, ?)",
-            link_id,
-            tag,
-            score,
-        )
-        .execute(&mut *connection)
+        trace!("locking the account's tags and the links which could be picked for its feed");
+
+        let (_tag_locks, candidates) =
+            feed::lock_feed(lock_map, &mut connection, account_id).await?;
+
+        // and again once the locks are held, as requests which were waiting on each other may
+        // have passed the first check together
+        check_refresh_quota(&algorithm_configuration, &mut connection, account_id, now).await?;
+
+        let mut feed = feed::read_feed(&mut connection, account_id)
+            .await?
+            .ok_or((
+                StatusCode::BAD_REQUEST,
+                "the requested account does not exist",
+            ))?;
+
+        // a feed which has outlived the refresh period (or has never been generated) is
+        // replaced the next time it's loaded, taking anything appended to it along with it
+        let append = append && !feed::is_stale(&algorithm_configuration, &feed)?;
+
+        let excluded_links = if append {
+            feed
+                .links
+                .iter()
+                .map(|(link_id, _)| link_id.clone())
+                .collect::<HashSet<_>>()
+        } else {
+            HashSet::new()
+        };
+
+        let entries = feed::generate_feed(
+            &algorithm_configuration,
+            sqlite.acquire().await.map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to acquire a db connection",
+                )
+            })?,
+            account_id,
+            &candidates,
+            &excluded_links,
+        )
+        .await?;
+
+        // the feed and the refresh counted against the quota are written together, so that a
+        // failure can't use up a refresh without changing the feed or vice versa
+        let mut transaction = connection.begin().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to begin a transaction",
+            )
+        })?;
+
+        feed::record_deliveries(
+            &algorithm_configuration,
+            &mut transaction,
+            account_id,
+            entries.iter().map(|(link_id, _, _)| link_id.as_str()),
+        )
+        .await?;
+
+        if append {
+            debug!("appending to the feed of {}: {:?}", account_id, entries);
+
+            feed::append_to_feed(&mut transaction, account_id, feed.refreshed, &entries).await?;
+        } else {
+            feed = model::Feed::new(now as u64, entries);
+
+            debug!("manually refreshed feed for {}: {:?}", account_id, feed);
+
+            feed::replace_feed(&algorithm_configuration, &mut transaction, account_id, &feed).await?;
+        }
+
+        sqlx::query!(
+            r"INSERT INTO feed_refreshes (account_id, refreshed) VALUES (?, ?)",
+            account_id,
+            now
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to record the feed refresh",
+            )
+        })?;
+
+        // refreshes older than a day no longer count towards the quota
+        let day_ago = now - 60 * 60 * 24;
+        sqlx::query!(
+            r"DELETE FROM feed_refreshes WHERE account_id = ? AND refreshed <= ?",
+            account_id,
+            day_ago
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to remove old feed refreshes",
+            )
+        })?;
+
+        transaction.commit().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to commit the refreshed feed",
+            )
+        })?;
+
+        Ok(Redirect::to("/"))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
+    }
+}
+
+pub async fn get_login(
+    Extension(style_id): Extension<model::StyleId>,
+    Query(model::Login { redirect_to }): Query<model::Login>,
+    cookies: Option<TypedHeader<Cookie>>,
+) -> Response<UnsyncBoxBody<Bytes, axum::Error>> {
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && cookies.get("flock.id").is_some() {
+        if let Some(url) = redirect_to {
+            Redirect::to(&url)
+        } else {
+            Redirect::to("/")
+        }
+        .into_response()
+    } else {
+        (
+            [("Content-Type", "application/xhtml+xml")],
+            templates::Login { style_id, redirect_to },
+        )
+        .into_response()
+    }
+}
+
+pub async fn post_login(
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(route_configuration): Extension<RouteConfiguration>,
+    Query(model::Login { redirect_to }): Query<model::Login>,
+    Form(model::PostLogin { account_id }): Form<model::PostLogin>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("login post-ed with account id {}", account_id);
+
+    coz_progress!();
+
+    let mut connection = sqlite.acquire().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to acquire a db connection",
+        )
+    })?;
+
+    if sqlx::query!(
+        r"SELECT COUNT(1) as count FROM accounts WHERE account_id = ?",
+        account_id
+    )
+    .fetch_one(&mut *connection)
+    .await
+    .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to query the db"))?
+    .count
+        == 1
+    {
+        Ok((
+            AppendHeaders([(
+                SET_COOKIE,
+                if route_configuration.secure_cookies {
+                    format!(
+                        "flock.id={}; SameSite=Strict; Expires={}; Max-Age=172800; HttpOnly; Secure",
+                        account_id,
+                        // lmao old browsers but why the heck not, it's hardly any effort
+                        httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(172800))
+                    )
+                } else {
+                    format!(
+                        "flock.id={}; SameSite=Strict; Expires={}; Max-Age=172800; HttpOnly",
+                        account_id,
+                        // lmao old browsers but why the heck not, it's hardly any effort
+                        httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(172800))
+                    )
+                },
+            )]),
+            if let Some(url) = redirect_to {
+                Redirect::to(&url)
+            } else {
+                Redirect::to("/")
+            },
+        ))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "the requested account does not exist",
+        ))
+    }
+}
+
+pub async fn get_signup(
+    Extension(style_id): Extension<model::StyleId>,
+    Extension(route_configuration): Extension<RouteConfiguration>,
+    Extension(challenge_key): Extension<&'static ChallengeKey>,
+    Query(model::Signup { invite }): Query<model::Signup>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    coz_progress!();
+
+    Ok((
+        [("Content-Type", "application/xhtml+xml"), ("Cache-Control", "no-store")],
+        templates::Signup {
+            style_id,
+            mode: route_configuration.signup_mode,
+            invite_code: invite.unwrap_or_default(),
+            challenge: if route_configuration.signup_mode == SignupMode::ProofOfWork {
+                Some(challenge_key.issue()?)
+            } else {
+                None
+            },
+            difficulty: route_configuration.proof_of_work_difficulty,
+        },
+    ))
+}
+
+pub async fn post_signup(
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(route_configuration): Extension<RouteConfiguration>,
+    Extension(challenge_key): Extension<&'static ChallengeKey>,
+    Form(model::PostSignup {
+        tags,
+        invite_code,
+        stamp,
+    }): Form<model::PostSignup>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("signup post-ed, tags: \"{}\"", tags);
+
+    coz_progress!();
+
+    if route_configuration.signup_mode == SignupMode::Closed {
+        return Err((StatusCode::FORBIDDEN, "signups are currently closed"));
+    }
+
+    let mut connection = sqlite.acquire().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to acquire a db connection",
+        )
+    })?;
+
+    // the tags are checked before anything is redeemed, so that invalid ones don't use up an
+    // invite or a stamp
+    let mut tags = tags;
+    let tags = string_to_tags(&mut tags)?;
+
+    let account_id = Ulid::with_source(&mut pcg_thread_rng()).to_string();
+
+    let mut transaction = connection.begin().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to begin a transaction",
+        )
+    })?;
+
+    match route_configuration.signup_mode {
+        SignupMode::Invite => {
+            signup::redeem_invite(&mut transaction, &invite_code, &account_id).await?
+        }
+        SignupMode::ProofOfWork => signup::redeem_stamp(
+            &mut transaction,
+            challenge_key,
+            route_configuration.proof_of_work_difficulty,
+            &stamp,
+        )
+        .await?,
+        SignupMode::Open | SignupMode::Closed => {}
+    }
+
+    debug!("account id generated: {}", account_id);
+
+    for tag in retrieve_tags(&mut transaction, tags).await? {
+        let score = ScaledRating::new(
+            0.0,
+            350.0 / instant_glicko_2::constants::RATING_SCALING_RATIO,
+            0.06,
+        );
+        let last_period = SystemTime::UNIX_EPOCH
+            .elapsed()
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to calculate the amount of time that has passed since the unix epoch",
+                )
+            })?
+            .as_secs();
+
+        scores::insert(&mut transaction, &account_id, &tag, score, last_period).await?;
+    }
+
+    sqlx::query!(
+        r"INSERT INTO accounts (account_id, style_id) VALUES (?, null)",
+        account_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to insert a new account into the db",
+        )
+    })?;
+
+    transaction.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to commit the new account",
+        )
+    })?;
+
+    Ok((
+        AppendHeaders([(
+            SET_COOKIE,
+            if route_configuration.secure_cookies {
+                format!(
+                    "flock.id={}; SameSite=Strict; Expires={}; Max-Age=172800; HttpOnly; Secure",
+                    account_id,
+                    // lmao old browsers but why the heck not, it's hardly any effort
+                    httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(172800))
+                )
+            } else {
+                format!(
+                    "flock.id={}; SameSite=Strict; Expires={}; Max-Age=172800; HttpOnly",
+                    account_id,
+                    // lmao old browsers but why the heck not, it's hardly any effort
+                    httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(172800))
+                )
+            },
+        )]),
+        Redirect::to("/welcome"),
+    ))
+}
+
+pub async fn get_logout(
+    cookies: Option<TypedHeader<Cookie>>,
+    Extension(route_configuration): Extension<RouteConfiguration>,
+) -> Response<UnsyncBoxBody<Bytes, axum::Error>> {
+    trace!("logout requested, cookies: {:?}", cookies);
+
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        (
+            AppendHeaders([
+                (
+                    SET_COOKIE,
+                    if route_configuration.secure_cookies {
+                        format!(
+                            "flock.id={}; SameSite=Strict; Expires={}; Max-Age=0; HttpOnly; Secure",
+                            account_id,
+                            // lmao old browsers but why the heck not, it's hardly any effort
+                            httpdate::fmt_http_date(SystemTime::UNIX_EPOCH)
+                        )
+                    } else {
+                        format!(
+                            "flock.id={}; SameSite=Strict; Expires={}; Max-Age=0; HttpOnly",
+                            account_id,
+                            // lmao old browsers but why the heck not, it's hardly any effort
+                            httpdate::fmt_http_date(SystemTime::UNIX_EPOCH)
+                        )
+                    },
+                ),
+            ]),
+            Redirect::to("/"),
+        )
+            .into_response()
+    } else {
+        Redirect::to("/").into_response()
+    }
+}
+
+pub async fn get_tags(
+    Extension(style_id): Extension<model::StyleId>,
+    Extension(sqlite): Extension<SqlitePool>,
+    Query(model::Tags { after }): Query<model::Tags>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    coz_progress!();
+
+    let mut connection = sqlite.acquire().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to acquire a db connection",
+        )
+    })?;
+
+    let tags = if let Some(after) = after {
+        sqlx::query_as!(
+            model::TagRow,
+            r#"SELECT name as "name!", tag_id as "id!" FROM tags WHERE tag_id > ? ORDER BY tag_id LIMIT 100"#,
+            after
+        )
+        .fetch_all(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to query the tags",
+            )
+        })?
+    } else {
+        sqlx::query_as!(
+            model::TagRow,
+            r#"SELECT name as "name!", tag_id as "id!" FROM tags ORDER BY tag_id LIMIT 100"#
+        )
+        .fetch_all(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to query the tags",
+            )
+        })?
+    };
+
+    Ok((
+        [("Content-Type", "application/xhtml+xml")],
+        templates::Tags {
+            style_id,
+            after: if tags.len() == 100 {
+                tags.last().map(|t| t.id.clone())
+            } else {
+                None
+            },
+            tags,
+        },
+    ))
+}
+
+pub async fn get_tag(
+    Extension(style_id): Extension<model::StyleId>,
+    Extension(sqlite): Extension<SqlitePool>,
+    Path(mut name): Path<String>,
+    Query(model::TagLinks { sort, page }): Query<model::TagLinks>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("tag {} requested, sort: {:?}, page: {}", name, sort, page);
+
+    coz_progress!();
+
+    name.make_ascii_lowercase();
+
+    let mut connection = sqlite.acquire().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to acquire a db connection",
+        )
+    })?;
+
+    let tag_id = tags::tag_id_from_name(&mut connection, &name).await?;
+
+    let links = tags::tag_links(&mut connection, &tag_id, sort, page).await?;
+
+    Ok((
+        [("Content-Type", "application/xhtml+xml")],
+        templates::TagPage {
+            style_id,
+            name,
+            sort,
+            page,
+            next_page: (links.len() == tags::TAG_PAGE_LENGTH).then_some(page + 1),
+            links: links
+                .into_iter()
+                .map(|link| templates::ScoredLink {
+                    id: link.id,
+                    description: link.description,
+                    score: link.score.to_string(),
+                })
+                .collect(),
+        },
+    ))
+}
+
+pub async fn get_tag_feed_xml(
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(http_configuration): Extension<HttpConfiguration>,
+    Path(mut name): Path<String>,
+    Query(model::TagFeedXml { sort }): Query<model::TagFeedXml>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("feed.xml requested for tag {}, sort: {:?}", name, sort);
+
+    coz_progress!();
+
+    name.make_ascii_lowercase();
+
+    let mut connection = sqlite.acquire().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to acquire a db connection",
+        )
+    })?;
+
+    let tag_id = tags::tag_id_from_name(&mut connection, &name).await?;
+
+    let mut links = Vec::with_capacity(tags::TAG_PAGE_LENGTH);
+    for link in tags::tag_links(&mut connection, &tag_id, sort, 0).await? {
+        links.push(
+            rss::ItemBuilder::default()
+                .title(link.description)
+                .description(
+                    templates::FeedItem {
+                        style_id: model::StyleId(None),
+                        flock_host: http_configuration.host.clone(),
+                        promote_url: syndication::login_rating_url(
+                            &http_configuration.host,
+                            &link.id,
+                            model::Rating::Promote,
+                        ),
+                        neutral_url: syndication::login_rating_url(
+                            &http_configuration.host,
+                            &link.id,
+                            model::Rating::Neutral,
+                        ),
+                        demote_url: syndication::login_rating_url(
+                            &http_configuration.host,
+                            &link.id,
+                            model::Rating::Demote,
+                        ),
+                    }
+                    .render()
+                    .map_err(|_| {
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            "unable to render an rss feed item's description",
+                        )
+                    })?,
+                )
+                .link(format!("{}/links/{}", http_configuration.host, link.id))
+                .guid(
+                    rss::GuidBuilder::default()
+                        .value(link.id)
+                        .permalink(false)
+                        .build(),
+                )
+                .build(),
+        );
+    }
+
+    Ok((
+        [("Content-Type", "application/rss+xml")],
+        rss::ChannelBuilder::default()
+            .title(format!("flock :: {}", name))
+            .description(format!(
+                "the {} links tagged {} on flock",
+                sort.as_str(),
+                name
+            ))
+            .link(format!(
+                "{}/tags/{}",
+                http_configuration.host,
+                urlencoding::encode(&name)
+            ))
+            .docs("https://www.rssboard.org/rss-specification".to_string())
+            .items(links)
+            .build()
+            .to_string(),
+    ))
+}
+
+pub async fn get_search(
+    Extension(style_id): Extension<model::StyleId>,
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(algorithm_configuration): Extension<AlgorithmConfiguration>,
+    Query(model::Search { q, tags, page }): Query<model::Search>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("search requested, q: \"{}\", tags: \"{}\", page: {}", q, tags, page);
+
+    coz_progress!();
+
+    let filter = search::TagFilter::parse(&tags)?;
+
+    let results = if q.trim().is_empty() && filter.is_empty() {
+        None
+    } else {
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
+
+        Some(
+            search::search(&algorithm_configuration, &mut connection, &q, &filter, page)
+                .await?,
+        )
+    };
+
+    Ok((
+        [("Content-Type", "application/xhtml+xml")],
+        templates::Search {
+            style_id,
+            next_page: results.as_ref().and_then(|results| {
+                (results.len() == search::SEARCH_PAGE_LENGTH).then_some(page + 1)
+            }),
+            results: results.map(|results| {
+                results
+                    .into_iter()
+                    .map(|result| templates::SearchResult {
+                        id: result.id,
+                        link: result.link,
+                        description: result.description,
+                        tags: result.tags,
+                        score: format!("{:.3}", result.score),
+                    })
+                    .collect()
+            }),
+            query: q,
+            tags,
+            page,
+        },
+    ))
+}
+
+pub async fn get_post(Extension(style_id): Extension<model::StyleId>) -> impl IntoResponse {
+    coz_progress!();
+
+    (
+        [("Content-Type", "application/xhtml+xml")],
+        templates::Post { style_id },
+    )
+}
+
+pub async fn post_post(
+    Extension(sqlite): Extension<SqlitePool>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Form(model::PostPost {
+        link,
+        description,
+        tags,
+    }): Form<model::PostPost>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("post post-ed, tags: \"{}\"", tags);
+
+    coz_progress!();
+
+    let mut connection = sqlite.acquire().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to acquire a db connection",
+        )
+    })?;
+
+    let account_id = if let Some(cookies) = &cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        sqlx::query_scalar!(
+            r#"SELECT account_id as "account_id!" FROM accounts WHERE account_id = ?"#,
+            account_id
+        )
+        .fetch_optional(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to check if an account exists",
+            )
+        })?
+    } else {
+        None
+    };
+
+    let tags = retrieve_tags_from_string(&mut connection, tags).await?;
+    let link_id = create_link(
+        &mut connection,
+        account_id.as_deref(),
+        &link,
+        &description,
+        tags,
+    )
+    .await?;
+
+    search::index_link(&mut connection, &link_id).await?;
+
+    Ok(Redirect::to("/"))
+}
+
+pub async fn get_import(Extension(style_id): Extension<model::StyleId>) -> impl IntoResponse {
+    trace!("import page requested");
+
+    coz_progress!();
+
+    (
+        [("Content-Type", "application/xhtml+xml")],
+        templates::Import { style_id },
+    )
+}
+
+pub async fn post_import(
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(lock_map): Extension<&'static LockMap>,
+    Extension(style_id): Extension<model::StyleId>,
+    cookies: Option<TypedHeader<Cookie>>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("import posted, cookies: {:?}", cookies);
+
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
+
+        if sqlx::query_scalar!(
+            r#"SELECT 1 FROM accounts WHERE account_id = ?"#,
+            account_id
+        )
+        .fetch_optional(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to check if an account exists",
+            )
+        })?
+        .is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "the requested account does not exist",
+            ));
+        }
+
+        let contents = loop {
+            if let Some(field) = multipart
+                .next_field()
+                .await
+                .map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        "unable to read multipart form data",
+                    )
+                })? {
+                if field.name() == Some("import") {
+                    break field
+                        .text()
+                        .await
+                        .map_err(|_| {
+                            (
+                                StatusCode::BAD_REQUEST,
+                                "unable to read multipart form data",
+                            )
+                        })?;
+                }
+            } else {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "no useful multipart form data was found",
+                ));
+            }
+        };
+
+        let links = import::parse(&contents)?;
+
+        debug!("account {} importing {} links", account_id, links.len());
+
+        // the account's tag scores are seeded, while the only links whose scores are written
+        // are the ones the import creates
+        let _tag_lock = lock_map.wait(account_id).await.ok_or((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "timed out waiting for a lock on your account's tag information. try again in a few seconds",
+        ))?;
+
+        let summary = import::import(&mut connection, account_id, links).await?;
+
+        Ok((
+            [("Content-Type", "application/xhtml+xml")],
+            templates::ImportResult {
+                style_id,
+                posted: summary.posted,
+                existing: summary.existing,
+                seeded_tags: summary.seeded_tags,
+            },
+        ))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
+    }
+}
+
+pub async fn get_report(
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(style_id): Extension<model::StyleId>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Query(model::Report { kind, id }): Query<model::Report>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!(
+        "report page requested for {} {}, cookies: {:?}",
+        kind.as_str(),
+        id,
+        cookies
+    );
+
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && cookies.get("flock.id").is_some() {
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
+
+        let description = match kind {
+            model::ReportKind::Link => sqlx::query_scalar!(
+                r#"SELECT description as "description!" FROM links WHERE link_id = ?"#,
+                id
+            )
+            .fetch_optional(&mut *connection)
+            .await,
+            model::ReportKind::Style => sqlx::query_scalar!(
+                r#"SELECT name as "name!" FROM styles WHERE style_id = ?"#,
+                id
+            )
+            .fetch_optional(&mut *connection)
+            .await,
+        }
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to query the db for the reported item",
+            )
+        })?
+        .ok_or((StatusCode::BAD_REQUEST, "the reported item does not exist"))?;
+
+        Ok((
+            [("Content-Type", "application/xhtml+xml")],
+            templates::Report {
+                style_id,
+                kind,
+                target_id: id,
+                description,
+            },
+        ))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
+    }
+}
+
+pub async fn post_report(
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(style_id): Extension<model::StyleId>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Form(model::PostReport { kind, id, reason }): Form<model::PostReport>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!(
+        "report posted for {} {}, cookies: {:?}",
+        kind.as_str(),
+        id,
+        cookies
+    );
+
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
+
+        if sqlx::query_scalar!(
+            r#"SELECT 1 FROM accounts WHERE account_id = ?"#,
+            account_id
+        )
+        .fetch_optional(&mut *connection)
         .await
         .map_err(|_| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to insert a tag score into the db",
+                "unable to check if an account exists",
+            )
+        })?
+        .is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "the requested account does not exist",
+            ));
+        }
+
+        moderation::report(&mut connection, account_id, kind, &id, reason.trim()).await?;
+
+        Ok((
+            [("Content-Type", "application/xhtml+xml")],
+            templates::ReportResult { style_id },
+        ))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
+    }
+}
+
+pub async fn get_moderation(
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(style_id): Extension<model::StyleId>,
+    cookies: Option<TypedHeader<Cookie>>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("moderation queue requested, cookies: {:?}", cookies);
+
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
             )
         })?;
+
+        let role = moderation::role(&mut connection, account_id)
+            .await?
+            .ok_or((
+                StatusCode::FORBIDDEN,
+                "only moderators can view the moderation queue",
+            ))?;
+
+        Ok((
+            [("Content-Type", "application/xhtml+xml"), ("Cache-Control", "private, no-store")],
+            templates::Moderation {
+                style_id,
+                role: role.as_str(),
+                reports: moderation::open_reports(&mut connection).await?,
+                log: moderation::recent_log(&mut connection).await?,
+            },
+        ))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
     }
+}
+
+pub async fn post_moderation(
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(lock_map): Extension<&'static LockMap>,
+    Extension(style_cache): Extension<&'static StyleCache>,
+    Extension(front_page): Extension<&'static FrontPage>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Form(model::PostModeration { action, target, tag }): Form<model::PostModeration>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!(
+        "moderation action {} posted against {}, cookies: {:?}",
+        action.as_str(),
+        target,
+        cookies
+    );
+
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
+
+        let role = moderation::role(&mut connection, account_id)
+            .await?
+            .ok_or((
+                StatusCode::FORBIDDEN,
+                "only moderators can take moderation actions",
+            ))?;
 
-    sqlx::query!(
-        "INSERT INTO links (link_id, link, description) VALUES (?, ?, ?)",
-        link_id,
-        link,
-        description
-    )
-    .execute(&mut *connection)
-    .await
-    .map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "unable to insert the link into the db",
+        moderation::act(
+            &mut connection,
+            lock_map,
+            style_cache,
+            front_page,
+            account_id,
+            role,
+            action,
+            &target,
+            &tag.trim().to_ascii_lowercase(),
         )
-    })?;
+        .await?;
 
-    Ok(Redirect::to("/"))
+        Ok(Redirect::to("/moderation"))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
+    }
 }
 
 pub async fn link(
     Extension(sqlite): Extension<SqlitePool>,
     cookies: Option<TypedHeader<Cookie>>,
     Path(link_id): Path<String>,
+    Query(model::Link { token }): Query<model::Link>,
 ) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
     trace!(
         "link requested, cookies: {:?}, link id: {}",
@@ -724,8 +1739,25 @@ pub async fn link(
     })?
     .ok_or((StatusCode::BAD_REQUEST, "the requested link does not exist"))?;
 
-    if let Some(cookies) = cookies
-        && let Some(account_id) = cookies.get("flock.id") {
+    if moderation::is_hidden(&mut connection, &link_id).await? {
+        return Err((
+            StatusCode::GONE,
+            "the requested link has been hidden by a moderator",
+        ));
+    }
+
+    // links followed from a feed carry its token, as feed readers don't have our cookies. a
+    // revoked token shouldn't break the link, so it just goes unrecorded
+    let account_id = if let Some(token) = token {
+        feed_tokens::account_from_token(&mut connection, &token).await?
+    } else {
+        cookies
+            .as_ref()
+            .and_then(|cookies| cookies.get("flock.id"))
+            .map(str::to_string)
+    };
+
+    if let Some(account_id) = account_id.as_deref() {
         debug!("account {} requested link {}", account_id, link_id);
 
         if sqlx::query!(
@@ -899,116 +1931,354 @@ pub async fn post_edit_link(
                 })?
                 .as_secs();
 
-            let score = rmp_serde::to_vec(&model::Score {
-                score,
-                last_period,
-                result_queue: vec![],
-            })
+            scores::insert(&mut connection, &link_id, tag, score, last_period).await?;
+        }
+
+        search::index_link(&mut connection, &link_id).await?;
+
+        Ok(Redirect::to("/"))
+    } else {
+        Err((StatusCode::BAD_REQUEST, "the requested link does not exist"))
+    }
+}
+
+//TODO(superwhiskers): implement link rating
+pub async fn get_promote_link(
+    Extension(algorithm_configuration): Extension<AlgorithmConfiguration>,
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(lock_map): Extension<&'static LockMap>,
+    Extension(signing_key): Extension<&'static SigningKey>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Path(link_id): Path<String>,
+    Query(signed_rating): Query<model::SignedRating>,
+    Query(model::RatingRedirect { redirect_to }): Query<model::RatingRedirect>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    rate_link(
+        algorithm_configuration,
+        sqlite,
+        lock_map,
+        signing_key,
+        cookies,
+        signed_rating,
+        redirect_to,
+        link_id,
+        model::Rating::Promote,
+    )
+    .await
+}
+
+pub async fn get_neutral_link(
+    Extension(algorithm_configuration): Extension<AlgorithmConfiguration>,
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(lock_map): Extension<&'static LockMap>,
+    Extension(signing_key): Extension<&'static SigningKey>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Path(link_id): Path<String>,
+    Query(signed_rating): Query<model::SignedRating>,
+    Query(model::RatingRedirect { redirect_to }): Query<model::RatingRedirect>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    rate_link(
+        algorithm_configuration,
+        sqlite,
+        lock_map,
+        signing_key,
+        cookies,
+        signed_rating,
+        redirect_to,
+        link_id,
+        model::Rating::Neutral,
+    )
+    .await
+}
+
+pub async fn get_demote_link(
+    Extension(algorithm_configuration): Extension<AlgorithmConfiguration>,
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(lock_map): Extension<&'static LockMap>,
+    Extension(signing_key): Extension<&'static SigningKey>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Path(link_id): Path<String>,
+    Query(signed_rating): Query<model::SignedRating>,
+    Query(model::RatingRedirect { redirect_to }): Query<model::RatingRedirect>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    rate_link(
+        algorithm_configuration,
+        sqlite,
+        lock_map,
+        signing_key,
+        cookies,
+        signed_rating,
+        redirect_to,
+        link_id,
+        model::Rating::Demote,
+    )
+    .await
+}
+
+/// Rate a link on behalf of either the logged-in account or, if the request carries a signed
+/// rating url from a feed, the account the feed token belongs to
+#[inline(always)]
+#[allow(clippy::too_many_arguments)]
+pub async fn rate_link(
+    algorithm_configuration: AlgorithmConfiguration,
+    sqlite: SqlitePool,
+    lock_map: &'static LockMap,
+    signing_key: &'static SigningKey,
+    cookies: Option<TypedHeader<Cookie>>,
+    signed_rating: model::SignedRating,
+    redirect_to: Option<String>,
+    link_id: String,
+    rating: model::Rating,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("link rated: {}, cookies: {:?}", link_id, cookies);
+
+    coz_progress!();
+
+    let base_outcome = rating.base_outcome();
+    let signed_rating = signed_rating.token.zip(signed_rating.signature);
+
+    let mut connection = sqlite.acquire().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to acqire a db connection",
+        )
+    })?;
+
+    let account_id = if let Some((token, signature)) = &signed_rating {
+        if !signing_key.verify_rating(token, &link_id, rating, signature) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "the rating url's signature is invalid",
+            ));
+        }
+
+        Some(
+            feed_tokens::account_from_token(&mut connection, token)
+                .await?
+                .ok_or((
+                    StatusCode::BAD_REQUEST,
+                    "the requested feed token does not exist",
+                ))?,
+        )
+    } else {
+        cookies
+            .as_ref()
+            .and_then(|cookies| cookies.get("flock.id"))
+            .map(str::to_string)
+    };
+
+    if let Some(account_id) = account_id.as_deref() {
+        debug!("account {} rating link {} with base outcome {}", account_id, link_id, base_outcome);
+
+        // signed rating urls carry no cookies, so they get past the suspension middleware
+        if signed_rating.is_some() && moderation::is_suspended(&mut connection, account_id).await? {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "your account has been suspended",
+            ));
+        }
+
+        if sqlx::query_scalar!(
+            r#"SELECT 1 FROM accounts WHERE account_id = ?"#,
+            account_id
+        )
+        .fetch_optional(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to check if an account exists",
+            )
+        })?
+        .is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "the requested account does not exist",
+            ));
+        }
+
+        if sqlx::query_scalar!(
+            r#"SELECT 1 FROM links where link_id = ?"#,
+            link_id
+        )
+        .fetch_optional(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to check if a link exists",
+            )
+        })?
+        .is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "the requested link does not exist",
+            ));
+        }
+
+        debug!("rating link {} / account {}", link_id, account_id);
+
+        let _tag_locks = lock_map.lock_many([account_id, link_id.as_str()]).await.ok_or((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "timed out waiting for a lock on your account's or the link's tag information, try again in a few seconds",
+        ))?;
+
+        // signed rating urls are single-use, so they can't be replayed by anyone who sees them
+        if signed_rating.is_some()
+            && sqlx::query_scalar!(
+                r#"SELECT rated as "rated!: bool" FROM seen WHERE account_id = ? AND link_id = ?"#,
+                account_id,
+                link_id
+            )
+            .fetch_optional(&mut *connection)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to check if the link has been rated",
+                )
+            })?
+            .unwrap_or(false) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "this rating url has already been used",
+            ));
+        }
+
+        let user_scores = scores::load(&mut connection, account_id)
+            .await?
+            .into_iter()
+            .map(|tag| (tag.tag_id, tag.score))
+            .collect::<HashMap<_, _>>();
+
+        let link_scores = scores::load(&mut connection, &link_id)
+            .await?
+            .into_iter()
+            .map(|tag| (tag.tag_id, tag.score))
+            .collect::<HashMap<_, _>>();
+
+        let user_tags = user_scores
+            .keys()
+            .collect::<HashSet<_>>();
+
+        let link_tags = link_scores
+            .keys()
+            .collect::<HashSet<_>>();
+
+        for tag in HashSet::intersection(&user_tags, &link_tags) {
+            let (mut user_score_data, mut link_score_data) = user_scores.get(*tag).cloned().zip(link_scores.get(*tag).cloned()).unwrap();
+            let (user_score, link_score): (ScaledRatingData, ScaledRatingData) =
+                (
+                    ScaledRatingWrapper(user_score_data.score).into(),
+                    ScaledRatingWrapper(link_score_data.score).into(),
+                );
+            let comparative_volatility = user_score.partial_cmp(&link_score).ok_or((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to compare the volatilities of scores",
+            ))?;
+
+            let (user_outcome, link_outcome) = if comparative_volatility == Ordering::Equal {
+                (base_outcome, base_outcome)
+            } else {
+                let overlap = util::rating_overlap(user_score, link_score);
+                if overlap.is_nan() {
+                    return Err((StatusCode::INTERNAL_SERVER_ERROR, "a nan was encountered while calculating overlap"));
+                }
+
+                let percent_overlap = overlap / if overlap.is_sign_positive() {
+                    2.0 * f64::min(user_score.deviation, link_score.deviation)
+                } else {
+                     user_score.deviation + link_score.deviation + (user_score.rating - link_score.rating).abs()
+                };
+
+                let tweaked_outcome = base_outcome + (if base_outcome == 0.0 { 0.75 } else { 0.25 } * percent_overlap).max(0.0);
+                let (favorable_outcome, unfavorable_outcome) = if tweaked_outcome > base_outcome {
+                    (tweaked_outcome, base_outcome)
+                } else {
+                    (base_outcome, tweaked_outcome)
+                };
+
+
+                if comparative_volatility == Ordering::Less {
+                    (favorable_outcome, unfavorable_outcome)
+                } else {
+                    (unfavorable_outcome, favorable_outcome)
+                }
+            };
+
+            debug!("link outcome: {}, user outcome: {}", link_outcome, user_outcome);
+
+            user_score_data.result_queue.push(model::QueuedResult { opponent: link_score_data.score, score: user_outcome });
+            link_score_data.result_queue.push(model::QueuedResult { opponent: user_score_data.score, score: link_outcome });
+
+            util::decay_score(&algorithm_configuration, &mut user_score_data, 1)?;
+            util::decay_score(&algorithm_configuration, &mut link_score_data, 12)?;
+
+            scores::store(&mut connection, account_id, tag, &user_score_data).await?;
+            scores::store(&mut connection, &link_id, tag, &link_score_data).await?;
+
+            // someone's going to try to rate before viewing. this handles that edge case
+            sqlx::query!(
+                "INSERT INTO seen (account_id, link_id, rated) VALUES (?, ?, true) ON CONFLICT (account_id, link_id) DO UPDATE SET rated = true",
+                account_id,
+                link_id
+            )
+            .execute(&mut *connection)
+            .await
             .map_err(|_| {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to convert data to messagepack",
+                    "unable to update the rated value",
                 )
             })?;
+        }
 
+        if signed_rating.is_some() {
+            // the rating has to be recorded even if no tags were shared, or the url could be
+            // used again
             sqlx::query!(
-                r"INSERT INTO scores (id, tag_id, score) VALUES (?, ?, ?)",
-                link_id,
-                tag,
-                score,
+                "INSERT INTO seen (account_id, link_id, rated) VALUES (?, ?, true) ON CONFLICT (account_id, link_id) DO UPDATE SET rated = true",
+                account_id,
+                link_id
             )
             .execute(&mut *connection)
             .await
             .map_err(|_| {
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to insert a tag score into the db",
+                    "unable to update the rated value",
                 )
             })?;
-        }
 
-        Ok(Redirect::to("/"))
-    } else {
-        Err((StatusCode::BAD_REQUEST, "the requested link does not exist"))
+            return Ok("your rating has been recorded. you can close this page".into_response());
+        }
     }
-}
 
-//TODO(superwhiskers): implement link rating
-pub async fn get_promote_link(
-    Extension(algorithm_configuration): Extension<AlgorithmConfiguration>,
-    Extension(sqlite): Extension<SqlitePool>,
-    Extension(lock_map): Extension<&'static LockMap>,
-    cookies: Option<TypedHeader<Cookie>>,
-    Path(link_id): Path<String>,
-) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
-    rate_link(
-        algorithm_configuration,
-        sqlite,
-        lock_map,
-        cookies,
-        link_id,
-        0.75,
-    )
-    .await
-}
+    // only paths on this site are redirected to, so rating urls can't send anyone elsewhere
+    let redirect_to = redirect_to
+        .filter(|path| path.starts_with('/') && !path.starts_with("//") && !path.starts_with("/\\"))
+        .unwrap_or_else(|| "/".to_string());
 
-pub async fn get_neutral_link(
-    Extension(algorithm_configuration): Extension<AlgorithmConfiguration>,
-    Extension(sqlite): Extension<SqlitePool>,
-    Extension(lock_map): Extension<&'static LockMap>,
-    cookies: Option<TypedHeader<Cookie>>,
-    Path(link_id): Path<String>,
-) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
-    rate_link(
-        algorithm_configuration,
-        sqlite,
-        lock_map,
-        cookies,
-        link_id,
-        0.5,
-    )
-    .await
+    Ok(Redirect::to(&redirect_to).into_response())
 }
 
-pub async fn get_demote_link(
-    Extension(algorithm_configuration): Extension<AlgorithmConfiguration>,
+pub async fn get_profile_tags(
+    Extension(style_id): Extension<model::StyleId>,
     Extension(sqlite): Extension<SqlitePool>,
-    Extension(lock_map): Extension<&'static LockMap>,
-    cookies: Option<TypedHeader<Cookie>>,
-    Path(link_id): Path<String>,
-) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
-    rate_link(
-        algorithm_configuration,
-        sqlite,
-        lock_map,
-        cookies,
-        link_id,
-        0.0,
-    )
-    .await
-}
-
-#[inline(always)]
-pub async fn rate_link(
-    algorithm_configuration: AlgorithmConfiguration,
-    sqlite: SqlitePool,
-    lock_map: &'static LockMap,
     cookies: Option<TypedHeader<Cookie>>,
-    link_id: String,
-    base_outcome: f64,
 ) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
-    trace!("link rated: {}, cookies: {:?}", link_id, cookies);
+    trace!("profile tag information requested, cookies: {:?}", cookies);
 
     coz_progress!();
 
     if let Some(cookies) = cookies
         && let Some(account_id) = cookies.get("flock.id") {
-        debug!("account {} rating link {} with base outcome {}", account_id, link_id, base_outcome);
+        debug!("account {} requesting profile tag information", account_id);
 
         let mut connection = sqlite.acquire().await.map_err(|_| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to acqire a db connection",
+                "unable to acquire a db connection",
             )
         })?;
 
@@ -1031,41 +2301,240 @@ pub async fn rate_link(
             ));
         }
 
+        let tags = scores::load(&mut connection, account_id)
+            .await?
+            .into_iter()
+            .map(|tag| templates::Tag {
+                name: tag.name,
+                score: <ScaledRatingWrapper as Into<ScaledRatingData>>::into(ScaledRatingWrapper(tag.score.score)).to_string(),
+            })
+            .collect::<Vec<_>>();
+
+        return Ok((
+            [("Content-Type", "application/xhtml+xml")],
+            templates::TagScores {
+                style_id,
+                id: account_id.to_string(),
+                tags,
+            }
+        ));
+    }
+
+    Err((StatusCode::BAD_REQUEST, "you are not logged in"))
+}
+
+//TODO(superwhiskers): decouple account ids from the id used to log in
+pub async fn get_profile(
+    Extension(style_id): Extension<model::StyleId>,
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(route_configuration): Extension<RouteConfiguration>,
+    cookies: Option<TypedHeader<Cookie>>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("profile requested for account, cookies: {:?}", cookies);
+
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        debug!("account {} requesting profile information", account_id);
+
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
+
+        let tags = sqlx::query_scalar!(
+            r#"SELECT tags.name as "name!" FROM scores INNER JOIN tags ON scores.tag_id = tags.tag_id WHERE scores.id = ?"#,
+            account_id
+        )
+        .fetch_all(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to query the account's tags from the db",
+            )
+        })?;
+
+        let feed_tokens = feed_tokens::account_tokens(&mut connection, account_id)
+            .await?
+            .into_iter()
+            .map(|feed_token| templates::ProfileFeedToken {
+                token: feed_token.token,
+                created: humantime::format_rfc3339_seconds(
+                    SystemTime::UNIX_EPOCH + Duration::from_secs(feed_token.created as u64),
+                )
+                .to_string(),
+            })
+            .collect();
+
+        let (invites_remaining, invites) = if route_configuration.signup_mode == SignupMode::Invite {
+            let invites = signup::account_invites(&mut connection, account_id)
+                .await?
+                .into_iter()
+                .map(|invite| templates::ProfileInvite {
+                    code: invite.code,
+                    created: humantime::format_rfc3339_seconds(
+                        SystemTime::UNIX_EPOCH + Duration::from_secs(invite.created as u64),
+                    )
+                    .to_string(),
+                    invitee: invite.invitee,
+                })
+                .collect::<Vec<_>>();
+
+            (
+                Some(route_configuration.invite_quota.saturating_sub(invites.len())),
+                invites,
+            )
+        } else {
+            (None, Vec::new())
+        };
+
+        Ok((
+            [("Content-Type", "application/xhtml+xml")],
+            templates::Profile {
+                style_id,
+                profile: templates::ProfileInformation {
+                    id: account_id.to_string(),
+                    tags: tags.iter().map(|tag| tag.as_str()).intersperse(",").collect::<String>(),
+                    feed_tokens,
+                    role: moderation::role(&mut connection, account_id)
+                        .await?
+                        .map(|role| role.as_str()),
+                    invites_remaining,
+                    invites,
+                },
+            }
+        ))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
+    }
+}
+
+pub async fn post_profile(
+    Extension(style_id): Extension<model::StyleId>,
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(lock_map): Extension<&'static LockMap>,
+    Extension(style_cache): Extension<&'static StyleCache>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Form(model::PostProfile {
+        refresh_account_id,
+        tags,
+        new_style_id,
+    }): Form<model::PostProfile>,
+) -> impl IntoResponse {
+    trace!(
+        "profile post-ed, tags: \"{}\", refresh_account_id: {}, cookies: {:?}",
+        tags,
+        refresh_account_id,
+        cookies
+    );
+
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        debug!(
+            "account {} making modifications to their profile, tags: \"{}\", refresh_account_id: {}",
+            account_id,
+            tags,
+            refresh_account_id,
+        );
+
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
+
         if sqlx::query_scalar!(
-            r#"SELECT 1 FROM links where link_id = ?"#,
-            link_id
+            "SELECT 1 FROM accounts WHERE account_id = ?",
+            account_id
         )
         .fetch_optional(&mut *connection)
         .await
         .map_err(|_| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to check if a link exists",
+                "unable to check if an account exists",
             )
         })?
         .is_none() {
             return Err((
                 StatusCode::BAD_REQUEST,
-                "the requested link does not exist",
+                "the requested account does not exist",
             ));
         }
 
-        debug!("rating link {} / account {}", link_id, account_id);
+        if new_style_id != style_id.0.as_ref().map_or("", |style| style.id.as_str()) {
+            if new_style_id.is_empty() {
+                sqlx::query!(
+                    "UPDATE accounts SET style_id = null WHERE account_id = ?",
+                    account_id
+                )
+                .execute(&mut *connection)
+                .await
+                .map_err(|_| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "unable to update an account",
+                    )
+                })?;
+            } else if sqlx::query_scalar!(
+                "SELECT 1 FROM styles WHERE style_id = ?",
+                new_style_id
+            )
+            .fetch_optional(&mut *connection)
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to check if a style exists",
+                )
+            })?
+            .is_some() {
+                sqlx::query!(
+                    "UPDATE accounts SET style_id = ? WHERE account_id = ?",
+                    new_style_id,
+                    account_id
+                )
+                .execute(&mut *connection)
+                .await
+                .map_err(|_| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "unable to update an account",
+                    )
+                })?;
+            } else {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "invalid style id",
+                ));
+            }
 
-        let _user_tag_lock = lock_map.lock(account_id).ok_or((
-            StatusCode::SERVICE_UNAVAILABLE,
-            "a lock is currently held on your account's tag information, try again in a few seconds",
-        ))?;
+            style_cache.invalidate_account(account_id);
+        }
+
+        let tags_owned = retrieve_tags_from_string(&mut connection, tags).await?;
+        let tags = tags_owned
+            .iter()
+            .map(|t| t.as_str())
+            .collect::<HashSet<_>>();
 
-        let _link_tag_lock = lock_map.lock(link_id.as_str()).ok_or((
+        let _tag_lock = lock_map.wait(account_id).await.ok_or((
             StatusCode::SERVICE_UNAVAILABLE,
-            "a lock is currently held on the link's tag information, try again in a few seconds",
+            "timed out waiting for a lock on your account's tag information. try again in a few seconds",
         ))?;
 
-        //TODO(superwhiskers): same thing mentioned in src/feed.rs, but we should
-        //                     additionally consider making this a function at this point
-        let user_scores = sqlx::query!(
-            r#"SELECT tag_id as "tag_id!", score as "score!" FROM scores WHERE id = ?"#,
+        let old_tags_owned = sqlx::query_scalar!(
+            r#"SELECT tag_id as "tag_id!" FROM scores WHERE id = ?"#,
             account_id
         )
         .fetch_all(&mut *connection)
@@ -1075,178 +2544,167 @@ pub async fn rate_link(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "unable to query the account's tags from the db",
             )
-        })?
-        .into_iter()
-        .map(|tag| {
-            rmp_serde::from_slice(&tag.score)
-                .map(|score| (tag.tag_id, score))
+        })?;
+        let old_tags = old_tags_owned
+            .iter()
+            .map(|t| t.as_str())
+            .collect::<HashSet<_>>();
+
+        for tag in &old_tags - &tags {
+            sqlx::query!(r"DELETE FROM scores WHERE id = ? AND tag_id = ?", account_id, tag)
+                .execute(&mut *connection)
+                .await
                 .map_err(|_| {
                     (
                         StatusCode::INTERNAL_SERVER_ERROR,
-                        "unable to deserialize the score data for a tag",
+                        "unable to remove an old tag from the db",
                     )
-                })
-        })
-        .collect::<Result<HashMap<String, model::Score>, _>>()?;
+                })?;
+        }
 
-        let link_scores = sqlx::query!(
-            r#"SELECT tag_id as "tag_id!", score as "score!" FROM scores WHERE id = ?"#,
-            link_id
-        )
-        .fetch_all(&mut *connection)
-        .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to query the link's tags from the db",
-            )
-        })?
-        .into_iter()
-        .map(|tag| {
-            rmp_serde::from_slice(&tag.score)
-                .map(|score| (tag.tag_id, score))
+        for tag in &tags - &old_tags {
+            let score = ScaledRating::new(
+                0.0,
+                350.0 / instant_glicko_2::constants::RATING_SCALING_RATIO,
+                0.06,
+            );
+            let last_period = SystemTime::UNIX_EPOCH
+                .elapsed()
                 .map_err(|_| {
                     (
                         StatusCode::INTERNAL_SERVER_ERROR,
-                        "unable to deserialize the score data for a tag",
+                        "unable to calculate the amount of time that has passed since the unix epoch",
                     )
-                })
-        })
-        .collect::<Result<HashMap<String, model::Score>, _>>()?;
+                })?
+                .as_secs();
 
-        let user_tags = user_scores
-            .keys()
-            .collect::<HashSet<_>>();
+            scores::insert(&mut connection, account_id, tag, score, last_period).await?;
+        }
+    }
 
-        let link_tags = link_scores
-            .keys()
-            .collect::<HashSet<_>>();
+    Ok(Redirect::to("/"))
+}
 
-        for tag in HashSet::intersection(&user_tags, &link_tags) {
-            let (mut user_score_data, mut link_score_data) = user_scores.get(*tag).cloned().zip(link_scores.get(*tag).cloned()).unwrap();
-            let (user_score, link_score): (ScaledRatingData, ScaledRatingData) =
-                (
-                    ScaledRatingWrapper(user_score_data.score).into(),
-                    ScaledRatingWrapper(link_score_data.score).into(),
-                );
-            let comparative_volatility = user_score.partial_cmp(&link_score).ok_or((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to compare the volatilities of scores",
-            ))?;
+pub async fn get_profile_export(
+    Extension(sqlite): Extension<SqlitePool>,
+    cookies: Option<TypedHeader<Cookie>>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("account data export requested, cookies: {:?}", cookies);
 
-            let (user_outcome, link_outcome) = if comparative_volatility == Ordering::Equal {
-                (base_outcome, base_outcome)
-            } else {
-                let overlap = util::rating_overlap(user_score, link_score);
-                if overlap.is_nan() {
-                    return Err((StatusCode::INTERNAL_SERVER_ERROR, "a nan was encountered while calculating overlap"));
-                }
+    coz_progress!();
 
-                let percent_overlap = overlap / if overlap.is_sign_positive() {
-                    2.0 * f64::min(user_score.deviation, link_score.deviation)
-                } else {
-                     user_score.deviation + link_score.deviation + (user_score.rating - link_score.rating).abs()
-                };
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        debug!("account {} exporting their data", account_id);
 
-                let tweaked_outcome = base_outcome + (if base_outcome == 0.0 { 0.75 } else { 0.25 } * percent_overlap).max(0.0);
-                let (favorable_outcome, unfavorable_outcome) = if tweaked_outcome > base_outcome {
-                    (tweaked_outcome, base_outcome)
-                } else {
-                    (base_outcome, tweaked_outcome)
-                };
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
 
+        let export = accounts::export(&mut connection, account_id).await?;
+        let export = serde_json::to_string_pretty(&export).map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to serialize the account's data",
+            )
+        })?;
 
-                if comparative_volatility == Ordering::Less {
-                    (favorable_outcome, unfavorable_outcome)
-                } else {
-                    (unfavorable_outcome, favorable_outcome)
-                }
-            };
+        Ok((
+            [
+                ("Content-Type", "application/json"),
+                ("Content-Disposition", "attachment; filename=\"flock-export.json\""),
+                ("Cache-Control", "private, no-store"),
+            ],
+            export,
+        ))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
+    }
+}
 
-            debug!("link outcome: {}, user outcome: {}", link_outcome, user_outcome);
+pub async fn post_profile_delete(
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(lock_map): Extension<&'static LockMap>,
+    Extension(style_cache): Extension<&'static StyleCache>,
+    Extension(route_configuration): Extension<RouteConfiguration>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Form(model::DeleteAccount { confirm }): Form<model::DeleteAccount>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("account deletion requested, cookies: {:?}", cookies);
 
-            user_score_data.result_queue.push(ScaledPlayerResult::new(link_score_data.score, user_outcome));
-            link_score_data.result_queue.push(ScaledPlayerResult::new(user_score_data.score, link_outcome));
+    coz_progress!();
 
-            util::decay_score(&algorithm_configuration, &mut user_score_data, 1)?;
-            util::decay_score(&algorithm_configuration, &mut link_score_data, 12)?;
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        if !confirm {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "you must confirm that you want to delete your account",
+            ));
+        }
 
-            let user_score_bin = rmp_serde::to_vec(&user_score_data).map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to convert data to messagepack",
-                )
-            })?;
+        debug!("account {} deleting itself", account_id);
 
-            sqlx::query!(
-                "UPDATE scores SET score = ? WHERE id = ? AND tag_id = ?",
-                user_score_bin,
-                account_id,
-                tag
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
             )
-            .execute(&mut *connection)
-            .await
-            .map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to update a score",
-                )
-            })?;
+        })?;
 
-            let link_score_bin = rmp_serde::to_vec(&link_score_data).map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to convert data to messagepack",
-                )
-            })?;
+        let _tag_lock = lock_map.wait(account_id).await.ok_or((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "timed out waiting for a lock on your account's tag information. try again in a few seconds",
+        ))?;
 
-            sqlx::query!(
-                "UPDATE scores SET score = ? WHERE id = ? AND tag_id = ?",
-                link_score_bin,
-                link_id,
-                tag
-            )
-            .execute(&mut *connection)
-            .await
-            .map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to update a score",
-                )
-            })?;
+        accounts::delete(&mut connection, account_id).await?;
 
-            // someone's going to try to rate before viewing. this handles that edge case
-            sqlx::query!(
-                "INSERT INTO seen (account_id, link_id, rated) VALUES (?, ?, true) ON CONFLICT (account_id, link_id) DO UPDATE SET rated = true",
-                account_id,
-                link_id
-            )
-            .execute(&mut *connection)
-            .await
-            .map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to update the rated value",
-                )
-            })?;
-        }
-    }
+        style_cache.invalidate_account(account_id);
 
-    Ok(Redirect::to("/"))
+        Ok((
+            AppendHeaders([(
+                SET_COOKIE,
+                if route_configuration.secure_cookies {
+                    format!(
+                        "flock.id={}; SameSite=Strict; Expires={}; Max-Age=0; HttpOnly; Secure",
+                        account_id,
+                        httpdate::fmt_http_date(SystemTime::UNIX_EPOCH)
+                    )
+                } else {
+                    format!(
+                        "flock.id={}; SameSite=Strict; Expires={}; Max-Age=0; HttpOnly",
+                        account_id,
+                        httpdate::fmt_http_date(SystemTime::UNIX_EPOCH)
+                    )
+                },
+            )]),
+            Redirect::to("/"),
+        ))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
+    }
 }
 
-pub async fn get_profile_tags(
-    Extension(style_id): Extension<model::StyleId>,
+pub async fn post_create_feed_token(
     Extension(sqlite): Extension<SqlitePool>,
     cookies: Option<TypedHeader<Cookie>>,
 ) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
-    trace!("profile tag information requested, cookies: {:?}", cookies);
+    trace!("feed token creation requested, cookies: {:?}", cookies);
 
     coz_progress!();
 
     if let Some(cookies) = cookies
         && let Some(account_id) = cookies.get("flock.id") {
-        debug!("account {} requesting profile tag information", account_id);
+        debug!("account {} creating a feed token", account_id);
 
         let mut connection = sqlite.acquire().await.map_err(|_| {
             (
@@ -1256,7 +2714,7 @@ pub async fn get_profile_tags(
         })?;
 
         if sqlx::query_scalar!(
-            r#"SELECT 1 FROM accounts WHERE account_id = ?"#,
+            "SELECT 1 FROM accounts WHERE account_id = ?",
             account_id
         )
         .fetch_optional(&mut *connection)
@@ -1274,62 +2732,29 @@ pub async fn get_profile_tags(
             ));
         }
 
-        let tags = sqlx::query!(
-            r#"SELECT tags.name as "name!", scores.score as "score!" FROM scores INNER JOIN tags ON scores.tag_id = tags.tag_id WHERE scores.id = ?"#,
-            account_id
-        )
-        .fetch_all(&mut *connection)
-        .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to query the account's tags from the db",
-            )
-        })?
-        .into_iter()
-        .map(|tag| {
-            rmp_serde::from_slice(&tag.score)
-                .map(|score: model::Score| <ScaledRatingWrapper as Into<ScaledRatingData>>::into(ScaledRatingWrapper(score.score)).to_string())
-                .map_err(|_| {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "unable to deserialize the score data for a tag",
-                    )
-                })
-                .map(|score| {
-                    templates::Tag {
-                        name: tag.name,
-                        score,
-                    }
-                })
-        }).collect::<Result<Vec<templates::Tag>, _>>()?;
+        feed_tokens::create_token(&mut connection, account_id).await?;
 
-        return Ok((
-            [("Content-Type", "application/xhtml+xml")],
-            templates::TagScores {
-                style_id,
-                id: account_id.to_string(),
-                tags,
-            }
-        ));
+        Ok(Redirect::to("/profile"))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
     }
-
-    Err((StatusCode::BAD_REQUEST, "you are not logged in"))
 }
 
-//TODO(superwhiskers): decouple account ids from the id used to log in
-pub async fn get_profile(
-    Extension(style_id): Extension<model::StyleId>,
+pub async fn post_revoke_feed_token(
     Extension(sqlite): Extension<SqlitePool>,
     cookies: Option<TypedHeader<Cookie>>,
+    Form(model::RevokeFeedToken { token }): Form<model::RevokeFeedToken>,
 ) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
-    trace!("profile requested for account, cookies: {:?}", cookies);
+    trace!("feed token revocation requested, cookies: {:?}", cookies);
 
     coz_progress!();
 
     if let Some(cookies) = cookies
         && let Some(account_id) = cookies.get("flock.id") {
-        debug!("account {} requesting profile information", account_id);
+        debug!("account {} revoking a feed token", account_id);
 
         let mut connection = sqlite.acquire().await.map_err(|_| {
             (
@@ -1337,30 +2762,15 @@ pub async fn get_profile(
                 "unable to acquire a db connection",
             )
         })?;
-
-        let tags = sqlx::query_scalar!(
-            r#"SELECT tags.name as "name!" FROM scores INNER JOIN tags ON scores.tag_id = tags.tag_id WHERE scores.id = ?"#,
-            account_id
-        )
-        .fetch_all(&mut *connection)
-        .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to query the account's tags from the db",
-            )
-        })?;
-
-        Ok((
-            [("Content-Type", "application/xhtml+xml")],
-            templates::Profile {
-                style_id,
-                profile: templates::ProfileInformation {
-                    id: account_id.to_string(),
-                    tags: tags.iter().map(|tag| tag.as_str()).intersperse(",").collect::<String>(),
-                },
-            }
-        ))
+
+        if !feed_tokens::revoke_token(&mut connection, account_id, &token).await? {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "the requested feed token does not exist",
+            ));
+        }
+
+        Ok(Redirect::to("/profile"))
     } else {
         Err((
             StatusCode::BAD_REQUEST,
@@ -1369,34 +2779,25 @@ pub async fn get_profile(
     }
 }
 
-pub async fn post_profile(
-    Extension(style_id): Extension<model::StyleId>,
+pub async fn post_create_invite(
     Extension(sqlite): Extension<SqlitePool>,
-    Extension(lock_map): Extension<&'static LockMap>,
+    Extension(route_configuration): Extension<RouteConfiguration>,
     cookies: Option<TypedHeader<Cookie>>,
-    Form(model::PostProfile {
-        refresh_account_id,
-        tags,
-        new_style_id,
-    }): Form<model::PostProfile>,
-) -> impl IntoResponse {
-    trace!(
-        "profile post-ed, tags: \"{}\", refresh_account_id: {}, cookies: {:?}",
-        tags,
-        refresh_account_id,
-        cookies
-    );
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("invite creation requested, cookies: {:?}", cookies);
 
     coz_progress!();
 
+    if route_configuration.signup_mode != SignupMode::Invite {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "invites are only needed while signups are invite-only",
+        ));
+    }
+
     if let Some(cookies) = cookies
         && let Some(account_id) = cookies.get("flock.id") {
-        debug!(
-            "account {} making modifications to their profile, tags: \"{}\", refresh_account_id: {}",
-            account_id,
-            tags,
-            refresh_account_id,
-        );
+        debug!("account {} creating an invite", account_id);
 
         let mut connection = sqlite.acquire().await.map_err(|_| {
             (
@@ -1424,140 +2825,20 @@ pub async fn post_profile(
             ));
         }
 
-        if new_style_id != style_id.0.as_deref().unwrap_or("") {
-            if new_style_id.is_empty() {
-                sqlx::query!(
-                    "UPDATE accounts SET style_id = null WHERE account_id = ?",
-                    account_id
-                )
-                .execute(&mut *connection)
-                .await
-                .map_err(|_| {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "unable to update an account",
-                    )
-                })?;
-            } else if sqlx::query_scalar!(
-                "SELECT 1 FROM styles WHERE style_id = ?",
-                new_style_id
-            )
-            .fetch_optional(&mut *connection)
-            .await
-            .map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to check if a style exists",
-                )
-            })?
-            .is_some() {
-                sqlx::query!(
-                    "UPDATE accounts SET style_id = ? WHERE account_id = ?",
-                    new_style_id,
-                    account_id
-                )
-                .execute(&mut *connection)
-                .await
-                .map_err(|_| {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "unable to update an account",
-                    )
-                })?;
-            } else {
-                return Err((
-                    StatusCode::BAD_REQUEST,
-                    "invalid style id",
-                ));
-            }
-        }
-
-        let tags_owned = retrieve_tags_from_string(&mut connection, tags).await?;
-        let tags = tags_owned
-            .iter()
-            .map(|t| t.as_str())
-            .collect::<HashSet<_>>();
-
-        let _tag_lock = lock_map.lock(account_id).ok_or((
-            StatusCode::SERVICE_UNAVAILABLE,
-            "a lock is currently held on your account's tag information. try again in a few seconds",
-        ))?;
-
-        let old_tags_owned = sqlx::query_scalar!(
-            r#"SELECT tag_id as "tag_id!" FROM scores WHERE id = ?"#,
-            account_id
+        signup::create_invite(
+            &mut connection,
+            Some(account_id),
+            route_configuration.invite_quota,
         )
-        .fetch_all(&mut *connection)
-        .await
-        .map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to query the account's tags from the db",
-            )
-        })?;
-        let old_tags = old_tags_owned
-            .iter()
-            .map(|t| t.as_str())
-            .collect::<HashSet<_>>();
-
-        for tag in &old_tags - &tags {
-            sqlx::query!(r"DELETE FROM scores WHERE id = ? AND tag_id = ?", account_id, tag)
-                .execute(&mut *connection)
-                .await
-                .map_err(|_| {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "unable to remove an old tag from the db",
-                    )
-                })?;
-        }
-
-        for tag in &tags - &old_tags {
-            let score = ScaledRating::new(
-                0.0,
-                350.0 / instant_glicko_2::constants::RATING_SCALING_RATIO,
-                0.06,
-            );
-            let last_period = SystemTime::UNIX_EPOCH
-                .elapsed()
-                .map_err(|_| {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "unable to calculate the amount of time that has passed since the unix epoch",
-                    )
-                })?
-                .as_secs();
-
-            let score = rmp_serde::to_vec(&model::Score {
-                score,
-                last_period,
-                result_queue: vec![],
-            })
-            .map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to convert data to messagepack",
-                )
-            })?;
+        .await?;
 
-            sqlx::query!(
-                r"INSERT INTO scores (id, tag_id, score) VALUES (?, ?, ?)",
-                account_id,
-                tag,
-                score
-            )
-            .execute(&mut *connection)
-            .await
-            .map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to insert a tag score into the db",
-                )
-            })?;
-        }
+        Ok(Redirect::to("/profile"))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
     }
-
-    Ok(Redirect::to("/"))
 }
 
 pub async fn get_feed_xml(
@@ -1565,17 +2846,98 @@ pub async fn get_feed_xml(
     Extension(algorithm_configuration): Extension<AlgorithmConfiguration>,
     Extension(lock_map): Extension<&'static LockMap>,
     Extension(http_configuration): Extension<HttpConfiguration>,
-    Query(model::FeedXml { account_id }): Query<model::FeedXml>,
-) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
-    trace!("feed.xml requested, account id: {}", &account_id);
+    Extension(signing_key): Extension<&'static SigningKey>,
+    Extension(style_cache): Extension<&'static StyleCache>,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
+    Query(model::FeedXml { token }): Query<model::FeedXml>,
+) -> Result<Response<UnsyncBoxBody<Bytes, axum::Error>>, (StatusCode, &'static str)> {
+    syndicate_feed(
+        sqlite,
+        algorithm_configuration,
+        lock_map,
+        http_configuration,
+        signing_key,
+        style_cache,
+        if_none_match,
+        if_modified_since,
+        token,
+        syndication::Format::Rss,
+    )
+    .await
+}
 
-    coz_progress!();
+pub async fn get_feed_atom(
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(algorithm_configuration): Extension<AlgorithmConfiguration>,
+    Extension(lock_map): Extension<&'static LockMap>,
+    Extension(http_configuration): Extension<HttpConfiguration>,
+    Extension(signing_key): Extension<&'static SigningKey>,
+    Extension(style_cache): Extension<&'static StyleCache>,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
+    Query(model::FeedXml { token }): Query<model::FeedXml>,
+) -> Result<Response<UnsyncBoxBody<Bytes, axum::Error>>, (StatusCode, &'static str)> {
+    syndicate_feed(
+        sqlite,
+        algorithm_configuration,
+        lock_map,
+        http_configuration,
+        signing_key,
+        style_cache,
+        if_none_match,
+        if_modified_since,
+        token,
+        syndication::Format::Atom,
+    )
+    .await
+}
+
+pub async fn get_feed_json(
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(algorithm_configuration): Extension<AlgorithmConfiguration>,
+    Extension(lock_map): Extension<&'static LockMap>,
+    Extension(http_configuration): Extension<HttpConfiguration>,
+    Extension(signing_key): Extension<&'static SigningKey>,
+    Extension(style_cache): Extension<&'static StyleCache>,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
+    Query(model::FeedXml { token }): Query<model::FeedXml>,
+) -> Result<Response<UnsyncBoxBody<Bytes, axum::Error>>, (StatusCode, &'static str)> {
+    syndicate_feed(
+        sqlite,
+        algorithm_configuration,
+        lock_map,
+        http_configuration,
+        signing_key,
+        style_cache,
+        if_none_match,
+        if_modified_since,
+        token,
+        syndication::Format::Json,
+    )
+    .await
+}
+
+#[inline(always)]
+#[allow(clippy::too_many_arguments)]
+pub async fn syndicate_feed(
+    sqlite: SqlitePool,
+    algorithm_configuration: AlgorithmConfiguration,
+    lock_map: &'static LockMap,
+    http_configuration: HttpConfiguration,
+    signing_key: &'static SigningKey,
+    style_cache: &'static StyleCache,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+    if_modified_since: Option<TypedHeader<IfModifiedSince>>,
+    token: String,
+    format: syndication::Format,
+) -> Result<Response<UnsyncBoxBody<Bytes, axum::Error>>, (StatusCode, &'static str)> {
+    trace!("{} requested", format.path());
 
-    //TODO(superwhiskers): factor out this and the body of the get_index function into a
-    //                     separate function or something
-    if !account_id.is_empty() {
-        trace!("preparing feed.xml for {}", &account_id);
+    coz_progress!();
 
+    if !token.is_empty() {
         let mut connection = sqlite.acquire().await.map_err(|_| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -1583,145 +2945,93 @@ pub async fn get_feed_xml(
             )
         })?;
 
-        if let Some(account) = sqlx::query!(
-            r#"SELECT feed as "feed!", style_id as "style_id?" FROM accounts WHERE account_id = ?"#,
-            account_id
-        )
-        .fetch_optional(&mut *connection)
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to query the db"))?
-        {
-            let mut feed = rmp_serde::from_slice::<model::Feed>(&account.feed).map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "unable to deserialize the feed",
-                )
-            })?;
-
-            debug!("deserialized feed for {}: {:?}", &account_id, feed);
-
-            if (SystemTime::UNIX_EPOCH + Duration::from_secs(feed.refreshed))
-                .elapsed()
-                .map_err(|_| {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "unable to calculate the amount of time that has passed since the last time the feed was refreshed",
-                    )
-                })?
-                    > algorithm_configuration.feed_refresh_period {
-                trace!("generating new feed for {}", &account_id);
-
-                trace!("locking the account's tags");
-
-                let _tag_lock = lock_map.lock(&account_id).ok_or((
-                    StatusCode::SERVICE_UNAVAILABLE,
-                    "a lock is currently held on your account's tag information. try again in a few seconds",
-                ))?;
-
-                feed = model::Feed {
-                    links: feed::generate_feed(&algorithm_configuration, sqlite.acquire().await.map_err(|_| {
-                        (
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            "unable to acquire a db connection",
-                        )
-                    })?, &account_id).await?,
-                    refreshed: SystemTime::UNIX_EPOCH
-                        .elapsed()
-                        .map_err(|_| {
-                            (
-                                StatusCode::INTERNAL_SERVER_ERROR,
-                                "unable to calculate the amount of time that has passed since the unix epoch",
-                            )
-                        })?
-                        .as_secs(),
-                };
-
-                debug!("new feed for {}: {:?}", &account_id, feed);
+        let account_id = feed_tokens::account_from_token(&mut connection, &token)
+            .await?
+            .ok_or((
+                StatusCode::BAD_REQUEST,
+                "the requested feed token does not exist",
+            ))?;
 
-                let serialized_feed = rmp_serde::to_vec(&feed)
-                    .map_err(|_| {
-                        (
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            "unable to serialize the new feed",
-                        )
-                    })?;
+        trace!("preparing {} for {}", format.path(), &account_id);
 
-                sqlx::query!(
-                    r"UPDATE accounts SET feed = ? WHERE account_id = ?",
-                    serialized_feed,
-                    account_id
-                )
-                .execute(&mut *connection)
-                .await
-                .map_err(|_| {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "unable to update the feed",
-                    )
-                })?;
-            }
+        if let Some(feed) = feed::load_feed(
+            &algorithm_configuration,
+            &sqlite,
+            lock_map,
+            &mut connection,
+            &account_id,
+        )
+        .await?
+        {
+            let style_id = style_cache.account_style(&sqlite, &account_id).await?;
 
-            let mut links = Vec::with_capacity(feed.links.len());
-            for (link_id, _) in feed.links {
-                let description = sqlx::query!(
-                    r#"SELECT description as "description!" FROM links WHERE link_id = ?"#,
-                    link_id,
-                )
-                .fetch_one(&mut *connection)
-                .await
-                .map_err(|_| {
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "unable to query for a link's information",
-                    )
-                })?
-                .description;
-
-                links.push(
-                    rss::ItemBuilder::default()
-                        .title(description)
-                        .description(
-                            templates::FeedItem {
-                                style_id: model::StyleId(account.style_id.clone()),
-                                flock_host: http_configuration.host.clone(),
-                                link_id: link_id.clone(),
-                            }
-                            .render()
-                            .map_err(|_| {
-                                (
-                                    StatusCode::INTERNAL_SERVER_ERROR,
-                                    "unable to render an rss feed item's description",
-                                )
-                            })?,
-                        )
-                        .link(format!(
-                            "{}/links/{}",
-                            http_configuration.host.clone(),
-                            link_id.clone()
-                        ))
-                        .guid(
-                            rss::GuidBuilder::default()
-                                .value(link_id)
-                                .permalink(false)
-                                .build(),
-                        )
-                        .build(),
-                );
+            let items = syndication::feed_items(
+                &mut connection,
+                &feed,
+                style_id,
+                &http_configuration.host,
+                signing_key,
+                &token,
+            )
+            .await?;
+
+            let last_modified =
+                syndication::last_modified(&mut connection, &account_id, &feed).await?;
+            let etag = syndication::etag(format, last_modified, &items)?;
+
+            // a missing if-none-match header is extracted as an empty one rather than `None`,
+            // which would otherwise keep if-modified-since from ever being checked
+            let if_none_match = if_none_match.filter(|TypedHeader(if_none_match)| {
+                let mut values = Vec::new();
+                if_none_match.encode(&mut values);
+
+                values.iter().any(|value| !value.is_empty())
+            });
+
+            // if-none-match takes precedence over if-modified-since when both are present
+            let not_modified = if let Some(TypedHeader(if_none_match)) = if_none_match {
+                !if_none_match.precondition_passes(&etag)
+            } else if let Some(TypedHeader(if_modified_since)) = if_modified_since {
+                !if_modified_since.is_modified(last_modified)
+            } else {
+                false
+            };
+
+            if not_modified {
+                trace!("{} for {} is unchanged", format.path(), &account_id);
+
+                return Ok((
+                    StatusCode::NOT_MODIFIED,
+                    TypedHeader(etag),
+                    TypedHeader(LastModified::from(last_modified)),
+                )
+                    .into_response());
             }
 
             trace!("sending response to {}", &account_id);
 
             Ok((
-                [("Content-Type", "application/rss+xml")],
-                rss::ChannelBuilder::default()
-                    .title("flock")
-                    .description(format!("the flock feed for account {}", account_id))
-                    .link(http_configuration.host)
-                    .docs("https://www.rssboard.org/rss-specification".to_string())
-                    .items(links)
-                    .build()
-                    .to_string(),
-            ))
+                [("Content-Type", format.content_type())],
+                TypedHeader(etag),
+                TypedHeader(LastModified::from(last_modified)),
+                syndication::render(
+                    format,
+                    syndication::Document {
+                        title: "flock".to_string(),
+                        description: "a personalized feed of links from flock".to_string(),
+                        feed_url: format!(
+                            "{}{}?token={}",
+                            http_configuration.host,
+                            format.path(),
+                            urlencoding::encode(&token)
+                        ),
+                        home_url: http_configuration.host,
+                        updated: last_modified,
+                        items,
+                    },
+                )?,
+            )
+                .into_response())
         } else {
             Err((
                 StatusCode::BAD_REQUEST,
@@ -1729,48 +3039,59 @@ pub async fn get_feed_xml(
             ))
         }
     } else {
-        trace!("an attempt was made to access an rss feed without an account");
+        trace!("an attempt was made to access a feed without a feed token");
 
         Err((
             StatusCode::BAD_REQUEST,
-            "in order to use the rss feed, you must provide an account id",
+            "in order to use a feed, you must provide a feed token",
         ))
     }
 }
 
 pub async fn get_style(
     Extension(sqlite): Extension<SqlitePool>,
+    Extension(style_cache): Extension<&'static StyleCache>,
     Path(style_id): Path<String>,
-) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
-    trace!("style requested, style id: {}", &style_id);
+    Query(model::StyleVersion { version }): Query<model::StyleVersion>,
+    if_none_match: Option<TypedHeader<IfNoneMatch>>,
+) -> Result<Response<UnsyncBoxBody<Bytes, axum::Error>>, (StatusCode, &'static str)> {
+    trace!("style requested, style id: {}, version: {:?}", &style_id, &version);
 
     coz_progress!();
 
-    let mut connection = sqlite.acquire().await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "unable to acquire a db connection",
-        )
-    })?;
+    let style = style_cache
+        .get(&sqlite, &style_id, version.as_deref())
+        .await?
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            "the requested style does not exist",
+        ))?;
 
-    let style = sqlx::query_scalar!(
-        r#"SELECT style as "style!" FROM styles WHERE style_id = ?"#,
-        style_id
-    )
-    .fetch_optional(&mut *connection)
-    .await
-    .map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "unable to query the db for the style",
+    // a version's stylesheet never changes, but anything else may be out of date
+    let cache_control = if version.as_deref() == Some(style.version.as_str()) {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    };
+
+    if let Some(TypedHeader(if_none_match)) = if_none_match
+        && !if_none_match.precondition_passes(&style.etag) {
+        trace!("style {} is unchanged", &style_id);
+
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [("Cache-Control", cache_control)],
+            TypedHeader(style.etag.clone()),
         )
-    })?
-    .ok_or((
-        StatusCode::BAD_REQUEST,
-        "the requested style does not exist",
-    ))?;
+            .into_response());
+    }
 
-    Ok(([("Content-Type", "text/css")], style))
+    Ok((
+        [("Content-Type", "text/css"), ("Cache-Control", cache_control)],
+        TypedHeader(style.etag.clone()),
+        style.stylesheet.clone(),
+    )
+        .into_response())
 }
 
 pub async fn get_welcome(
@@ -1802,6 +3123,7 @@ pub async fn get_welcome(
 
 pub async fn get_post_style(
     Extension(style_id): Extension<model::StyleId>,
+    Extension(route_configuration): Extension<RouteConfiguration>,
 ) -> impl IntoResponse {
     trace!("post-style requested");
 
@@ -1809,13 +3131,17 @@ pub async fn get_post_style(
 
     (
         [("Content-Type", "application/xhtml+xml")],
-        templates::PostStyle { style_id },
+        templates::PostStyle {
+            style_id,
+            max_style_size: route_configuration.max_style_size,
+        },
     )
 }
 
 pub async fn post_post_style(
     Extension(sqlite): Extension<SqlitePool>,
     Extension(style_id): Extension<model::StyleId>,
+    Extension(route_configuration): Extension<RouteConfiguration>,
     cookies: Option<TypedHeader<Cookie>>,
     mut multipart: Multipart,
 ) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
@@ -1851,67 +3177,196 @@ pub async fn post_post_style(
             ));
         }
 
-        let (name, stylesheet) = loop {
-            if let Some(field) = multipart
-                .next_field()
-                .await
-                .map_err(|_| {
-                    (
-                        StatusCode::BAD_REQUEST,
-                        "unable to read multipart form data",
-                    )
-                })? {
-                if field.name() == Some("stylesheet")
-                   && field.content_type() == Some("text/css") {
-                    break (
-                        field
-                            .file_name()
-                            .map(|n| n.trim_end_matches(".css").to_string())
-                            .unwrap_or_else(|| "unnamed".to_string()),
-                        field
-                            .text()
-                            .await
-                            .map_err(|_| {
-                                (
-                                    StatusCode::BAD_REQUEST,
-                                    "unable to read multipart form data",
-                                )
-                            })?
-                    );
-                }
-            } else {
-                return Err((
-                    StatusCode::BAD_REQUEST,
-                    "no useful multipart form data was found",
-                ));
-            }
-        };
+        let styles::Upload {
+            name,
+            file_name,
+            stylesheet,
+        } = styles::read_upload(&mut multipart, route_configuration.max_style_size).await?;
 
-        let new_style_id = Ulid::with_source(&mut pcg_thread_rng()).to_string();
+        let css::Sanitized { stylesheet, removals } = stylesheet.ok_or((
+            StatusCode::BAD_REQUEST,
+            "no useful multipart form data was found",
+        ))?;
+        let name = name.or(file_name).unwrap_or_else(|| "unnamed".to_string());
 
-        debug!("style id generated: {}", new_style_id);
+        let new_style_id =
+            styles::create(&mut connection, account_id, &name, &stylesheet).await?;
 
-        sqlx::query!(
-            "INSERT INTO styles (style_id, name, creator, style) VALUES (?, ?, ?, ?)",
-            new_style_id,
-            name,
-            account_id,
-            stylesheet
+        Ok((
+            [("Content-Type", "application/xhtml+xml")],
+            templates::PostStyleResult {
+                style_id,
+                created_style_id: new_style_id,
+                removals,
+            },
+        ))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
+    }
+}
+
+pub async fn get_styles(
+    Extension(style_id): Extension<model::StyleId>,
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(creator_key): Extension<&'static CreatorKey>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Query(model::Styles { sort, page }): Query<model::Styles>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("styles requested, sort: {:?}, page: {}, cookies: {:?}", sort, page, cookies);
+
+    coz_progress!();
+
+    let account_id = cookies
+        .as_ref()
+        .and_then(|cookies| cookies.get("flock.id"));
+
+    let mut connection = sqlite.acquire().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to acquire a db connection",
         )
-        .execute(&mut *connection)
-        .await
-        .map_err(|_| {
+    })?;
+
+    let styles = styles::gallery(&mut connection, creator_key, account_id, sort, page).await?;
+
+    Ok((
+        [("Content-Type", "application/xhtml+xml")],
+        templates::Styles {
+            style_id,
+            logged_in: account_id.is_some(),
+            sort,
+            page,
+            next_page: (styles.len() == styles::STYLE_PAGE_LENGTH).then_some(page + 1),
+            styles,
+        },
+    ))
+}
+
+pub async fn post_use_style(
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(style_cache): Extension<&'static StyleCache>,
+    Path(edited_style_id): Path<String>,
+    cookies: Option<TypedHeader<Cookie>>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("style {} applied, cookies: {:?}", edited_style_id, cookies);
+
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
+
+        styles::apply(&mut connection, account_id, &edited_style_id).await?;
+
+        style_cache.invalidate_account(account_id);
+
+        Ok(Redirect::to("/styles"))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
+    }
+}
+
+pub async fn get_edit_style(
+    Extension(style_id): Extension<model::StyleId>,
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(route_configuration): Extension<RouteConfiguration>,
+    Path(edited_style_id): Path<String>,
+    cookies: Option<TypedHeader<Cookie>>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("edit-style for {} requested, cookies: {:?}", edited_style_id, cookies);
+
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        let mut connection = sqlite.acquire().await.map_err(|_| {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to insert the stylesheet into the db",
+                "unable to acquire a db connection",
             )
         })?;
 
+        let name = styles::owned_style_name(&mut connection, account_id, &edited_style_id).await?;
+
         Ok((
             [("Content-Type", "application/xhtml+xml")],
-            templates::PostStyleResult {
+            templates::EditStyle {
                 style_id,
-                created_style_id: new_style_id,
+                edited_style_id,
+                name,
+                max_style_size: route_configuration.max_style_size,
+                saved: false,
+                removals: Vec::new(),
+            },
+        ))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
+    }
+}
+
+pub async fn post_edit_style(
+    Extension(style_id): Extension<model::StyleId>,
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(style_cache): Extension<&'static StyleCache>,
+    Extension(route_configuration): Extension<RouteConfiguration>,
+    Path(edited_style_id): Path<String>,
+    cookies: Option<TypedHeader<Cookie>>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!("edit-style for {} posted, cookies: {:?}", edited_style_id, cookies);
+
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
+
+        let styles::Upload { name, stylesheet, .. } =
+            styles::read_upload(&mut multipart, route_configuration.max_style_size).await?;
+        let (stylesheet, removals) = stylesheet
+            .map(|css::Sanitized { stylesheet, removals }| (Some(stylesheet), removals))
+            .unwrap_or_default();
+
+        styles::update(
+            &mut connection,
+            style_cache,
+            account_id,
+            &edited_style_id,
+            name.as_deref(),
+            stylesheet.as_deref(),
+        )
+        .await?;
+
+        let name = styles::owned_style_name(&mut connection, account_id, &edited_style_id).await?;
+
+        Ok((
+            [("Content-Type", "application/xhtml+xml")],
+            templates::EditStyle {
+                style_id,
+                edited_style_id,
+                name,
+                max_style_size: route_configuration.max_style_size,
+                saved: true,
+                removals,
             },
         ))
     } else {
@@ -1921,3 +3376,199 @@ pub async fn post_post_style(
         ))
     }
 }
+
+pub async fn post_delete_style(
+    Extension(sqlite): Extension<SqlitePool>,
+    Extension(style_cache): Extension<&'static StyleCache>,
+    Path(edited_style_id): Path<String>,
+    cookies: Option<TypedHeader<Cookie>>,
+    Form(model::DeleteStyle { confirm }): Form<model::DeleteStyle>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    trace!(
+        "style {} deletion requested, confirm: {}, cookies: {:?}",
+        edited_style_id,
+        confirm,
+        cookies
+    );
+
+    coz_progress!();
+
+    if let Some(cookies) = cookies
+        && let Some(account_id) = cookies.get("flock.id") {
+        if !confirm {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "you must confirm that you want to delete the style",
+            ));
+        }
+
+        let mut connection = sqlite.acquire().await.map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to acquire a db connection",
+            )
+        })?;
+
+        styles::delete(&mut connection, style_cache, account_id, &edited_style_id).await?;
+
+        Ok(Redirect::to("/styles"))
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            "you are not logged in",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::{Path, Query},
+        headers::{ETag, IfNoneMatch},
+        http::StatusCode,
+        Extension, TypedHeader,
+    };
+    use sqlx::SqlitePool;
+    use std::time::Duration;
+
+    use super::{get_style, rate_link};
+    use crate::{
+        configuration::Algorithm, feed_tokens::SigningKey, locks::LockMap, model, schema,
+        styles::StyleCache,
+    };
+
+    async fn rate(
+        sqlite: &SqlitePool,
+        signing_key: &'static SigningKey,
+        signature: String,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        rate_link(
+            Algorithm::default(),
+            sqlite.clone(),
+            LockMap::new(Duration::from_secs(1)),
+            signing_key,
+            None,
+            model::SignedRating {
+                token: Some("token".to_string()),
+                signature: Some(signature),
+            },
+            None,
+            "link".to_string(),
+            model::Rating::Promote,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    #[tokio::test]
+    async fn signed_ratings_are_single_use() {
+        let sqlite = schema::memory().await;
+        let signing_key = SigningKey::load(&sqlite).await.unwrap();
+
+        sqlx::query(
+            "INSERT INTO accounts (account_id) VALUES ('account');
+             INSERT INTO feed_tokens VALUES ('token', 'account', 0);
+             INSERT INTO links VALUES ('link', 'https://example.com', 'a link');
+             INSERT INTO tags VALUES ('cats', 'cats');
+             INSERT INTO scores VALUES ('account', 'cats', 0, 2, 0.06, 0),
+                                       ('link', 'cats', 0, 2, 0.06, 0);",
+        )
+        .execute(&sqlite)
+        .await
+        .unwrap();
+
+        let signature = signing_key.sign_rating("token", "link", model::Rating::Promote);
+        let forged = signing_key.sign_rating("token", "link", model::Rating::Demote);
+
+        assert_eq!(
+            rate(&sqlite, signing_key, forged).await,
+            Err((
+                StatusCode::BAD_REQUEST,
+                "the rating url's signature is invalid"
+            ))
+        );
+        assert_eq!(rate(&sqlite, signing_key, signature.clone()).await, Ok(()));
+        assert_eq!(
+            rate(&sqlite, signing_key, signature).await,
+            Err((
+                StatusCode::BAD_REQUEST,
+                "this rating url has already been used"
+            ))
+        );
+
+        let rated = sqlx::query_scalar::<_, bool>(
+            "SELECT rated FROM seen WHERE account_id = 'account' AND link_id = 'link'",
+        )
+        .fetch_one(&sqlite)
+        .await
+        .unwrap();
+        assert!(rated);
+    }
+
+    /// Request a style, returning the response's status, `Cache-Control` and `ETag`
+    async fn style(
+        sqlite: &SqlitePool,
+        version: Option<&str>,
+        etag: Option<ETag>,
+    ) -> (StatusCode, String, ETag) {
+        let response = get_style(
+            Extension(sqlite.clone()),
+            Extension(StyleCache::new(1, 1)),
+            Path("style".to_string()),
+            Query(model::StyleVersion {
+                version: version.map(str::to_string),
+            }),
+            etag.map(|etag| TypedHeader(IfNoneMatch::from(etag))),
+        )
+        .await
+        .unwrap();
+
+        let header = |name| response.headers()[name].to_str().unwrap().to_string();
+        (
+            response.status(),
+            header("Cache-Control"),
+            header("ETag").parse().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn only_versioned_styles_are_cached_indefinitely() {
+        let sqlite = schema::memory().await;
+
+        sqlx::query(
+            "INSERT INTO styles VALUES ('style', 'a style', 'account', 'a {}');
+             INSERT INTO style_versions VALUES ('style', 'current');",
+        )
+        .execute(&sqlite)
+        .await
+        .unwrap();
+
+        let (status, cache_control, etag) = style(&sqlite, Some("current"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(cache_control, "public, max-age=31536000, immutable");
+
+        for version in [None, Some("outdated")] {
+            let (status, cache_control, _) = style(&sqlite, version, None).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(cache_control, "no-cache");
+        }
+
+        let (status, cache_control, _) = style(&sqlite, None, Some(etag.clone())).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert_eq!(cache_control, "no-cache");
+
+        let (status, cache_control, _) = style(&sqlite, Some("current"), Some(etag)).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert_eq!(cache_control, "public, max-age=31536000, immutable");
+
+        assert!(get_style(
+            Extension(sqlite.clone()),
+            Extension(StyleCache::new(1, 1)),
+            Path("missing".to_string()),
+            Query(model::StyleVersion { version: None }),
+            None,
+        )
+        .await
+        .is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use anyhow::{anyhow, bail, Context};
+use sqlx::{Connection, Executor, Sqlite, SqliteConnection};
+use tracing::info;
+
+use crate::{feed, model, scores, search};
+
+/// The version of the schema this build of flock expects, as recorded in the database's
+/// `user_version` pragma by `schema.sql`
+pub const SCHEMA_VERSION: i64 = 7;
+
+/// The schema itself
+const SCHEMA: &str = include_str!("../schema.sql");
+
+/// Read the version of the schema a database was created with
+pub async fn version(connection: &mut SqliteConnection) -> sqlx::Result<i64> {
+    sqlx::query_scalar::<Sqlite, i64>("PRAGMA user_version")
+        .fetch_one(connection)
+        .await
+}
+
+/// Bring a database up to the schema this build expects, returning the version it was at
+/// beforehand
+///
+/// Every statement in the schema is idempotent, so applying it to an empty database creates
+/// everything and applying it to an older one fills in whatever is missing. Data which can't
+/// be converted by sql alone (scores and feeds stored as messagepack) is converted here
+pub async fn migrate(connection: &mut SqliteConnection) -> anyhow::Result<i64> {
+    let previous_version = version(connection)
+        .await
+        .context("unable to read the db's schema version")?;
+
+    if previous_version > SCHEMA_VERSION {
+        bail!(
+            "the db has schema version {}, which is newer than this build of flock's schema version {}",
+            previous_version,
+            SCHEMA_VERSION
+        );
+    }
+
+    let mut transaction = connection
+        .begin()
+        .await
+        .context("unable to begin a transaction")?;
+
+    // scores were stored as messagepack before version 6. the old table is moved out of the
+    // way of the new one, and its scores are converted once the schema has been applied
+    let legacy_scores = sqlx::query_scalar::<Sqlite, i64>(
+        "SELECT COUNT(1) FROM pragma_table_info('scores') WHERE name = 'score'",
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("unable to inspect the scores table")?
+        > 0;
+
+    if legacy_scores {
+        transaction
+            .execute("ALTER TABLE scores RENAME TO legacy_scores")
+            .await
+            .context("unable to move the old scores table")?;
+    }
+
+    // feeds were stored as messagepack in the accounts table before version 7. the schema
+    // doesn't touch an existing accounts table, so they can be converted afterwards
+    let legacy_feeds = sqlx::query_scalar::<Sqlite, i64>(
+        "SELECT COUNT(1) FROM pragma_table_info('accounts') WHERE name = 'feed'",
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("unable to inspect the accounts table")?
+        > 0;
+
+    transaction
+        .execute(SCHEMA)
+        .await
+        .context("unable to apply the schema")?;
+
+    if legacy_scores {
+        convert_legacy_scores(&mut transaction).await?;
+    }
+
+    if legacy_feeds {
+        convert_legacy_feeds(&mut transaction).await?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("unable to commit the migration")?;
+
+    Ok(previous_version)
+}
+
+/// Convert the messagepack-encoded scores in the `legacy_scores` table into rows of the
+/// `scores` and `score_results` tables, dropping it afterwards and rebuilding the search index
+async fn convert_legacy_scores(connection: &mut SqliteConnection) -> anyhow::Result<()> {
+    let legacy_scores = sqlx::query_as::<Sqlite, (String, String, Vec<u8>)>(
+        "SELECT id, tag_id, score FROM legacy_scores",
+    )
+    .fetch_all(&mut *connection)
+    .await
+    .context("unable to query the old scores")?;
+
+    for (id, tag_id, score) in &legacy_scores {
+        let score = rmp_serde::from_slice::<model::Score>(score)
+            .with_context(|| format!("unable to deserialize the score for ({}, {})", id, tag_id))?;
+
+        scores::store(connection, id, tag_id, &score)
+            .await
+            .map_err(|(_, message)| anyhow!(message))?;
+    }
+
+    connection
+        .execute("DROP TABLE legacy_scores")
+        .await
+        .context("unable to drop the old scores table")?;
+
+    // the search index is filled in from the tags in `scores` when the schema is applied,
+    // which was still empty at the time
+    let link_ids = sqlx::query_scalar::<Sqlite, String>("SELECT link_id FROM links")
+        .fetch_all(&mut *connection)
+        .await
+        .context("unable to query the links to reindex")?;
+
+    for link_id in &link_ids {
+        search::index_link(connection, link_id)
+            .await
+            .map_err(|(_, message)| anyhow!(message))?;
+    }
+
+    info!(
+        "converted {} scores from messagepack, reindexing {} links",
+        legacy_scores.len(),
+        link_ids.len()
+    );
+
+    Ok(())
+}
+
+/// Convert the messagepack-encoded feeds in the `feed` column of the `accounts` table into rows
+/// of the `feeds`, `feed_items` and `feed_item_tags` tables, dropping the column afterwards
+async fn convert_legacy_feeds(connection: &mut SqliteConnection) -> anyhow::Result<()> {
+    let legacy_feeds =
+        sqlx::query_as::<Sqlite, (String, Vec<u8>)>("SELECT account_id, feed FROM accounts")
+            .fetch_all(&mut *connection)
+            .await
+            .context("unable to query the old feeds")?;
+
+    let mut converted = 0;
+
+    for (account_id, feed) in &legacy_feeds {
+        let feed = rmp_serde::from_slice::<model::Feed>(feed)
+            .with_context(|| format!("unable to deserialize the feed of account {}", account_id))?;
+
+        // accounts which have never had a feed generated have nothing to convert
+        if feed.refreshed == 0 && feed.links.is_empty() {
+            continue;
+        }
+
+        feed::store_feed(connection, account_id, &feed)
+            .await
+            .map_err(|(_, message)| anyhow!(message))?;
+
+        converted += 1;
+    }
+
+    connection
+        .execute("ALTER TABLE accounts DROP COLUMN feed")
+        .await
+        .context("unable to drop the old feed column")?;
+
+    info!("converted {} feeds from messagepack", converted);
+
+    Ok(())
+}
+
+/// Open an empty in-memory database with the schema applied, for tests of code which queries
+/// the db
+///
+/// The pool is limited to a single connection which is never closed, as every connection to
+/// an in-memory database gets a database of its own
+#[cfg(test)]
+pub async fn memory() -> sqlx::SqlitePool {
+    let sqlite = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .expect("unable to open an in-memory db");
+
+    migrate(
+        &mut sqlite
+            .acquire()
+            .await
+            .expect("unable to acquire a connection"),
+    )
+    .await
+    .expect("unable to apply the schema");
+
+    sqlite
+}
@@ -0,0 +1,384 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use axum::http::StatusCode;
+use instant_glicko_2::ScaledRating;
+use sqlx::{Connection, SqliteConnection};
+use std::collections::HashMap;
+
+use crate::model;
+
+/// The score of an account or link for one of its tags
+#[derive(Debug, Clone)]
+pub struct TagScore {
+    pub tag_id: String,
+    pub name: String,
+    pub score: model::Score,
+}
+
+/// Load the scores of an account or link for each of its tags, ordered by the tags' names
+pub async fn load(
+    connection: &mut SqliteConnection,
+    id: &str,
+) -> Result<Vec<TagScore>, (StatusCode, &'static str)> {
+    let mut result_queues = load_result_queues(connection, id, None).await?;
+
+    Ok(sqlx::query!(
+        r#"SELECT scores.tag_id as "tag_id!",
+                  tags.name as "name!",
+                  scores.rating as "rating!: f64",
+                  scores.deviation as "deviation!: f64",
+                  scores.volatility as "volatility!: f64",
+                  scores.last_period as "last_period!: i64"
+             FROM scores
+       INNER JOIN tags ON tags.tag_id = scores.tag_id
+            WHERE scores.id = ?
+         ORDER BY tags.name"#,
+        id
+    )
+    .fetch_all(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the tag scores from the db",
+        )
+    })?
+    .into_iter()
+    .map(|row| TagScore {
+        score: model::Score {
+            score: ScaledRating::new(row.rating, row.deviation, row.volatility),
+            last_period: row.last_period as u64,
+            result_queue: result_queues.remove(&row.tag_id).unwrap_or_default(),
+        },
+        tag_id: row.tag_id,
+        name: row.name,
+    })
+    .collect())
+}
+
+/// Load the score of an account or link for a single tag
+pub async fn load_one(
+    connection: &mut SqliteConnection,
+    id: &str,
+    tag_id: &str,
+) -> Result<Option<model::Score>, (StatusCode, &'static str)> {
+    let Some(row) = sqlx::query!(
+        r#"SELECT rating as "rating!: f64",
+                  deviation as "deviation!: f64",
+                  volatility as "volatility!: f64",
+                  last_period as "last_period!: i64"
+             FROM scores
+            WHERE id = ? AND tag_id = ?"#,
+        id,
+        tag_id
+    )
+    .fetch_optional(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query a tag score from the db",
+        )
+    })?
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(model::Score {
+        score: ScaledRating::new(row.rating, row.deviation, row.volatility),
+        last_period: row.last_period as u64,
+        result_queue: load_result_queues(connection, id, Some(tag_id))
+            .await?
+            .remove(tag_id)
+            .unwrap_or_default(),
+    }))
+}
+
+/// Load the scores of several accounts or links at once, keyed by id, with each one's scores
+/// ordered by the tags' names
+///
+/// Ids without any scores are absent from the result
+pub async fn load_many(
+    connection: &mut SqliteConnection,
+    ids: &[String],
+) -> Result<HashMap<String, Vec<TagScore>>, (StatusCode, &'static str)> {
+    let ids = serde_json::to_string(ids).map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to convert the ids to json",
+        )
+    })?;
+
+    let mut result_queues: HashMap<(String, String), Vec<model::QueuedResult>> = HashMap::new();
+    for row in sqlx::query!(
+        r#"SELECT id as "id!",
+                  tag_id as "tag_id!",
+                  opponent_rating as "opponent_rating!: f64",
+                  opponent_deviation as "opponent_deviation!: f64",
+                  opponent_volatility as "opponent_volatility!: f64",
+                  score as "score!: f64"
+             FROM score_results
+            WHERE id IN (SELECT value FROM json_each(?))
+         ORDER BY id, tag_id, position"#,
+        ids
+    )
+    .fetch_all(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the queued results of the tag scores from the db",
+        )
+    })? {
+        result_queues
+            .entry((row.id, row.tag_id))
+            .or_default()
+            .push(model::QueuedResult {
+                opponent: ScaledRating::new(
+                    row.opponent_rating,
+                    row.opponent_deviation,
+                    row.opponent_volatility,
+                ),
+                score: row.score,
+            });
+    }
+
+    let mut scores: HashMap<String, Vec<TagScore>> = HashMap::new();
+    for row in sqlx::query!(
+        r#"SELECT scores.id as "id!",
+                  scores.tag_id as "tag_id!",
+                  tags.name as "name!",
+                  scores.rating as "rating!: f64",
+                  scores.deviation as "deviation!: f64",
+                  scores.volatility as "volatility!: f64",
+                  scores.last_period as "last_period!: i64"
+             FROM scores
+       INNER JOIN tags ON tags.tag_id = scores.tag_id
+            WHERE scores.id IN (SELECT value FROM json_each(?))
+         ORDER BY scores.id, tags.name"#,
+        ids
+    )
+    .fetch_all(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the tag scores from the db",
+        )
+    })? {
+        let result_queue = result_queues
+            .remove(&(row.id.clone(), row.tag_id.clone()))
+            .unwrap_or_default();
+
+        scores.entry(row.id).or_default().push(TagScore {
+            score: model::Score {
+                score: ScaledRating::new(row.rating, row.deviation, row.volatility),
+                last_period: row.last_period as u64,
+                result_queue,
+            },
+            tag_id: row.tag_id,
+            name: row.name,
+        });
+    }
+
+    Ok(scores)
+}
+
+/// Load the queued results of an account's or link's scores, keyed by tag, optionally only
+/// for a single tag
+async fn load_result_queues(
+    connection: &mut SqliteConnection,
+    id: &str,
+    tag_id: Option<&str>,
+) -> Result<HashMap<String, Vec<model::QueuedResult>>, (StatusCode, &'static str)> {
+    let mut result_queues: HashMap<String, Vec<model::QueuedResult>> = HashMap::new();
+
+    for row in sqlx::query!(
+        r#"SELECT tag_id as "tag_id!",
+                  opponent_rating as "opponent_rating!: f64",
+                  opponent_deviation as "opponent_deviation!: f64",
+                  opponent_volatility as "opponent_volatility!: f64",
+                  score as "score!: f64"
+             FROM score_results
+            WHERE id = ?1 AND (?2 IS NULL OR tag_id = ?2)
+         ORDER BY tag_id, position"#,
+        id,
+        tag_id
+    )
+    .fetch_all(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the queued results of the tag scores from the db",
+        )
+    })? {
+        result_queues
+            .entry(row.tag_id)
+            .or_default()
+            .push(model::QueuedResult {
+                opponent: ScaledRating::new(
+                    row.opponent_rating,
+                    row.opponent_deviation,
+                    row.opponent_volatility,
+                ),
+                score: row.score,
+            });
+    }
+
+    Ok(result_queues)
+}
+
+/// Store the score of an account or link for a tag, replacing any score it already had
+///
+/// The score and its queued results are written in a transaction (or a savepoint, if one is
+/// already open on the connection), so the queue is never left partially written
+pub async fn store(
+    connection: &mut SqliteConnection,
+    id: &str,
+    tag_id: &str,
+    score: &model::Score,
+) -> Result<(), (StatusCode, &'static str)> {
+    let (rating, deviation, volatility, last_period) = (
+        score.score.rating(),
+        score.score.deviation(),
+        score.score.volatility(),
+        score.last_period as i64,
+    );
+
+    let mut transaction = connection.begin().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to begin a transaction",
+        )
+    })?;
+
+    sqlx::query!(
+        r"INSERT INTO scores (id, tag_id, rating, deviation, volatility, last_period)
+               VALUES (?, ?, ?, ?, ?, ?)
+          ON CONFLICT (id, tag_id) DO UPDATE
+                  SET rating = excluded.rating,
+                      deviation = excluded.deviation,
+                      volatility = excluded.volatility,
+                      last_period = excluded.last_period",
+        id,
+        tag_id,
+        rating,
+        deviation,
+        volatility,
+        last_period
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to update a score",
+        )
+    })?;
+
+    sqlx::query!(
+        r"DELETE FROM score_results WHERE id = ? AND tag_id = ?",
+        id,
+        tag_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to clear the queued results of a score",
+        )
+    })?;
+
+    for (position, result) in score.result_queue.iter().enumerate() {
+        let (position, opponent_rating, opponent_deviation, opponent_volatility) = (
+            position as i64,
+            result.opponent.rating(),
+            result.opponent.deviation(),
+            result.opponent.volatility(),
+        );
+
+        sqlx::query!(
+            r"INSERT INTO score_results (id, tag_id, position, opponent_rating, opponent_deviation, opponent_volatility, score)
+                   VALUES (?, ?, ?, ?, ?, ?, ?)",
+            id,
+            tag_id,
+            position,
+            opponent_rating,
+            opponent_deviation,
+            opponent_volatility,
+            result.score
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to queue the result of a rating",
+            )
+        })?;
+    }
+
+    transaction.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to commit a score",
+        )
+    })
+}
+
+/// Give an account or link a fresh score for a tag, unless it already has one
+///
+/// Returns whether or not the score was inserted
+pub async fn insert(
+    connection: &mut SqliteConnection,
+    id: &str,
+    tag_id: &str,
+    score: ScaledRating,
+    last_period: u64,
+) -> Result<bool, (StatusCode, &'static str)> {
+    let (rating, deviation, volatility, last_period) = (
+        score.rating(),
+        score.deviation(),
+        score.volatility(),
+        last_period as i64,
+    );
+
+    Ok(sqlx::query!(
+        r"INSERT OR IGNORE INTO scores (id, tag_id, rating, deviation, volatility, last_period)
+               VALUES (?, ?, ?, ?, ?, ?)",
+        id,
+        tag_id,
+        rating,
+        deviation,
+        volatility,
+        last_period
+    )
+    .execute(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to insert a tag score into the db",
+        )
+    })?
+    .rows_affected()
+        > 0)
+}
@@ -0,0 +1,480 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use axum::http::StatusCode;
+use sqlx::{pool::PoolConnection, Sqlite, SqliteConnection};
+use std::collections::HashSet;
+use tracing::{debug, trace};
+
+use crate::{configuration::Algorithm as AlgorithmConfiguration, routes, scores, util};
+
+/// The number of results displayed on a single page of search results
+pub const SEARCH_PAGE_LENGTH: usize = 25;
+
+/// The maximum number of links considered when ranking the results of a search
+const SEARCH_CANDIDATE_LIMIT: i64 = 500;
+
+/// How much text relevance contributes to the rank of a result, with the remainder being
+/// contributed by the link's tag scores
+const RELEVANCE_WEIGHT: f64 = 0.75;
+
+/// A single result of a search
+#[derive(Debug)]
+pub struct SearchResult {
+    pub id: String,
+    pub link: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub score: f64,
+}
+
+/// A set of tags that results must (or must not) carry
+///
+/// These are written as a whitespace-delimited list of tag names, where a name prefixed with
+/// `-` excludes links carrying that tag (e.g. `rust -async`)
+#[derive(Debug, Default)]
+pub struct TagFilter {
+    pub include: HashSet<String>,
+    pub exclude: HashSet<String>,
+}
+
+impl TagFilter {
+    pub fn parse(filter: &str) -> Result<Self, (StatusCode, &'static str)> {
+        let mut tag_filter = Self::default();
+
+        for term in filter.split_whitespace() {
+            let (set, name) = if let Some(name) = term.strip_prefix('-') {
+                (&mut tag_filter.exclude, name)
+            } else {
+                (&mut tag_filter.include, term)
+            };
+
+            if !routes::TAG_REGEX.is_match(name) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "the provided tag filter is invalid",
+                ));
+            }
+
+            set.insert(name.to_ascii_lowercase());
+        }
+
+        Ok(tag_filter)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+}
+
+/// Convert user input into an fts5 query, treating every whitespace-delimited term as a
+/// string so that none of it is interpreted as query syntax
+fn to_fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .intersperse(" ".to_string())
+        .collect()
+}
+
+/// (Re)build the search index entry for a link from its current description and tags
+///
+/// Links hidden by a moderator are left out of the index
+pub async fn index_link(
+    connection: &mut SqliteConnection,
+    link_id: &str,
+) -> Result<(), (StatusCode, &'static str)> {
+    trace!("indexing link {} for search", link_id);
+
+    sqlx::query!(r"DELETE FROM links_search WHERE link_id = ?", link_id)
+        .execute(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to remove a link from the search index",
+            )
+        })?;
+
+    sqlx::query!(
+        r"INSERT INTO links_search (link_id, link, description, tags)
+               SELECT links.link_id,
+                      links.link,
+                      links.description,
+                      COALESCE(
+                          (
+                              SELECT group_concat(tags.name, ' ')
+                                FROM scores
+                          INNER JOIN tags ON scores.tag_id = tags.tag_id
+                               WHERE scores.id = links.link_id
+                          ),
+                          ''
+                      )
+                 FROM links
+                WHERE links.link_id = ?
+                  AND links.link_id NOT IN (SELECT link_id FROM hidden_links)",
+        link_id
+    )
+    .execute(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to add a link to the search index",
+        )
+    })?;
+
+    Ok(())
+}
+
+/// Search for links matching the provided text query and tag filter, ranked by a mix of
+/// text relevance and the links' tag scores
+pub async fn search(
+    algorithm_configuration: &AlgorithmConfiguration,
+    connection: &mut PoolConnection<Sqlite>,
+    query: &str,
+    filter: &TagFilter,
+    page: usize,
+) -> Result<Vec<SearchResult>, (StatusCode, &'static str)> {
+    trace!(
+        "searching for \"{}\" with tag filter {:?}, page {}",
+        query,
+        filter,
+        page
+    );
+
+    let offset = page.checked_mul(SEARCH_PAGE_LENGTH).ok_or((
+        StatusCode::BAD_REQUEST,
+        "the requested page is out of range",
+    ))?;
+
+    // the filter is applied in the query, before the candidates are limited, so that matching
+    // links aren't crowded out of the candidates by ones which would be filtered out anyway
+    let to_json = |tags: &HashSet<String>| {
+        serde_json::to_string(tags).map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to convert the tag filter to json",
+            )
+        })
+    };
+    let (include, exclude) = (to_json(&filter.include)?, to_json(&filter.exclude)?);
+
+    // (link id, link, description, relevance)
+    let candidates: Vec<(String, String, String, f64)> = if !query.trim().is_empty() {
+        let fts_query = to_fts_query(query);
+
+        sqlx::query!(
+            r#"SELECT links.link_id as "link_id!",
+                      links.link as "link!",
+                      links.description as "description!",
+                      links_search.rank as "rank!: f64"
+                 FROM links_search
+           INNER JOIN links ON links.link_id = links_search.link_id
+                WHERE links_search MATCH ?1
+                  AND NOT EXISTS (
+                          SELECT 1
+                            FROM json_each(?2) AS included
+                           WHERE included.value NOT IN (
+                                     SELECT tags.name
+                                       FROM scores
+                                 INNER JOIN tags ON tags.tag_id = scores.tag_id
+                                      WHERE scores.id = links.link_id
+                                 )
+                      )
+                  AND NOT EXISTS (
+                          SELECT 1
+                            FROM scores
+                      INNER JOIN tags ON tags.tag_id = scores.tag_id
+                           WHERE scores.id = links.link_id
+                             AND tags.name IN (SELECT value FROM json_each(?3))
+                      )
+             ORDER BY links_search.rank
+                LIMIT ?4"#,
+            fts_query,
+            include,
+            exclude,
+            SEARCH_CANDIDATE_LIMIT
+        )
+        .fetch_all(&mut **connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to query the search index",
+            )
+        })?
+        .into_iter()
+        // bm25 ranks are negative, with more negative ranks being more relevant
+        .map(|row| (row.link_id, row.link, row.description, -row.rank))
+        .collect()
+    } else if !filter.is_empty() {
+        sqlx::query!(
+            r#"SELECT links.link_id as "link_id!",
+                      links.link as "link!",
+                      links.description as "description!"
+                 FROM links
+                WHERE links.link_id NOT IN (SELECT link_id FROM hidden_links)
+                  AND NOT EXISTS (
+                          SELECT 1
+                            FROM json_each(?1) AS included
+                           WHERE included.value NOT IN (
+                                     SELECT tags.name
+                                       FROM scores
+                                 INNER JOIN tags ON tags.tag_id = scores.tag_id
+                                      WHERE scores.id = links.link_id
+                                 )
+                      )
+                  AND NOT EXISTS (
+                          SELECT 1
+                            FROM scores
+                      INNER JOIN tags ON tags.tag_id = scores.tag_id
+                           WHERE scores.id = links.link_id
+                             AND tags.name IN (SELECT value FROM json_each(?2))
+                      )
+             ORDER BY links.link_id DESC
+                LIMIT ?3"#,
+            include,
+            exclude,
+            SEARCH_CANDIDATE_LIMIT
+        )
+        .fetch_all(&mut **connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to query the links matching a tag filter",
+            )
+        })?
+        .into_iter()
+        .map(|row| (row.link_id, row.link, row.description, 0.0))
+        .collect()
+    } else {
+        Vec::new()
+    };
+
+    let max_relevance = candidates
+        .iter()
+        .map(|(_, _, _, relevance)| *relevance)
+        .fold(0.0, f64::max);
+
+    let mut scores = scores::load_many(
+        connection,
+        &candidates
+            .iter()
+            .map(|(id, _, _, _)| id.clone())
+            .collect::<Vec<_>>(),
+    )
+    .await?;
+
+    let mut results = Vec::with_capacity(candidates.len());
+    for (id, link, description, relevance) in candidates {
+        let tags = scores
+            .remove(&id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tag| (tag.name, tag.score))
+            .collect::<Vec<_>>();
+
+        // only the tags that were asked for are considered if any were, as the scores of
+        // the others say little about how well the link fits the search
+        let mut tag_score = 0.0;
+        let mut tag_count = 0.0;
+        for (name, mut score) in tags.iter().cloned() {
+            if filter.include.is_empty() || filter.include.contains(&name) {
+                // as with tag pages, decayed scores aren't persisted here
+                util::decay_score(algorithm_configuration, &mut score, 12)?;
+
+                // map the rating onto (0, 1) so it can be mixed with the relevance
+                tag_score += 1.0 / (1.0 + (-score.score.rating()).exp());
+                tag_count += 1.0;
+            }
+        }
+
+        let tag_score = if tag_count > 0.0 {
+            tag_score / tag_count
+        } else {
+            0.5
+        };
+
+        let score = if max_relevance > 0.0 {
+            RELEVANCE_WEIGHT * (relevance / max_relevance) + (1.0 - RELEVANCE_WEIGHT) * tag_score
+        } else {
+            tag_score
+        };
+
+        results.push(SearchResult {
+            id,
+            link,
+            description,
+            tags: tags.into_iter().map(|(name, _)| name).collect(),
+            score,
+        });
+    }
+
+    results.sort_unstable_by(|left, right| {
+        right
+            .score
+            .partial_cmp(&left.score)
+            .expect("invariant violation lmao (nan)")
+    });
+
+    debug!("{} results found for \"{}\"", results.len(), query);
+
+    Ok(results
+        .into_iter()
+        .skip(offset)
+        .take(SEARCH_PAGE_LENGTH)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{pool::PoolConnection, Sqlite, SqliteConnection};
+    use std::time::SystemTime;
+
+    use super::{index_link, search, to_fts_query, TagFilter, SEARCH_CANDIDATE_LIMIT};
+    use crate::{configuration::Algorithm, schema};
+
+    async fn post(
+        connection: &mut SqliteConnection,
+        link_id: &str,
+        description: &str,
+        tags: &[&str],
+    ) {
+        let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs() as i64;
+
+        sqlx::query("INSERT INTO links VALUES (?, ?, ?)")
+            .bind(link_id)
+            .bind(format!("https://example.com/{}", link_id))
+            .bind(description)
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+
+        for tag in tags {
+            sqlx::query("INSERT OR IGNORE INTO tags VALUES (?1, ?1)")
+                .bind(tag)
+                .execute(&mut *connection)
+                .await
+                .unwrap();
+            sqlx::query("INSERT INTO scores VALUES (?, ?, 0, 2, 0.06, ?)")
+                .bind(link_id)
+                .bind(tag)
+                .bind(now)
+                .execute(&mut *connection)
+                .await
+                .unwrap();
+        }
+
+        index_link(connection, link_id).await.unwrap();
+    }
+
+    async fn ids(
+        connection: &mut PoolConnection<Sqlite>,
+        query: &str,
+        filter: &str,
+    ) -> Vec<String> {
+        let filter = TagFilter::parse(filter).unwrap();
+        let mut ids = search(&Algorithm::default(), connection, query, &filter, 0)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|result| result.id)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[tokio::test]
+    async fn tags_can_be_excluded_without_being_included() {
+        let mut connection = schema::memory().await.acquire().await.unwrap();
+        post(&mut connection, "a", "first", &["rust"]).await;
+        post(&mut connection, "b", "second", &["rust", "async"]).await;
+        post(&mut connection, "c", "third", &["async"]).await;
+        post(&mut connection, "d", "fourth", &[]).await;
+
+        assert_eq!(ids(&mut connection, "", "-async").await, ["a", "d"]);
+        assert_eq!(ids(&mut connection, "", "rust -async").await, ["a"]);
+        assert_eq!(ids(&mut connection, "", "rust async").await, ["b"]);
+    }
+
+    #[tokio::test]
+    async fn filtering_happens_before_candidates_are_limited() {
+        let mut connection = schema::memory().await.acquire().await.unwrap();
+        for link in 0..SEARCH_CANDIDATE_LIMIT {
+            post(
+                &mut connection,
+                &format!("{:04}", link),
+                "flock",
+                &["async"],
+            )
+            .await;
+        }
+
+        // the least relevant match, which would be the last candidate without the filter
+        post(
+            &mut connection,
+            "rust",
+            "a link about flock and many other things besides",
+            &["rust"],
+        )
+        .await;
+
+        assert_eq!(ids(&mut connection, "flock", "rust").await, ["rust"]);
+        assert_eq!(ids(&mut connection, "flock", "-async").await, ["rust"]);
+    }
+
+    #[tokio::test]
+    async fn hidden_links_are_left_out() {
+        let mut connection = schema::memory().await.acquire().await.unwrap();
+        post(&mut connection, "a", "flock", &["rust"]).await;
+        post(&mut connection, "b", "flock", &["rust"]).await;
+
+        sqlx::query("INSERT INTO hidden_links VALUES ('b')")
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+        index_link(&mut connection, "b").await.unwrap();
+
+        assert_eq!(ids(&mut connection, "flock", "").await, ["a"]);
+        assert_eq!(ids(&mut connection, "", "rust").await, ["a"]);
+    }
+
+    #[test]
+    fn terms_are_quoted() {
+        assert_eq!(
+            to_fts_query("rust  async\tawait"),
+            r#""rust" "async" "await""#
+        );
+    }
+
+    #[test]
+    fn query_syntax_is_escaped() {
+        assert_eq!(
+            to_fts_query(r#"a OR b* "c" NEAR(d)"#),
+            r#""a" "OR" "b*" """c""" "NEAR(d)""#
+        );
+    }
+
+    #[test]
+    fn empty_queries_stay_empty() {
+        assert_eq!(to_fts_query(""), "");
+        assert_eq!(to_fts_query(" \n "), "");
+    }
+}
@@ -0,0 +1,392 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use anyhow::Context;
+use axum::http::StatusCode;
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use sqlx::{pool::PoolConnection, Sqlite, SqliteConnection, SqlitePool};
+use std::time::{Duration, SystemTime};
+use tracing::debug;
+
+use crate::feed_tokens;
+
+/// How long a proof-of-work challenge may be used for after it is issued
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// An invite code created by an account, as shown on the profile page
+#[derive(Debug)]
+pub struct Invite {
+    pub code: String,
+
+    /// Seconds since the unix epoch at which the invite was created
+    pub created: i64,
+
+    /// The account which signed up with the invite, if it has been used
+    pub invitee: Option<String>,
+}
+
+/// The key used to sign proof-of-work challenges, so that they don't need to be stored until
+/// they're used
+pub struct ChallengeKey(Vec<u8>);
+
+impl ChallengeKey {
+    pub async fn load(sqlite: &SqlitePool) -> anyhow::Result<&'static Self> {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        let secret = secret.as_slice();
+
+        // only the first instance to start will have its secret stored
+        sqlx::query!(
+            "INSERT INTO secrets (name, secret) VALUES ('signup-challenge-key', ?) ON CONFLICT (name) DO NOTHING",
+            secret
+        )
+        .execute(sqlite)
+        .await
+        .context("unable to store the signup challenge key")?;
+
+        let secret = sqlx::query_scalar!(
+            r#"SELECT secret as "secret!" FROM secrets WHERE name = 'signup-challenge-key'"#
+        )
+        .fetch_one(sqlite)
+        .await
+        .context("unable to load the signup challenge key")?;
+
+        Ok(Box::leak(Box::new(Self(secret))))
+    }
+
+    fn mac(&self, expires: u64, nonce: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("hmac accepts keys of any length");
+
+        mac.update(expires.to_string().as_bytes());
+        mac.update(b"\0");
+        mac.update(nonce.as_bytes());
+
+        mac
+    }
+
+    /// Issue a new challenge, to be used as the resource of a hashcash stamp
+    ///
+    /// Challenges take the form `{expiry}.{nonce}.{signature}`, which contains nothing a
+    /// hashcash stamp can't carry in its resource field
+    pub fn issue(&self) -> Result<String, (StatusCode, &'static str)> {
+        let expires = (SystemTime::now() + CHALLENGE_LIFETIME)
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to calculate the amount of time that has passed since the unix epoch",
+                )
+            })?
+            .as_secs();
+        let nonce = feed_tokens::generate_token();
+
+        Ok(format!(
+            "{}.{}.{}",
+            expires,
+            nonce,
+            hex::encode(self.mac(expires, &nonce).finalize().into_bytes())
+        ))
+    }
+
+    /// Check that a challenge was issued by us and hasn't expired, returning its expiry
+    fn verify(&self, challenge: &str, now: u64) -> Option<u64> {
+        let mut fields = challenge.split('.');
+        let (Some(expires), Some(nonce), Some(signature), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return None;
+        };
+
+        let expires = expires.parse().ok()?;
+        let signature = hex::decode(signature).ok()?;
+
+        (expires > now && self.mac(expires, nonce).verify_slice(&signature).is_ok())
+            .then_some(expires)
+    }
+}
+
+/// Count the number of leading zero bits in a hash
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+
+    for byte in hash {
+        bits += byte.leading_zeros();
+
+        if *byte != 0 {
+            break;
+        }
+    }
+
+    bits
+}
+
+/// Check a version 1 hashcash stamp (as produced by `hashcash -mb<difficulty> <challenge>`)
+/// against the required difficulty, and mark its challenge as used
+pub async fn redeem_stamp(
+    connection: &mut SqliteConnection,
+    challenge_key: &ChallengeKey,
+    difficulty: u32,
+    stamp: &str,
+) -> Result<(), (StatusCode, &'static str)> {
+    let stamp = stamp.trim();
+    let now = SystemTime::UNIX_EPOCH
+        .elapsed()
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to calculate the amount of time that has passed since the unix epoch",
+            )
+        })?
+        .as_secs();
+
+    // version:bits:date:resource:extension:random:counter
+    let fields = stamp.split(':').collect::<Vec<_>>();
+    let ["1", _, _, challenge, _, _, _] = fields.as_slice() else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "the provided stamp isn't a version 1 hashcash stamp",
+        ));
+    };
+
+    let expires = challenge_key.verify(challenge, now).ok_or((
+        StatusCode::BAD_REQUEST,
+        "the provided stamp's challenge is invalid or has expired. reload the signup page for a new one",
+    ))? as i64;
+
+    if leading_zero_bits(&Sha1::digest(stamp.as_bytes())) < difficulty {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "the provided stamp doesn't meet the required difficulty",
+        ));
+    }
+
+    let now = now as i64;
+
+    sqlx::query!("DELETE FROM spent_challenges WHERE expires <= ?", now)
+        .execute(&mut *connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to remove expired challenges",
+            )
+        })?;
+
+    if sqlx::query!(
+        "INSERT INTO spent_challenges (challenge, expires) VALUES (?, ?) ON CONFLICT (challenge) DO NOTHING",
+        challenge,
+        expires
+    )
+    .execute(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to mark the challenge as used",
+        )
+    })?
+    .rows_affected()
+        == 0
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "the provided stamp's challenge has already been used. reload the signup page for a new one",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Mark an invite as used by a new account, failing if it doesn't exist or was already used
+pub async fn redeem_invite(
+    connection: &mut SqliteConnection,
+    code: &str,
+    invitee: &str,
+) -> Result<(), (StatusCode, &'static str)> {
+    let code = code.trim();
+
+    if sqlx::query!(
+        "UPDATE invites SET invitee = ? WHERE code = ? AND invitee IS NULL",
+        invitee,
+        code
+    )
+    .execute(&mut *connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to redeem the invite",
+        )
+    })?
+    .rows_affected()
+        == 0
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "the provided invite code does not exist or has already been used",
+        ));
+    }
+
+    debug!("account {} signed up with invite {}", invitee, code);
+
+    Ok(())
+}
+
+/// Retrieve all of the invites an account has created, newest first
+pub async fn account_invites(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+) -> Result<Vec<Invite>, (StatusCode, &'static str)> {
+    Ok(sqlx::query!(
+        r#"SELECT code as "code!", created as "created!: i64", invitee FROM invites WHERE inviter = ? ORDER BY created DESC, code"#,
+        account_id
+    )
+    .fetch_all(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's invites",
+        )
+    })?
+    .into_iter()
+    .map(|invite| Invite {
+        code: invite.code,
+        created: invite.created,
+        invitee: invite.invitee,
+    })
+    .collect())
+}
+
+/// Create a new invite, failing if the inviter has already used up their quota
+///
+/// Invites created from the command line have no inviter and aren't subject to a quota
+pub async fn create_invite(
+    connection: &mut PoolConnection<Sqlite>,
+    inviter: Option<&str>,
+    quota: usize,
+) -> Result<String, (StatusCode, &'static str)> {
+    let code = feed_tokens::generate_token();
+    let quota = inviter.map(|_| quota as i64);
+    let inviter = inviter.unwrap_or_default();
+    let created = SystemTime::UNIX_EPOCH
+        .elapsed()
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to calculate the amount of time that has passed since the unix epoch",
+            )
+        })?
+        .as_secs() as i64;
+
+    // the quota is checked by the insert itself, so concurrent requests can't both slip under it
+    if sqlx::query!(
+        r"INSERT INTO invites (code, inviter, created, invitee)
+               SELECT ?1, ?2, ?3, NULL
+                WHERE ?4 IS NULL
+                   OR (SELECT COUNT(1) FROM invites WHERE inviter = ?2) < ?4",
+        code,
+        inviter,
+        created,
+        quota
+    )
+    .execute(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to insert the invite into the db",
+        )
+    })?
+    .rows_affected()
+        == 0
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "you have already created as many invites as you're allowed to",
+        ));
+    }
+
+    debug!("created a new invite for account {:?}", inviter);
+
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{leading_zero_bits, ChallengeKey, CHALLENGE_LIFETIME};
+    use std::time::SystemTime;
+
+    fn now() -> u64 {
+        SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs()
+    }
+
+    #[test]
+    fn leading_zero_bits_span_bytes() {
+        assert_eq!(leading_zero_bits(&[]), 0);
+        assert_eq!(leading_zero_bits(&[0x80, 0x00]), 0);
+        assert_eq!(leading_zero_bits(&[0x0f, 0x00]), 4);
+        assert_eq!(leading_zero_bits(&[0x00, 0x01, 0x00]), 15);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00, 0xff]), 16);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00]), 16);
+    }
+
+    #[test]
+    fn issued_challenges_verify_until_they_expire() {
+        let key = ChallengeKey(vec![1; 32]);
+        let now = now();
+        let challenge = key.issue().unwrap();
+
+        let expires = key.verify(&challenge, now).unwrap();
+        assert!(expires >= now + CHALLENGE_LIFETIME.as_secs() - 1);
+        assert_eq!(key.verify(&challenge, expires - 1), Some(expires));
+        assert_eq!(key.verify(&challenge, expires), None);
+    }
+
+    #[test]
+    fn tampered_challenges_are_rejected() {
+        let key = ChallengeKey(vec![1; 32]);
+        let now = now();
+        let challenge = key.issue().unwrap();
+        let [expires, nonce, signature] = challenge.split('.').collect::<Vec<_>>()[..] else {
+            panic!("challenges have three fields");
+        };
+
+        assert_eq!(ChallengeKey(vec![2; 32]).verify(&challenge, now), None);
+        for tampered in [
+            format!(
+                "{}.{}.{}",
+                expires.parse::<u64>().unwrap() + 1,
+                nonce,
+                signature
+            ),
+            format!("{}.{}x.{}", expires, nonce, signature),
+            format!("{}.{}.{}00", expires, nonce, signature),
+            format!("{}.{}.{}", expires, nonce, &signature[1..]),
+            format!("{}.", challenge),
+            format!("{}.{}", expires, nonce),
+            String::new(),
+        ] {
+            assert_eq!(key.verify(&tampered, now), None, "{}", tampered);
+        }
+    }
+}
@@ -0,0 +1,808 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use anyhow::Context;
+use axum::{body::Bytes, extract::Multipart, headers::ETag, http::StatusCode};
+use hmac::{Hmac, Mac};
+use parking_lot::Mutex;
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use sqlx::{pool::PoolConnection, Connection, Sqlite, SqlitePool};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+use tracing::{debug, trace};
+use ulid::Ulid;
+
+use crate::{css, model, rand::pcg_thread_rng, templates};
+
+/// The number of styles displayed on a single page of the gallery
+pub const STYLE_PAGE_LENGTH: usize = 25;
+
+/// The maximum length of a style's name, in bytes
+const MAX_NAME_LENGTH: usize = 100;
+
+/// A stylesheet as held in the [`StyleCache`]
+#[derive(Debug)]
+pub struct CachedStyle {
+    pub version: String,
+    pub etag: ETag,
+
+    /// The stylesheet, which is cheap to clone into a response
+    pub stylesheet: Bytes,
+}
+
+/// A map bounded to a capacity, evicting the least recently used entry once it is full
+///
+/// A capacity of zero disables it entirely
+struct Lru<V> {
+    capacity: usize,
+
+    /// Each entry alongside the value of `clock` when it was last used
+    entries: HashMap<String, (V, u64)>,
+
+    /// The key of each entry, keyed by the value of `clock` when it was last used
+    order: BTreeMap<u64, String>,
+    clock: u64,
+}
+
+impl<V> Lru<V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<&V> {
+        let (value, used) = self.entries.get_mut(key)?;
+
+        self.clock += 1;
+        if let Some(key) = self.order.remove(used) {
+            self.order.insert(self.clock, key);
+        }
+        *used = self.clock;
+
+        Some(value)
+    }
+
+    fn insert(&mut self, key: String, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some((_, used)) = self.entries.remove(&key) {
+            self.order.remove(&used);
+        } else if self.entries.len() >= self.capacity
+            && let Some((_, oldest)) = self.order.pop_first()
+        {
+            trace!("evicting {} from a cache", oldest);
+
+            self.entries.remove(&oldest);
+        }
+
+        self.clock += 1;
+        self.order.insert(self.clock, key.clone());
+        self.entries.insert(key, (value, self.clock));
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((_, used)) = self.entries.remove(key) {
+            self.order.remove(&used);
+        }
+    }
+}
+
+/// An in-process cache of the most recently served stylesheets and the styles used by the
+/// most recently active accounts
+///
+/// Stylesheets are replaced whenever a different version of a style is requested than the one
+/// cached, so changes made through other instances are picked up as soon as pages link to
+/// them
+pub struct StyleCache {
+    /// Stylesheets, keyed by style id
+    styles: Mutex<Lru<Arc<CachedStyle>>>,
+
+    /// The id of the style used by each account, keyed by account id
+    account_styles: Mutex<Lru<Option<String>>>,
+}
+
+impl StyleCache {
+    pub fn new(capacity: usize, account_capacity: usize) -> &'static Self {
+        Box::leak(Box::new(Self {
+            styles: Mutex::new(Lru::new(capacity)),
+            account_styles: Mutex::new(Lru::new(account_capacity)),
+        }))
+    }
+
+    /// Retrieve a style, loading it from the db unless it is cached with the requested version
+    /// (or any version, if none was requested)
+    pub async fn get(
+        &self,
+        sqlite: &SqlitePool,
+        style_id: &str,
+        version: Option<&str>,
+    ) -> Result<Option<Arc<CachedStyle>>, (StatusCode, &'static str)> {
+        if let Some(style) = self.styles.lock().get(style_id)
+            && (version.is_none() || version == Some(style.version.as_str()))
+        {
+            return Ok(Some(Arc::clone(style)));
+        }
+
+        trace!("style {} isn't cached, loading it from the db", style_id);
+
+        let Some(style) = sqlx::query!(
+            r#"SELECT styles.style as "style!", style_versions.version as "version!"
+                 FROM styles
+           INNER JOIN style_versions ON style_versions.style_id = styles.style_id
+                WHERE styles.style_id = ?"#,
+            style_id
+        )
+        .fetch_optional(sqlite)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to query the db for the style",
+            )
+        })?
+        else {
+            self.invalidate(style_id);
+
+            return Ok(None);
+        };
+
+        // the etag is derived from the stylesheet itself rather than its version, so that
+        // clients don't download it again if it is changed back
+        let etag = format!("\"{}\"", hex::encode(Sha256::digest(style.style.as_bytes())))
+            .parse()
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to create an etag",
+                )
+            })?;
+        let style = Arc::new(CachedStyle {
+            version: style.version,
+            etag,
+            stylesheet: Bytes::from(style.style),
+        });
+
+        self.styles
+            .lock()
+            .insert(style_id.to_string(), Arc::clone(&style));
+
+        Ok(Some(style))
+    }
+
+    /// Look up the style an account uses, alongside its current version
+    ///
+    /// Both are usually cached, in which case the db isn't touched at all
+    pub async fn account_style(
+        &self,
+        sqlite: &SqlitePool,
+        account_id: &str,
+    ) -> Result<model::StyleId, (StatusCode, &'static str)> {
+        let cached = self.account_styles.lock().get(account_id).cloned();
+        let style_id = if let Some(style_id) = cached {
+            style_id
+        } else {
+            trace!("account {}'s style isn't cached, loading it from the db", account_id);
+
+            let style_id = sqlx::query_scalar!(
+                r#"SELECT (SELECT style_id FROM accounts WHERE account_id = ?) AS "style_id?";"#,
+                account_id
+            )
+            .fetch_one(sqlite)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to query the db"))?;
+
+            self.account_styles
+                .lock()
+                .insert(account_id.to_string(), style_id.clone());
+
+            style_id
+        };
+
+        let Some(style_id) = style_id else {
+            return Ok(model::StyleId(None));
+        };
+
+        // styles which have been deleted since the account's style was cached are treated as
+        // unset, as they are by the db
+        Ok(model::StyleId(
+            self.get(sqlite, &style_id, None)
+                .await?
+                .map(|style| model::AppliedStyle {
+                    version: style.version.clone(),
+                    id: style_id,
+                }),
+        ))
+    }
+
+    /// Remove a style from the cache after it has been changed or deleted
+    pub fn invalidate(&self, style_id: &str) {
+        self.styles.lock().remove(style_id);
+    }
+
+    /// Remove an account's style from the cache after it has been changed or the account has
+    /// been deleted
+    pub fn invalidate_account(&self, account_id: &str) {
+        self.account_styles.lock().remove(account_id);
+    }
+}
+
+/// A stylesheet and name read from a multipart form
+#[derive(Debug, Default)]
+pub struct Upload {
+    /// The name provided explicitly, if any
+    pub name: Option<String>,
+
+    /// The name of the uploaded file, with the `.css` extension stripped
+    pub file_name: Option<String>,
+
+    /// The uploaded stylesheet, which has already been sanitized
+    pub stylesheet: Option<css::Sanitized>,
+}
+
+/// Read a style's name and stylesheet from a multipart form, sanitizing the stylesheet
+///
+/// Either may be missing, as updating a style doesn't require both
+pub async fn read_upload(
+    multipart: &mut Multipart,
+    max_style_size: usize,
+) -> Result<Upload, (StatusCode, &'static str)> {
+    let mut upload = Upload::default();
+
+    while let Some(mut field) = multipart.next_field().await.map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            "unable to read multipart form data",
+        )
+    })? {
+        match field.name() {
+            Some("name") => {
+                let name = field.text().await.map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        "unable to read multipart form data",
+                    )
+                })?;
+                let name = name.trim();
+
+                if name.len() > MAX_NAME_LENGTH {
+                    return Err((StatusCode::BAD_REQUEST, "the provided name is too long"));
+                }
+
+                if !name.is_empty() {
+                    upload.name = Some(name.to_string());
+                }
+            }
+            // browsers send an empty file with a generic content type when none is chosen
+            Some("stylesheet") if field.content_type() == Some("text/css") => {
+                upload.file_name = field
+                    .file_name()
+                    .map(|name| name.trim_end_matches(".css").to_string());
+
+                // read a chunk at a time so that oversized stylesheets are refused without
+                // buffering all of them first
+                let mut stylesheet = Vec::new();
+                while let Some(chunk) = field.chunk().await.map_err(|_| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        "unable to read multipart form data",
+                    )
+                })? {
+                    if stylesheet.len() + chunk.len() > max_style_size {
+                        return Err((
+                            StatusCode::PAYLOAD_TOO_LARGE,
+                            "the provided stylesheet is larger than the maximum allowed size",
+                        ));
+                    }
+
+                    stylesheet.extend_from_slice(&chunk);
+                }
+
+                let stylesheet = String::from_utf8_lossy(&stylesheet);
+
+                let sanitized = css::sanitize(&stylesheet);
+
+                debug!(
+                    "{} parts of the stylesheet were removed",
+                    sanitized.removals.len()
+                );
+
+                upload.stylesheet = Some(sanitized);
+            }
+            _ => (),
+        }
+    }
+
+    Ok(upload)
+}
+
+/// The key used to derive the handles shown for the creators of styles
+///
+/// Account ids double as credentials, so they can't be shown to anyone else, and a plain hash
+/// of one could be used to check a guessed id against. A keyed hash still lets styles by the
+/// same creator be recognized without that
+pub struct CreatorKey(Vec<u8>);
+
+impl CreatorKey {
+    pub async fn load(sqlite: &SqlitePool) -> anyhow::Result<&'static Self> {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        let secret = secret.as_slice();
+
+        // only the first instance to start will have its secret stored
+        sqlx::query!(
+            "INSERT INTO secrets (name, secret) VALUES ('style-creator-key', ?) ON CONFLICT (name) DO NOTHING",
+            secret
+        )
+        .execute(sqlite)
+        .await
+        .context("unable to store the style creator key")?;
+
+        let secret = sqlx::query_scalar!(
+            r#"SELECT secret as "secret!" FROM secrets WHERE name = 'style-creator-key'"#
+        )
+        .fetch_one(sqlite)
+        .await
+        .context("unable to load the style creator key")?;
+
+        Ok(Box::leak(Box::new(Self(secret))))
+    }
+
+    /// Derive a short, stable handle for the creator of a style
+    pub fn handle(&self, account_id: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("hmac accepts keys of any length");
+        mac.update(account_id.as_bytes());
+
+        hex::encode(&mac.finalize().into_bytes()[..4])
+    }
+}
+
+/// Retrieve a page of the style gallery, sorted in the requested order
+pub async fn gallery(
+    connection: &mut PoolConnection<Sqlite>,
+    creator_key: &CreatorKey,
+    account_id: Option<&str>,
+    sort: model::StyleSort,
+    page: usize,
+) -> Result<Vec<templates::GalleryStyle>, (StatusCode, &'static str)> {
+    trace!("retrieving page {} of styles sorted by {:?}", page, sort);
+
+    let offset = page
+        .checked_mul(STYLE_PAGE_LENGTH)
+        .and_then(|offset| i64::try_from(offset).ok())
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            "the requested page is out of range",
+        ))?;
+    let limit = STYLE_PAGE_LENGTH as i64;
+
+    let rows = match sort {
+        model::StyleSort::Popular => sqlx::query!(
+            r#"SELECT styles.style_id as "style_id!", styles.name as "name!", styles.creator as "creator!", COUNT(accounts.account_id) as "users!: i64"
+                 FROM styles
+            LEFT JOIN accounts ON accounts.style_id = styles.style_id
+             GROUP BY styles.style_id
+             ORDER BY COUNT(accounts.account_id) DESC, styles.style_id DESC
+                LIMIT ? OFFSET ?"#,
+            limit,
+            offset
+        )
+        .fetch_all(&mut **connection)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| (row.style_id, row.name, row.creator, row.users))
+                .collect::<Vec<_>>()
+        }),
+        model::StyleSort::New => sqlx::query!(
+            r#"SELECT styles.style_id as "style_id!", styles.name as "name!", styles.creator as "creator!", COUNT(accounts.account_id) as "users!: i64"
+                 FROM styles
+            LEFT JOIN accounts ON accounts.style_id = styles.style_id
+             GROUP BY styles.style_id
+             ORDER BY styles.style_id DESC
+                LIMIT ? OFFSET ?"#,
+            limit,
+            offset
+        )
+        .fetch_all(&mut **connection)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| (row.style_id, row.name, row.creator, row.users))
+                .collect::<Vec<_>>()
+        }),
+    }
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the styles",
+        )
+    })?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(id, name, creator, users)| templates::GalleryStyle {
+            id,
+            name,
+            yours: account_id.is_some_and(|account_id| account_id == creator),
+            // the styles of deleted accounts have no creator
+            creator: (!creator.is_empty()).then(|| creator_key.handle(&creator)),
+            users,
+        })
+        .collect())
+}
+
+/// Create a new style, returning its id
+pub async fn create(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+    name: &str,
+    stylesheet: &str,
+) -> Result<String, (StatusCode, &'static str)> {
+    let style_id = Ulid::with_source(&mut pcg_thread_rng()).to_string();
+
+    debug!("style id generated: {}", style_id);
+
+    let mut transaction = connection.begin().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to begin a transaction",
+        )
+    })?;
+
+    sqlx::query!(
+        "INSERT INTO styles (style_id, name, creator, style) VALUES (?, ?, ?, ?)",
+        style_id,
+        name,
+        account_id,
+        stylesheet
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to insert the stylesheet into the db",
+        )
+    })?;
+
+    // the first version of a style shares its id
+    sqlx::query!(
+        "INSERT INTO style_versions (style_id, version) VALUES (?, ?)",
+        style_id,
+        style_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to insert the style's version into the db",
+        )
+    })?;
+
+    transaction.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to commit the transaction",
+        )
+    })?;
+
+    Ok(style_id)
+}
+
+/// Set the style used by an account
+pub async fn apply(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+    style_id: &str,
+) -> Result<(), (StatusCode, &'static str)> {
+    if sqlx::query_scalar!("SELECT 1 FROM styles WHERE style_id = ?", style_id)
+        .fetch_optional(&mut **connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to check if a style exists",
+            )
+        })?
+        .is_none()
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "the requested style does not exist",
+        ));
+    }
+
+    if sqlx::query!(
+        "UPDATE accounts SET style_id = ? WHERE account_id = ?",
+        style_id,
+        account_id
+    )
+    .execute(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to update an account",
+        )
+    })?
+    .rows_affected()
+        == 0
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "the requested account does not exist",
+        ));
+    }
+
+    debug!("account {} is now using style {}", account_id, style_id);
+
+    Ok(())
+}
+
+/// Retrieve the name of a style, failing unless it was created by the provided account
+pub async fn owned_style_name(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+    style_id: &str,
+) -> Result<String, (StatusCode, &'static str)> {
+    let style = sqlx::query!(
+        r#"SELECT name as "name!", creator as "creator!" FROM styles WHERE style_id = ?"#,
+        style_id
+    )
+    .fetch_optional(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the db for the style",
+        )
+    })?
+    .ok_or((
+        StatusCode::BAD_REQUEST,
+        "the requested style does not exist",
+    ))?;
+
+    if style.creator != account_id {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "only the creator of a style can change it",
+        ));
+    }
+
+    Ok(style.name)
+}
+
+/// Update the name and/or stylesheet of a style created by the provided account
+///
+/// Replacing the stylesheet creates a new version of the style, so that the old one can stay
+/// cached wherever it already is
+pub async fn update(
+    connection: &mut PoolConnection<Sqlite>,
+    style_cache: &StyleCache,
+    account_id: &str,
+    style_id: &str,
+    name: Option<&str>,
+    stylesheet: Option<&str>,
+) -> Result<(), (StatusCode, &'static str)> {
+    owned_style_name(connection, account_id, style_id).await?;
+
+    let mut transaction = connection.begin().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to begin a transaction",
+        )
+    })?;
+
+    sqlx::query!(
+        "UPDATE styles SET name = COALESCE(?, name), style = COALESCE(?, style) WHERE style_id = ?",
+        name,
+        stylesheet,
+        style_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to update the style",
+        )
+    })?;
+
+    if stylesheet.is_some() {
+        let version = Ulid::with_source(&mut pcg_thread_rng()).to_string();
+
+        sqlx::query!(
+            "INSERT INTO style_versions (style_id, version) VALUES (?, ?) ON CONFLICT (style_id) DO UPDATE SET version = excluded.version",
+            style_id,
+            version
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to update the style's version",
+            )
+        })?;
+    }
+
+    transaction.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to commit the transaction",
+        )
+    })?;
+
+    style_cache.invalidate(style_id);
+
+    debug!("account {} updated style {}", account_id, style_id);
+
+    Ok(())
+}
+
+/// Delete a style created by the provided account, unsetting it for every account using it
+///
+/// Any reports about the style are resolved, as there's nothing left to moderate
+pub async fn delete(
+    connection: &mut PoolConnection<Sqlite>,
+    style_cache: &StyleCache,
+    account_id: &str,
+    style_id: &str,
+) -> Result<(), (StatusCode, &'static str)> {
+    owned_style_name(connection, account_id, style_id).await?;
+
+    let mut transaction = connection.begin().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to begin a transaction",
+        )
+    })?;
+
+    sqlx::query!(
+        "UPDATE accounts SET style_id = NULL WHERE style_id = ?",
+        style_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to remove the style from the accounts using it",
+        )
+    })?;
+
+    sqlx::query!(
+        "UPDATE reports SET resolved = true WHERE kind = 'style' AND target_id = ?",
+        style_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to resolve the reports about the style",
+        )
+    })?;
+
+    for (query, error) in [
+        (
+            sqlx::query!("DELETE FROM style_versions WHERE style_id = ?", style_id),
+            "unable to delete the style's version",
+        ),
+        (
+            sqlx::query!("DELETE FROM styles WHERE style_id = ?", style_id),
+            "unable to delete the style",
+        ),
+    ] {
+        query
+            .execute(&mut *transaction)
+            .await
+            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, error))?;
+    }
+
+    transaction.commit().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to commit the transaction",
+        )
+    })?;
+
+    style_cache.invalidate(style_id);
+
+    debug!("account {} deleted style {}", account_id, style_id);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CreatorKey, Lru};
+    use crate::schema;
+
+    fn keys<V>(lru: &Lru<V>) -> Vec<&str> {
+        lru.order.values().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let mut lru = Lru::new(3);
+        lru.insert("a".to_string(), 1);
+        lru.insert("b".to_string(), 2);
+        lru.insert("c".to_string(), 3);
+
+        assert_eq!(lru.get("a"), Some(&1));
+        lru.insert("d".to_string(), 4);
+
+        assert_eq!(lru.get("b"), None);
+        assert_eq!(keys(&lru), ["c", "a", "d"]);
+
+        lru.insert("e".to_string(), 5);
+        assert_eq!(lru.get("c"), None);
+        assert_eq!(keys(&lru), ["a", "d", "e"]);
+    }
+
+    #[test]
+    fn lru_replaces_without_evicting() {
+        let mut lru = Lru::new(2);
+        lru.insert("a".to_string(), 1);
+        lru.insert("b".to_string(), 2);
+        lru.insert("a".to_string(), 3);
+
+        assert_eq!(lru.get("b"), Some(&2));
+        assert_eq!(lru.get("a"), Some(&3));
+        assert_eq!(lru.entries.len(), lru.order.len());
+
+        lru.remove("b");
+        lru.insert("c".to_string(), 4);
+        assert_eq!(keys(&lru), ["a", "c"]);
+    }
+
+    #[test]
+    fn lru_without_capacity_is_empty() {
+        let mut lru = Lru::new(0);
+        lru.insert("a".to_string(), 1);
+
+        assert_eq!(lru.get("a"), None);
+        assert!(lru.order.is_empty());
+    }
+
+    #[tokio::test]
+    async fn creator_handles_are_keyed() {
+        let sqlite = schema::memory().await;
+        let key = CreatorKey::load(&sqlite).await.unwrap();
+
+        // the key is kept in the db, so handles stay the same across restarts
+        let reloaded = CreatorKey::load(&sqlite).await.unwrap();
+        assert_eq!(key.handle("account"), reloaded.handle("account"));
+        assert_ne!(key.handle("account"), key.handle("other"));
+
+        let other_key = CreatorKey::load(&schema::memory().await).await.unwrap();
+        assert_ne!(key.handle("account"), other_key.handle("account"));
+    }
+}
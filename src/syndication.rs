@@ -0,0 +1,359 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use askama::Template;
+use atom_syndication::FixedDateTime;
+use axum::{headers::ETag, http::StatusCode};
+use sha2::{Digest, Sha256};
+use sqlx::{pool::PoolConnection, Sqlite};
+use std::time::{Duration, SystemTime};
+use ulid::Ulid;
+
+use crate::{feed_tokens::SigningKey, model, templates};
+
+/// The formats a feed can be syndicated in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// RSS 2.0
+    Rss,
+
+    /// The Atom Syndication Format
+    Atom,
+
+    /// JSON Feed 1.1
+    Json,
+}
+
+impl Format {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Rss => "application/rss+xml",
+            Self::Atom => "application/atom+xml",
+            Self::Json => "application/feed+json",
+        }
+    }
+
+    /// The path the format is served from
+    pub fn path(&self) -> &'static str {
+        match self {
+            Self::Rss => "/feed.xml",
+            Self::Atom => "/feed.atom",
+            Self::Json => "/feed.json",
+        }
+    }
+}
+
+/// A feed, independent of the format it is syndicated in
+pub struct Document {
+    pub title: String,
+    pub description: String,
+    pub home_url: String,
+    pub feed_url: String,
+    pub updated: SystemTime,
+    pub items: Vec<Item>,
+}
+
+/// A single item of a feed
+pub struct Item {
+    pub link_id: String,
+    pub url: String,
+    pub title: String,
+    pub content: String,
+    pub published: SystemTime,
+}
+
+/// The time at which the provided account's feed was last modified
+///
+/// Links are appended to a feed by manual refreshes without it being regenerated, so the time
+/// of the account's latest manual refresh is used if it is more recent than the feed
+pub async fn last_modified(
+    connection: &mut PoolConnection<Sqlite>,
+    account_id: &str,
+    feed: &model::Feed,
+) -> Result<SystemTime, (StatusCode, &'static str)> {
+    let last_refresh = sqlx::query_scalar!(
+        r#"SELECT MAX(refreshed) as "refreshed?: i64" FROM feed_refreshes WHERE account_id = ?"#,
+        account_id
+    )
+    .fetch_one(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the account's last feed refresh",
+        )
+    })?;
+
+    let modified = last_refresh.map_or(0, |refreshed| refreshed as u64);
+
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(modified.max(feed.refreshed)))
+}
+
+/// Compute an entity tag for a feed in the provided format, last modified at the provided time
+///
+/// The items are hashed as they are rendered, so that anything changing them (links being
+/// appended or hidden, or the account's style changing) changes the tag as well
+pub fn etag(
+    format: Format,
+    last_modified: SystemTime,
+    items: &[Item],
+) -> Result<ETag, (StatusCode, &'static str)> {
+    let mut hasher = Sha256::new();
+
+    // every field is prefixed with its length, so that no two feeds hash the same input
+    let mut field = |bytes: &[u8]| {
+        hasher.update((bytes.len() as u64).to_le_bytes());
+        hasher.update(bytes);
+    };
+
+    field(format.path().as_bytes());
+    field(httpdate::fmt_http_date(last_modified).as_bytes());
+    for item in items {
+        field(item.link_id.as_bytes());
+        field(item.url.as_bytes());
+        field(item.title.as_bytes());
+        field(item.content.as_bytes());
+    }
+
+    format!("\"{}\"", hex::encode(hasher.finalize()))
+        .parse()
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to create an etag",
+            )
+        })
+}
+
+/// Build a url rating a link that requires the reader to log in first, for feeds that aren't
+/// associated with an account
+pub fn login_rating_url(flock_host: &str, link_id: &str, rating: model::Rating) -> String {
+    format!(
+        "{}/login?redirect-to={}",
+        flock_host,
+        urlencoding::encode(&format!("/links/{}/{}", link_id, rating.as_str()))
+    )
+}
+
+/// Retrieve the items for each of the links in the provided feed
+pub async fn feed_items(
+    connection: &mut PoolConnection<Sqlite>,
+    feed: &model::Feed,
+    style_id: model::StyleId,
+    flock_host: &str,
+    signing_key: &SigningKey,
+    token: &str,
+) -> Result<Vec<Item>, (StatusCode, &'static str)> {
+    let rating_url = |link_id: &str, rating: model::Rating| {
+        format!(
+            "{}/links/{}/{}?t={}&s={}",
+            flock_host,
+            link_id,
+            rating.as_str(),
+            urlencoding::encode(token),
+            signing_key.sign_rating(token, link_id, rating)
+        )
+    };
+
+    let mut items = Vec::with_capacity(feed.links.len());
+
+    for (link_id, _) in &feed.links {
+        // links hidden since the feed was generated are skipped
+        let Some(title) = sqlx::query_scalar!(
+            r#"SELECT description as "description!" FROM links WHERE link_id = ? AND link_id NOT IN (SELECT link_id FROM hidden_links)"#,
+            link_id,
+        )
+        .fetch_optional(&mut **connection)
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "unable to query for a link's information",
+            )
+        })?
+        else {
+            continue;
+        };
+
+        items.push(Item {
+            link_id: link_id.clone(),
+            url: format!(
+                "{}/links/{}?t={}",
+                flock_host,
+                link_id,
+                urlencoding::encode(token)
+            ),
+            title,
+            content: templates::FeedItem {
+                style_id: style_id.clone(),
+                flock_host: flock_host.to_string(),
+                promote_url: rating_url(link_id, model::Rating::Promote),
+                neutral_url: rating_url(link_id, model::Rating::Neutral),
+                demote_url: rating_url(link_id, model::Rating::Demote),
+            }
+            .render()
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "unable to render a feed item's description",
+                )
+            })?,
+            // links are identified by ulids, so they carry the time they were posted
+            published: Ulid::from_string(link_id)
+                .map_err(|_| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "unable to parse a link id",
+                    )
+                })?
+                .datetime(),
+        });
+    }
+
+    Ok(items)
+}
+
+/// Render the provided document in the provided format
+pub fn render(format: Format, document: Document) -> Result<String, (StatusCode, &'static str)> {
+    match format {
+        Format::Rss => Ok(render_rss(document)),
+        Format::Atom => render_atom(document),
+        Format::Json => render_json(document),
+    }
+}
+
+fn render_rss(document: Document) -> String {
+    rss::ChannelBuilder::default()
+        .title(document.title)
+        .description(document.description)
+        .link(document.home_url)
+        .docs("https://www.rssboard.org/rss-specification".to_string())
+        .last_build_date(httpdate::fmt_http_date(document.updated))
+        .items(
+            document
+                .items
+                .into_iter()
+                .map(|item| {
+                    rss::ItemBuilder::default()
+                        .title(item.title)
+                        .description(item.content)
+                        .link(item.url)
+                        .pub_date(httpdate::fmt_http_date(item.published))
+                        .guid(
+                            rss::GuidBuilder::default()
+                                .value(item.link_id)
+                                .permalink(false)
+                                .build(),
+                        )
+                        .build()
+                })
+                .collect::<Vec<_>>(),
+        )
+        .build()
+        .to_string()
+}
+
+fn to_fixed_date_time(time: SystemTime) -> Result<FixedDateTime, (StatusCode, &'static str)> {
+    FixedDateTime::parse_from_rfc3339(&humantime::format_rfc3339(time).to_string()).map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to convert a timestamp",
+        )
+    })
+}
+
+fn render_atom(document: Document) -> Result<String, (StatusCode, &'static str)> {
+    let mut entries = Vec::with_capacity(document.items.len());
+    for item in document.items {
+        let published = to_fixed_date_time(item.published)?;
+
+        entries.push(
+            atom_syndication::EntryBuilder::default()
+                .title(item.title)
+                .id(format!("urn:ulid:{}", item.link_id))
+                .updated(published)
+                .published(Some(published))
+                .link(
+                    atom_syndication::LinkBuilder::default()
+                        .href(item.url)
+                        .build(),
+                )
+                .content(Some(
+                    atom_syndication::ContentBuilder::default()
+                        .value(Some(item.content))
+                        .content_type(Some("html".to_string()))
+                        .build(),
+                ))
+                .build(),
+        );
+    }
+
+    Ok(atom_syndication::FeedBuilder::default()
+        .title(document.title)
+        .subtitle(Some(document.description.into()))
+        .id(document.feed_url.clone())
+        .updated(to_fixed_date_time(document.updated)?)
+        .author(
+            atom_syndication::PersonBuilder::default()
+                .name("flock")
+                .build(),
+        )
+        .link(
+            atom_syndication::LinkBuilder::default()
+                .href(document.feed_url)
+                .rel("self")
+                .build(),
+        )
+        .link(
+            atom_syndication::LinkBuilder::default()
+                .href(document.home_url)
+                .build(),
+        )
+        .entries(entries)
+        .build()
+        .to_string())
+}
+
+fn render_json(document: Document) -> Result<String, (StatusCode, &'static str)> {
+    serde_json::to_string(&serde_json::json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": document.title,
+        "description": document.description,
+        "home_page_url": document.home_url,
+        "feed_url": document.feed_url,
+        "items": document
+            .items
+            .into_iter()
+            .map(|item| {
+                serde_json::json!({
+                    "id": item.link_id,
+                    "url": item.url,
+                    "title": item.title,
+                    "content_html": item.content,
+                    "date_published": humantime::format_rfc3339(item.published).to_string(),
+                })
+            })
+            .collect::<Vec<_>>(),
+    }))
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to serialize the feed as json",
+        )
+    })
+}
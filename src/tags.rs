@@ -0,0 +1,158 @@
+//
+//  flock - baa (with twenty instances of the letter "a")
+//  Copyright (C) superwhiskers <whiskerdev@protonmail.com> 2022
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Affero General Public License as published by
+//  the Free Software Foundation, either version 3 of the License, or
+//  (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Affero General Public License for more details.
+//
+//  You should have received a copy of the GNU Affero General Public License
+//  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+
+use axum::http::StatusCode;
+use sqlx::{pool::PoolConnection, Sqlite};
+use tracing::{debug, trace};
+
+use crate::{model, util::ScaledRatingData};
+
+/// The number of links displayed on a single page of a tag's links
+pub const TAG_PAGE_LENGTH: usize = 25;
+
+/// A link carrying a tag, alongside its score for that tag
+#[derive(Debug)]
+pub struct TagLink {
+    pub id: String,
+    pub description: String,
+    pub score: ScaledRatingData,
+}
+
+/// Look up the id of the tag with the provided name
+pub async fn tag_id_from_name(
+    connection: &mut PoolConnection<Sqlite>,
+    name: &str,
+) -> Result<String, (StatusCode, &'static str)> {
+    sqlx::query_scalar!(
+        r#"SELECT tag_id as "tag_id!" FROM tags WHERE name = ?"#,
+        name
+    )
+    .fetch_optional(&mut **connection)
+    .await
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the db for the tag",
+        )
+    })?
+    .ok_or((StatusCode::BAD_REQUEST, "the requested tag does not exist"))
+}
+
+/// Retrieve a page of the links carrying the provided tag, sorted in the requested order
+///
+/// Links are sorted by, and displayed with, their stored scores. Decay isn't applied here, as
+/// it would leave the displayed scores out of order with each other
+pub async fn tag_links(
+    connection: &mut PoolConnection<Sqlite>,
+    tag_id: &str,
+    sort: model::TagLinksSort,
+    page: usize,
+) -> Result<Vec<TagLink>, (StatusCode, &'static str)> {
+    trace!(
+        "retrieving page {} of links for tag {} sorted by {:?}",
+        page,
+        tag_id,
+        sort
+    );
+
+    let offset = page
+        .checked_mul(TAG_PAGE_LENGTH)
+        .and_then(|offset| i64::try_from(offset).ok())
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            "the requested page is out of range",
+        ))?;
+    let limit = TAG_PAGE_LENGTH as i64;
+
+    // the order of the top links matches the ordering of `ScaledRatingData`, where more
+    // certain scores rank above less certain ones with the same rating
+    let links = match sort {
+        model::TagLinksSort::New => sqlx::query!(
+            r#"SELECT links.link_id as "link_id!",
+                      links.description as "description!",
+                      scores.rating as "rating!: f64",
+                      scores.deviation as "deviation!: f64",
+                      scores.volatility as "volatility!: f64"
+                 FROM links
+           INNER JOIN scores ON scores.id = links.link_id
+                WHERE scores.tag_id = ?
+                  AND links.link_id NOT IN (SELECT link_id FROM hidden_links)
+             ORDER BY links.link_id DESC
+                LIMIT ? OFFSET ?"#,
+            tag_id,
+            limit,
+            offset
+        )
+        .fetch_all(&mut **connection)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| TagLink {
+                    id: row.link_id,
+                    description: row.description,
+                    score: ScaledRatingData {
+                        rating: row.rating,
+                        deviation: row.deviation,
+                        volatility: row.volatility,
+                    },
+                })
+                .collect::<Vec<_>>()
+        }),
+        model::TagLinksSort::Top => sqlx::query!(
+            r#"SELECT links.link_id as "link_id!",
+                      links.description as "description!",
+                      scores.rating as "rating!: f64",
+                      scores.deviation as "deviation!: f64",
+                      scores.volatility as "volatility!: f64"
+                 FROM links
+           INNER JOIN scores ON scores.id = links.link_id
+                WHERE scores.tag_id = ?
+                  AND links.link_id NOT IN (SELECT link_id FROM hidden_links)
+             ORDER BY scores.rating DESC, scores.deviation, scores.volatility
+                LIMIT ? OFFSET ?"#,
+            tag_id,
+            limit,
+            offset
+        )
+        .fetch_all(&mut **connection)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| TagLink {
+                    id: row.link_id,
+                    description: row.description,
+                    score: ScaledRatingData {
+                        rating: row.rating,
+                        deviation: row.deviation,
+                        volatility: row.volatility,
+                    },
+                })
+                .collect::<Vec<_>>()
+        }),
+    }
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "unable to query the links for the tag",
+        )
+    })?;
+
+    debug!("{} links retrieved for tag {}", links.len(), tag_id);
+
+    Ok(links)
+}
@@ -18,18 +18,31 @@
 
 use askama_axum::Template;
 
-use crate::model;
+use crate::{configuration::SignupMode, model};
 
 #[derive(Template)]
 #[template(path = "index.html")]
 pub struct Index {
     pub style_id: model::StyleId,
     pub account: Option<Account>,
+    pub front_page: Option<FrontPage>,
 }
 
-pub struct Account {
+#[derive(Clone, Default)]
+pub struct FrontPage {
+    pub hot: Vec<FrontPageLink>,
+    pub new: Vec<FrontPageLink>,
+}
+
+#[derive(Clone)]
+pub struct FrontPageLink {
     pub id: String,
+    pub description: String,
+}
+
+pub struct Account {
     pub links: Vec<Link>,
+    pub feed_token: Option<String>,
 }
 
 pub struct Link {
@@ -40,6 +53,67 @@ pub struct Link {
     pub visited: bool,
 }
 
+#[derive(Template)]
+#[template(path = "feed-explanation.html")]
+pub struct FeedExplanation {
+    pub style_id: model::StyleId,
+    pub links: Vec<ExplainedLink>,
+}
+
+pub struct ExplainedLink {
+    pub id: String,
+    pub description: String,
+    pub overall_score: String,
+    pub explanation: Option<LinkExplanation>,
+}
+
+pub struct LinkExplanation {
+    pub segment: usize,
+    pub tags: Vec<ExplainedTag>,
+}
+
+pub struct ExplainedTag {
+    pub name: String,
+    pub importance: String,
+    pub score: String,
+}
+
+#[derive(Template)]
+#[template(path = "feed-archive.html")]
+pub struct FeedArchive {
+    pub style_id: model::StyleId,
+    pub links: Vec<ArchivedLink>,
+    pub page: usize,
+    pub next_page: Option<usize>,
+}
+
+pub struct ArchivedLink {
+    pub id: String,
+    pub description: String,
+    pub delivered: String,
+    pub visited: bool,
+    pub rated: bool,
+}
+
+#[derive(Template)]
+#[template(path = "feed-history.html")]
+pub struct FeedHistory {
+    pub style_id: model::StyleId,
+    pub feeds: Vec<PastFeed>,
+}
+
+pub struct PastFeed {
+    pub generated: String,
+    pub current: bool,
+    pub links: Vec<PastFeedLink>,
+}
+
+pub struct PastFeedLink {
+    pub id: String,
+    pub description: String,
+    pub overall_score: String,
+}
+
 #[derive(Template)]
 #[template(path = "login.html")]
 pub struct Login {
@@ -51,6 +125,10 @@ pub struct Login {
 #[template(path = "signup.html")]
 pub struct Signup {
     pub style_id: model::StyleId,
+    pub mode: SignupMode,
+    pub invite_code: String,
+    pub challenge: Option<String>,
+    pub difficulty: u32,
 }
 
 #[derive(Template)]
@@ -61,6 +139,42 @@ pub struct Tags {
     pub after: Option<String>,
 }
 
+#[derive(Template)]
+#[template(path = "tag.html")]
+pub struct TagPage {
+    pub style_id: model::StyleId,
+    pub name: String,
+    pub sort: model::TagLinksSort,
+    pub page: usize,
+    pub next_page: Option<usize>,
+    pub links: Vec<ScoredLink>,
+}
+
+pub struct ScoredLink {
+    pub id: String,
+    pub description: String,
+    pub score: String,
+}
+
+#[derive(Template)]
+#[template(path = "search.html")]
+pub struct Search {
+    pub style_id: model::StyleId,
+    pub query: String,
+    pub tags: String,
+    pub page: usize,
+    pub next_page: Option<usize>,
+    pub results: Option<Vec<SearchResult>>,
+}
+
+pub struct SearchResult {
+    pub id: String,
+    pub link: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub score: String,
+}
+
 #[derive(Template)]
 #[template(path = "tag-scores.html")]
 pub struct TagScores {
@@ -99,6 +213,23 @@ pub struct Profile {
 pub struct ProfileInformation {
     pub id: String,
     pub tags: String,
+    pub feed_tokens: Vec<ProfileFeedToken>,
+    pub role: Option<&'static str>,
+
+    /// The number of invites the account may still create, if signups are invite-only
+    pub invites_remaining: Option<usize>,
+    pub invites: Vec<ProfileInvite>,
+}
+
+pub struct ProfileInvite {
+    pub code: String,
+    pub created: String,
+    pub invitee: Option<String>,
+}
+
+pub struct ProfileFeedToken {
+    pub token: String,
+    pub created: String,
 }
 
 #[derive(Template)]
@@ -106,7 +237,9 @@ pub struct ProfileInformation {
 pub struct FeedItem {
     pub style_id: model::StyleId,
     pub flock_host: String,
-    pub link_id: String,
+    pub promote_url: String,
+    pub neutral_url: String,
+    pub demote_url: String,
 }
 
 #[derive(Template)]
@@ -121,6 +254,7 @@ pub struct Welcome {
 #[template(path = "post-style.html")]
 pub struct PostStyle {
     pub style_id: model::StyleId,
+    pub max_style_size: usize,
 }
 
 #[derive(Template)]
@@ -128,6 +262,103 @@ pub struct PostStyle {
 pub struct PostStyleResult {
     pub style_id: model::StyleId,
     pub created_style_id: String,
+    pub removals: Vec<String>,
+}
+
+#[derive(Template)]
+#[template(path = "styles.html")]
+pub struct Styles {
+    pub style_id: model::StyleId,
+    pub logged_in: bool,
+    pub sort: model::StyleSort,
+    pub page: usize,
+    pub next_page: Option<usize>,
+    pub styles: Vec<GalleryStyle>,
+}
+
+pub struct GalleryStyle {
+    pub id: String,
+    pub name: String,
+
+    /// A handle identifying the style's creator, if their account still exists
+    pub creator: Option<String>,
+
+    /// Whether the style was created by the account viewing the gallery
+    pub yours: bool,
+
+    /// The number of accounts using the style
+    pub users: i64,
+}
+
+#[derive(Template)]
+#[template(path = "edit-style.html")]
+pub struct EditStyle {
+    pub style_id: model::StyleId,
+    pub edited_style_id: String,
+    pub name: String,
+    pub max_style_size: usize,
+    pub saved: bool,
+    pub removals: Vec<String>,
+}
+
+#[derive(Template)]
+#[template(path = "import.html")]
+pub struct Import {
+    pub style_id: model::StyleId,
+}
+
+#[derive(Template)]
+#[template(path = "import-result.html")]
+pub struct ImportResult {
+    pub style_id: model::StyleId,
+    pub posted: usize,
+    pub existing: usize,
+    pub seeded_tags: usize,
+}
+
+#[derive(Template)]
+#[template(path = "report.html")]
+pub struct Report {
+    pub style_id: model::StyleId,
+    pub kind: model::ReportKind,
+    pub target_id: String,
+    pub description: String,
+}
+
+#[derive(Template)]
+#[template(path = "report-result.html")]
+pub struct ReportResult {
+    pub style_id: model::StyleId,
+}
+
+#[derive(Template)]
+#[template(path = "moderation.html")]
+pub struct Moderation {
+    pub style_id: model::StyleId,
+    pub role: &'static str,
+    pub reports: Vec<ModerationReport>,
+    pub log: Vec<ModerationLogEntry>,
+}
+
+pub struct ModerationReport {
+    pub id: String,
+    pub created: String,
+    pub is_link: bool,
+    pub target_id: String,
+    pub description: String,
+    /// The account which posted the link or created the style, if known
+    pub owner: Option<String>,
+    pub tags: Vec<String>,
+    pub reporter: String,
+    pub reason: String,
+}
+
+pub struct ModerationLogEntry {
+    pub created: String,
+    pub moderator: String,
+    pub action: String,
+    pub target_id: String,
+    pub details: String,
 }
 
 mod filters {
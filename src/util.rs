@@ -41,7 +41,11 @@ use std::{
 use tokio::signal;
 use tracing::{debug, trace};
 
-use crate::{configuration::Algorithm as AlgorithmConfiguration, model};
+use crate::{
+    configuration::Algorithm as AlgorithmConfiguration,
+    model,
+    styles::StyleCache,
+};
 
 //TODO(superwhiskers): should we make these configurable?
 pub static GLICKO_2_PARAMETERS: LazyLock<Parameters> = LazyLock::new(|| {
@@ -287,6 +291,10 @@ pub fn rating_overlap(a: ScaledRatingData, b: ScaledRatingData) -> f64 {
     f64::min(e1, e2) - f64::max(s1, s2)
 }
 
+pub fn period_length(period: u64) -> u64 {
+    60 * 60 * 24 * 30 * period
+}
+
 pub fn decay_score(
     algorithm_configuration: &AlgorithmConfiguration,
     score: &mut model::Score,
@@ -294,7 +302,7 @@ pub fn decay_score(
 ) -> Result<bool, (StatusCode, &'static str)> {
     debug!("checking decay for score {:?} with a time period of {} and an algorithm configuration of {:?}", score, period, algorithm_configuration);
 
-    let period_as_seconds = 60 * 60 * 24 * 30 * period;
+    let period_as_seconds = period_length(period);
 
     let periods =
         (SystemTime::UNIX_EPOCH + Duration::from_secs(score.last_period))
@@ -308,13 +316,19 @@ pub fn decay_score(
             .as_secs()
                 / period_as_seconds;
 
+    let results = score
+        .result_queue
+        .iter()
+        .map(|&result| result.into())
+        .collect::<Vec<_>>();
+
     Ok(if periods != 0 {
         debug!("updating an old score");
         for i in 0..periods {
             glicko_2::close_player_rating_period_scaled(
                 &mut score.score,
                 if i == 0 {
-                    score.result_queue.as_slice()
+                    results.as_slice()
                 } else {
                     &[]
                 },
@@ -332,7 +346,7 @@ pub fn decay_score(
         //TODO(superwhiskers): ditto
         glicko_2::close_player_rating_period_scaled(
             &mut score.score,
-            score.result_queue.as_slice(),
+            results.as_slice(),
             *GLICKO_2_PARAMETERS,
         );
 
@@ -344,6 +358,7 @@ pub fn decay_score(
 
 pub async fn apply_style_id_extension<B>(
     Extension(sqlite): Extension<SqlitePool>,
+    Extension(style_cache): Extension<&'static StyleCache>,
     cookies: Option<TypedHeader<Cookie>>,
     Query(model::Style { style }): Query<model::Style>,
     mut request: Request<B>,
@@ -357,27 +372,22 @@ pub async fn apply_style_id_extension<B>(
     request.extensions_mut().insert(model::StyleId(None));
 
     if let Some(style) = style {
-        request.extensions_mut().insert(model::StyleId(Some(style)));
+        request.extensions_mut().insert(model::StyleId(
+            style_cache
+                .get(&sqlite, &style, None)
+                .await?
+                .map(|cached| model::AppliedStyle {
+                    version: cached.version.clone(),
+                    id: style,
+                }),
+        ));
     } else if let Some(cookies) = cookies
         && let Some(account_id) = cookies.get("flock.id") {
         debug!("account {} being checked for applied styles", account_id);
 
-        let mut connection = sqlite.acquire().await.map_err(|_| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "unable to acquire a db connection",
-            )
-        })?;
-
-        if let Some(style_id) = sqlx::query_scalar!(
-            r#"SELECT (SELECT style_id FROM accounts WHERE account_id = ?) AS "style_id?";"#,
-            account_id
-        )
-        .fetch_one(&mut *connection)
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "unable to query the db"))? {
-            request.extensions_mut().insert(model::StyleId(Some(style_id)));
-        }
+        request
+            .extensions_mut()
+            .insert(style_cache.account_style(&sqlite, account_id).await?);
     }
 
     Ok(next.run(request).await)